@@ -0,0 +1,63 @@
+use db::api_tokens::ApiTokenScope;
+use hyper::Method;
+
+/// The top level resource of an api path, like `tasks` for `/api/v1/tasks/on-going`.
+fn resource(path: &str) -> &str {
+    let path = path.split_once("/v1/").map_or(path, |(_, rest)| rest);
+
+    return path.split('/').next().unwrap_or_default();
+}
+
+fn scope_allows(scope: &ApiTokenScope, method: &Method, resource: &str) -> bool {
+    let is_read = *method == Method::GET || *method == Method::HEAD;
+
+    return match scope {
+        ApiTokenScope::ReadOnly => is_read,
+        ApiTokenScope::StatsRead => is_read && resource == "stats",
+        ApiTokenScope::TasksWrite => resource == "tasks",
+    };
+}
+
+/// Whether a personal API token with `scopes` may make a `method` request to `path`.
+pub fn scopes_allow(scopes: &[ApiTokenScope], method: &Method, path: &str) -> bool {
+    let resource = resource(path);
+
+    return scopes
+        .iter()
+        .any(|scope| scope_allows(scope, method, resource));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scopes_allow() {
+        let read_only = [ApiTokenScope::ReadOnly];
+        assert!(scopes_allow(&read_only, &Method::GET, "/api/v1/tags"));
+        assert!(!scopes_allow(
+            &read_only,
+            &Method::POST,
+            "/api/v1/tasks/on-going"
+        ));
+
+        let stats = [ApiTokenScope::StatsRead];
+        assert!(scopes_allow(&stats, &Method::GET, "/api/v1/stats/calendar"));
+        assert!(!scopes_allow(&stats, &Method::GET, "/api/v1/tasks"));
+
+        let timer = [ApiTokenScope::TasksWrite];
+        assert!(scopes_allow(
+            &timer,
+            &Method::POST,
+            "/api/v1/tasks/on-going"
+        ));
+        assert!(scopes_allow(
+            &timer,
+            &Method::DELETE,
+            "/api/v1/tasks/on-going"
+        ));
+        assert!(!scopes_allow(&timer, &Method::DELETE, "/api/v1/tags/1"));
+
+        assert!(!scopes_allow(&[], &Method::GET, "/api/v1/tasks"));
+    }
+}
@@ -1,2 +1,3 @@
+pub mod api_token;
 pub mod session;
 pub mod user_id;
@@ -1,20 +1,107 @@
-use anyhow::Context;
-use auth::{cookie::create_cookie, token::create_token};
-use chrono::{DateTime, Utc};
-use config::CONFIG;
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::request::Parts,
+};
+use hyper::header;
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
 
-pub async fn create_session(
-    db: &db::Db,
-    expiry: &DateTime<Utc>,
-    user_id: &str,
-) -> anyhow::Result<String> {
-    let session_id = db::sessions::insert(db, user_id, &expiry)
-        .await
-        .context("error inserting session")?;
+const MAX_USER_AGENT_LENGTH: usize = 500;
 
-    let token = create_token(&CONFIG.secret, user_id, &session_id);
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
-    let cookie = create_cookie(&token, &expiry);
+/// Where a login came from, shown in the session list so the user can tell
+/// their devices apart.
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let header = |name: &header::HeaderName| {
+            return parts
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+                .filter(|value| !value.is_empty());
+        };
+
+        let user_agent = header(&header::USER_AGENT)
+            .map(|user_agent| user_agent.chars().take(MAX_USER_AGENT_LENGTH).collect());
+
+        let ip = proxied_ip(
+            header(&header::HeaderName::from_static("x-real-ip")),
+            header(&header::HeaderName::from_static("x-forwarded-for")),
+        )
+        .or_else(|| {
+            parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip())
+        })
+        .map(|ip| ip.to_string());
+
+        return Ok(Self { user_agent, ip });
+    }
+}
+
+/// The client's address as the proxy in front of the api saw it. The proxy
+/// sets `X-Real-IP` and appends to `X-Forwarded-For`, so only the last hop
+/// of the latter is trusted, the ones before it come from the client.
+fn proxied_ip(real_ip: Option<&str>, forwarded_for: Option<&str>) -> Option<IpAddr> {
+    return real_ip
+        .or_else(|| forwarded_for.and_then(|forwarded_for| forwarded_for.rsplit(',').next()))
+        .and_then(|ip| ip.trim().parse::<IpAddr>().ok());
+}
+
+/// Deletes expired sessions every hour, they can't be used anymore
+/// and would only pile up.
+pub async fn start_session_cleanup(db: db::Db) {
+    loop {
+        match db::sessions::delete_expired(&db, &chrono::Utc::now()).await {
+            Ok(0) => {}
+            Ok(deleted) => tracing::info!("deleted {} expired sessions", deleted),
+            Err(e) => tracing::error!("error deleting expired sessions: {:#}", e),
+        }
+
+        tokio::time::sleep(CLEANUP_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proxied_ip() {
+        let ip = |ip: &str| Some(ip.parse::<IpAddr>().unwrap());
+
+        assert_eq!(proxied_ip(Some("203.0.113.7"), None), ip("203.0.113.7"));
+        assert_eq!(
+            proxied_ip(Some("203.0.113.7"), Some("198.51.100.1")),
+            ip("203.0.113.7")
+        );
+
+        // a forged first entry is ignored
+        assert_eq!(
+            proxied_ip(None, Some("198.51.100.1, 203.0.113.7")),
+            ip("203.0.113.7")
+        );
+        assert_eq!(proxied_ip(None, Some("2001:db8::1")), ip("2001:db8::1"));
 
-    return Ok(cookie);
+        assert_eq!(proxied_ip(None, Some("198.51.100.1, not-an-ip")), None);
+        assert_eq!(proxied_ip(None, None), None);
+    }
 }
@@ -1,25 +1,24 @@
 use anyhow::{anyhow, Context};
 use auth::{
+    api_token::hash_api_token,
     cookie::{create_cookie, COOKIE_NAME},
+    session_policy::SESSION_POLICY,
     token::verify_token,
 };
 use axum::{
     async_trait,
-    extract::{FromRef, FromRequestParts},
+    extract::{FromRef, FromRequestParts, OriginalUri},
     http::request::Parts,
     RequestPartsExt,
 };
 use axum_extra::{headers, typed_header::TypedHeaderRejectionReason, TypedHeader};
-use chrono::{Duration, Utc};
+use chrono::Utc;
 use config::CONFIG;
 use hyper::header;
-use once_cell::sync::Lazy;
 
 use crate::{error::ApiError, state::RequestStateStruct};
 
-use super::session::create_session;
-
-const EXPIRE_AFTER: Lazy<Duration> = Lazy::new(|| Duration::days(30));
+use super::api_token::scopes_allow;
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct UserId(pub String);
@@ -33,57 +32,64 @@ where
     type Rejection = ApiError;
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        let cookies = parts
-            .extract::<TypedHeader<headers::Cookie>>()
-            .await
-            .map_err(|e| match *e.name() {
-                header::COOKIE => match e.reason() {
-                    TypedHeaderRejectionReason::Missing => {
-                        ApiError::Unauthorized("no cookie".to_owned())
-                    }
-                    _ => ApiError::UnexpectedError(anyhow!("error getting cookies")),
-                },
-                _ => ApiError::UnexpectedError(anyhow!("error getting cookies")),
-            })?;
-
-        let session_cookie = cookies
-            .get(COOKIE_NAME)
-            .ok_or(ApiError::Unauthorized("no cookie".to_owned()))?;
+        if let Some(token) = bearer_token(parts) {
+            return authenticate_api_token(parts, state, &token).await;
+        }
 
-        let token =
-            verify_token(&CONFIG.secret, &session_cookie).context("error verifying token")?;
+        let auth = authenticate(parts, state).await?;
 
-        let state = parts
-            .extract_with_state::<RequestStateStruct, _>(state)
-            .await
-            .context("error extracting state")?;
+        Ok(UserId(auth.user_id))
+    }
+}
 
-        let new_expiry = Utc::now() + *EXPIRE_AFTER;
+fn bearer_token(parts: &Parts) -> Option<String> {
+    return parts
+        .headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_owned());
+}
 
-        let ok = db::sessions::update_expires_at(
-            &state.db,
-            &token.session_id,
-            &token.user_id,
-            &new_expiry,
-        )
+/// Checks a personal API token and that its scopes allow the request.
+async fn authenticate_api_token<S>(
+    parts: &mut Parts,
+    state: &S,
+    token: &str,
+) -> Result<UserId, ApiError>
+where
+    RequestStateStruct: FromRef<S>,
+    S: Send + Sync,
+{
+    let state = parts
+        .extract_with_state::<RequestStateStruct, _>(state)
         .await
-        .context("error updating session")?;
-
-        if !ok {
-            return Err(ApiError::Unauthorized("session not found".to_owned()));
-        }
-
-        parts.headers.insert(
-            header::SET_COOKIE,
-            create_cookie(&session_cookie, &new_expiry)
-                .parse()
-                .context("error parsing cookie")?,
-        );
-
-        Ok(UserId(token.user_id.to_owned()))
+        .context("error extracting state")?;
+
+    let api_token = db::api_tokens::use_token(
+        &state.db,
+        &hash_api_token(&CONFIG.secret, token),
+        &Utc::now(),
+    )
+    .await
+    .context("error getting api token")?
+    .ok_or(ApiError::Unauthorized("invalid api token".to_owned()))?;
+
+    // nested routers only see the rest of the path
+    let path = match parts.extensions.get::<OriginalUri>() {
+        Some(OriginalUri(uri)) => uri.path(),
+        None => parts.uri.path(),
+    };
+
+    if !scopes_allow(&api_token.scopes, &parts.method, path) {
+        return Err(ApiError::Forbidden);
     }
+
+    return Ok(UserId(api_token.user_id));
 }
 
+/// Like [`UserId`], along with the session the request was made with. Only
+/// takes the session cookie, personal API tokens can't manage sessions or tokens.
 pub struct Auth(pub AuthStruct);
 
 pub struct AuthStruct {
@@ -100,45 +106,64 @@ where
     type Rejection = ApiError;
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        let cookies = parts
-            .extract::<TypedHeader<headers::Cookie>>()
-            .await
-            .map_err(|e| match *e.name() {
-                header::COOKIE => match e.reason() {
-                    TypedHeaderRejectionReason::Missing => {
-                        ApiError::Unauthorized("no cookie".to_owned())
-                    }
-                    _ => ApiError::UnexpectedError(anyhow!("error getting cookies")),
-                },
-                _ => ApiError::UnexpectedError(anyhow!("error getting cookies")),
-            })?;
-
-        let session_cookie = cookies
-            .get(COOKIE_NAME)
-            .ok_or(ApiError::Unauthorized("no cookie".to_owned()))?;
-
-        let token =
-            verify_token(&CONFIG.secret, &session_cookie).context("error verifying token")?;
+        let auth = authenticate(parts, state).await?;
 
-        let state = parts
-            .extract_with_state::<RequestStateStruct, _>(state)
-            .await
-            .context("error extracting state")?;
+        Ok(Auth(auth))
+    }
+}
 
-        let new_expiry = Utc::now() + *EXPIRE_AFTER;
+/// Verifies the session cookie, then extends the session and records it as
+/// seen. Sessions past their idle timeout or max age are rejected.
+async fn authenticate<S>(parts: &mut Parts, state: &S) -> Result<AuthStruct, ApiError>
+where
+    RequestStateStruct: FromRef<S>,
+    S: Send + Sync,
+{
+    let cookies = parts
+        .extract::<TypedHeader<headers::Cookie>>()
+        .await
+        .map_err(|e| match *e.name() {
+            header::COOKIE => match e.reason() {
+                TypedHeaderRejectionReason::Missing => {
+                    ApiError::Unauthorized("no cookie".to_owned())
+                }
+                _ => ApiError::UnexpectedError(anyhow!("error getting cookies")),
+            },
+            _ => ApiError::UnexpectedError(anyhow!("error getting cookies")),
+        })?;
 
-        let cookie = create_session(&state.db, &new_expiry, &token.user_id)
-            .await
-            .context("error creating session")?;
+    let session_cookie = cookies
+        .get(COOKIE_NAME)
+        .ok_or(ApiError::Unauthorized("no cookie".to_owned()))?;
 
-        parts.headers.insert(
-            header::SET_COOKIE,
-            cookie.parse().context("error parsing cookie")?,
-        );
+    let token = verify_token(&CONFIG.secret, session_cookie).context("error verifying token")?;
 
-        Ok(Auth(AuthStruct {
-            user_id: token.user_id.to_owned(),
-            session_id: token.session_id.to_owned(),
-        }))
-    }
+    let state = parts
+        .extract_with_state::<RequestStateStruct, _>(state)
+        .await
+        .context("error extracting state")?;
+
+    let new_expiry = db::sessions::update_expires_at(
+        &state.db,
+        &token.session_id,
+        &token.user_id,
+        &Utc::now(),
+        &SESSION_POLICY.idle_timeout,
+        &SESSION_POLICY.max_age,
+    )
+    .await
+    .context("error updating session")?
+    .ok_or(ApiError::Unauthorized("session not found".to_owned()))?;
+
+    parts.headers.insert(
+        header::SET_COOKIE,
+        create_cookie(session_cookie, &new_expiry)
+            .parse()
+            .context("error parsing cookie")?,
+    );
+
+    return Ok(AuthStruct {
+        user_id: token.user_id,
+        session_id: token.session_id,
+    });
 }
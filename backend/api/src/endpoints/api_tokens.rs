@@ -0,0 +1,124 @@
+use crate::{auth::user_id::Auth, error::ApiError, state::RequestState};
+use anyhow::Context;
+use auth::api_token::{create_api_token, hash_api_token};
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use config::CONFIG;
+use db::api_tokens::{ApiToken, ApiTokenScope};
+use hyper::StatusCode;
+
+const MAX_API_TOKENS: usize = 20;
+const MAX_NAME_LENGTH: usize = 100;
+
+// the token endpoints take the session cookie only, a token can't create more tokens
+
+pub async fn get_api_tokens_endpoint(
+    Auth(auth): Auth,
+    State(state): RequestState,
+) -> Result<impl IntoResponse, ApiError> {
+    let api_tokens = db::api_tokens::get_by_user_id(&state.db, &auth.user_id)
+        .await
+        .context("error fetching api tokens")?;
+
+    return Ok((StatusCode::OK, Json(api_tokens)));
+}
+
+#[derive(serde::Deserialize)]
+pub struct AddApiTokenBody {
+    pub name: String,
+    pub scopes: Vec<ApiTokenScope>,
+    /// `None` never expires.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct AddApiTokenResponse {
+    #[serde(flatten)]
+    pub api_token: ApiToken,
+    /// Only returned here, just the hash is stored.
+    pub token: String,
+}
+
+pub async fn add_api_token_endpoint(
+    Auth(auth): Auth,
+    State(state): RequestState,
+    Json(body): Json<AddApiTokenBody>,
+) -> Result<impl IntoResponse, ApiError> {
+    let name = body.name.trim();
+
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(ApiError::BadRequest(format!(
+            "name must be between 1 and {MAX_NAME_LENGTH} characters"
+        )));
+    }
+
+    if body.scopes.is_empty() {
+        return Err(ApiError::BadRequest(
+            "a token needs at least one scope".to_owned(),
+        ));
+    }
+
+    if body
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= Utc::now())
+    {
+        return Err(ApiError::BadRequest(
+            "expires_at must be in the future".to_owned(),
+        ));
+    }
+
+    let existing = db::api_tokens::get_by_user_id(&state.db, &auth.user_id)
+        .await
+        .context("error fetching api tokens")?;
+
+    if existing.len() >= MAX_API_TOKENS {
+        return Err(ApiError::BadRequest(format!(
+            "at most {MAX_API_TOKENS} api tokens are allowed"
+        )));
+    }
+
+    let mut scopes = Vec::<ApiTokenScope>::new();
+    for scope in body.scopes {
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+
+    let token = create_api_token();
+
+    let api_token = db::api_tokens::insert(
+        &state.db,
+        &auth.user_id,
+        name,
+        &hash_api_token(&CONFIG.secret, &token),
+        &scopes,
+        body.expires_at.as_ref(),
+    )
+    .await
+    .context("error inserting api token")?;
+
+    return Ok((
+        StatusCode::CREATED,
+        Json(AddApiTokenResponse { api_token, token }),
+    ));
+}
+
+pub async fn delete_api_token_endpoint(
+    Auth(auth): Auth,
+    State(state): RequestState,
+    Path(token_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let deleted = db::api_tokens::delete(&state.db, &token_id, &auth.user_id)
+        .await
+        .context("error deleting api token")?;
+
+    if !deleted {
+        return Err(ApiError::NotFound("api token not found".to_owned()));
+    }
+
+    return Ok(StatusCode::NO_CONTENT);
+}
@@ -1,17 +1,21 @@
 use crate::{
-    auth::user_id::{Auth, UserId},
+    auth::{
+        session::ClientInfo,
+        user_id::{Auth, UserId},
+    },
     error::ApiError,
     state::RequestState,
 };
 use anyhow::Context;
-use auth::{cookie::create_empty_cookie, token::create_token};
+use auth::{cookie::create_empty_cookie, session_policy::SESSION_POLICY, token::create_token};
 use axum::{
-    extract::{Query, State},
+    extract::{Path, Query, State},
     response::{IntoResponse, Redirect},
     Json,
 };
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Utc};
 use config::CONFIG;
+use db::sessions::Session;
 use hyper::{header, HeaderMap, StatusCode};
 use std::collections::HashMap;
 
@@ -20,9 +24,9 @@ pub async fn auth_init_endpoint() -> Result<impl IntoResponse, ApiError> {
         .scheme("https")
         .authority("accounts.google.com")
         .path_and_query(format!(
-            "/o/oauth2/v2/auth?client_id={}&redirect_uri={}&response_type=code&scope=email&prompt=select_account",
+            "/o/oauth2/v2/auth?client_id={}&redirect_uri={}/auth/callback&response_type=code&scope=email&prompt=select_account",
             CONFIG.google_client_id,
-            format!("{}/auth/callback", CONFIG.front_url),
+            CONFIG.front_url,
         ))
         .build().context("error building auth url")?;
 
@@ -41,6 +45,7 @@ struct OAuthUserInfoResponseBody {
 
 pub async fn auth_verify_endpoint(
     State(state): RequestState,
+    client_info: ClientInfo,
     Query(query): Query<HashMap<String, String>>,
 ) -> Result<impl IntoResponse, ApiError> {
     let code = query
@@ -117,11 +122,18 @@ pub async fn auth_verify_endpoint(
         }
     };
 
-    let expires_at = Utc::now() + Duration::days(30);
+    let now = Utc::now();
+    let expires_at = SESSION_POLICY.expires_at(&now, &now);
 
-    let session_id = db::sessions::insert(&state.db, &user.id, &expires_at)
-        .await
-        .context("error creating session")?;
+    let session_id = db::sessions::insert(
+        &state.db,
+        &user.id,
+        &expires_at,
+        client_info.user_agent.as_deref(),
+        client_info.ip.as_deref(),
+    )
+    .await
+    .context("error creating session")?;
 
     let headers: HeaderMap = HeaderMap::from_iter(vec![(
         header::SET_COOKIE,
@@ -167,8 +179,102 @@ pub async fn auth_logout_endpoint(
     return Ok((StatusCode::OK, headers));
 }
 
+/// A logged in device, without the token.
+#[derive(serde::Serialize)]
+pub struct SessionResponse {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub expires_at: DateTime<Utc>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    /// The session the request was made with.
+    pub current: bool,
+}
+
+impl SessionResponse {
+    fn new(session: Session, current_session_id: &str) -> Self {
+        return Self {
+            current: session.id == current_session_id,
+            id: session.id,
+            created_at: session.created_at,
+            last_seen_at: session.last_seen_at,
+            expires_at: session.expires_at,
+            user_agent: session.user_agent,
+            ip: session.ip,
+        };
+    }
+}
+
+pub async fn get_sessions_endpoint(
+    Auth(auth): Auth,
+    State(state): RequestState,
+) -> Result<impl IntoResponse, ApiError> {
+    let sessions = db::sessions::get_active(&state.db, &auth.user_id, &Utc::now())
+        .await
+        .context("error getting sessions")?;
+
+    let sessions = sessions
+        .into_iter()
+        .map(|session| SessionResponse::new(session, &auth.session_id))
+        .collect::<Vec<SessionResponse>>();
+
+    return Ok((StatusCode::OK, Json(sessions)));
+}
+
+/// Revokes one session, the device is logged out on its next request.
+/// Revoking the current session is the same as logging out.
+pub async fn delete_session_endpoint(
+    Auth(auth): Auth,
+    State(state): RequestState,
+    Path(session_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let deleted = db::sessions::delete(&state.db, &session_id, &auth.user_id)
+        .await
+        .context("error deleting session")?;
+
+    if !deleted {
+        return Err(ApiError::NotFound("session not found".to_owned()));
+    }
+
+    let mut headers = HeaderMap::new();
+    if session_id == auth.session_id {
+        headers.insert(
+            header::SET_COOKIE,
+            create_empty_cookie()
+                .parse()
+                .context("error parsing cookie")?,
+        );
+    }
+
+    return Ok((StatusCode::NO_CONTENT, headers));
+}
+
+#[derive(serde::Serialize)]
+pub struct DeleteOtherSessionsResponse {
+    pub deleted: u64,
+}
+
+/// Logs out everywhere else.
+pub async fn delete_other_sessions_endpoint(
+    Auth(auth): Auth,
+    State(state): RequestState,
+) -> Result<impl IntoResponse, ApiError> {
+    let deleted = db::sessions::delete_others(&state.db, &auth.session_id, &auth.user_id)
+        .await
+        .context("error deleting other sessions")?;
+
+    return Ok((
+        StatusCode::OK,
+        Json(DeleteOtherSessionsResponse { deleted }),
+    ));
+}
+
 #[cfg(debug_assertions)]
-pub async fn dev_login(State(state): RequestState) -> Result<impl IntoResponse, ApiError> {
+pub async fn dev_login(
+    State(state): RequestState,
+    client_info: ClientInfo,
+) -> Result<impl IntoResponse, ApiError> {
     use auth::cookie::create_cookie;
 
     let email = "dev@dev.local";
@@ -188,11 +294,18 @@ pub async fn dev_login(State(state): RequestState) -> Result<impl IntoResponse,
         }
     };
 
-    let expires_at = Utc::now() + Duration::days(30);
-
-    let session_id = db::sessions::insert(&state.db, &user_id, &expires_at)
-        .await
-        .context("error creating session")?;
+    let now = Utc::now();
+    let expires_at = SESSION_POLICY.expires_at(&now, &now);
+
+    let session_id = db::sessions::insert(
+        &state.db,
+        &user_id,
+        &expires_at,
+        client_info.user_agent.as_deref(),
+        client_info.ip.as_deref(),
+    )
+    .await
+    .context("error creating session")?;
 
     let headers: HeaderMap = HeaderMap::from_iter(vec![
         (
@@ -0,0 +1,84 @@
+use crate::{auth::user_id::UserId, error::ApiError, state::RequestState};
+use anyhow::Context;
+use axum::{
+    extract::{Path, Query, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::Utc;
+use hyper::StatusCode;
+use serde_json::json;
+use std::collections::HashMap;
+
+const MAX_MARK_READ_IDS: usize = 100;
+
+/// A page of the notification history, newest first. Pass the id of the
+/// last item as `last_id` for the next page, `unread=1` leaves out read items.
+pub async fn get_inbox_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let last_id = query
+        .get("last_id")
+        .map(|last_id| last_id.as_str())
+        .filter(|last_id| !last_id.is_empty());
+    let unread_only = query.get("unread").is_some_and(|unread| unread == "1");
+
+    let items = db::inbox::get_many(&state.db, &user_id, last_id, unread_only)
+        .await
+        .context("error fetching inbox")?;
+
+    return Ok((StatusCode::OK, Json(items)));
+}
+
+pub async fn get_unread_count_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+) -> Result<impl IntoResponse, ApiError> {
+    let count = db::inbox::count_unread(&state.db, &user_id)
+        .await
+        .context("error counting unread notifications")?;
+
+    return Ok((StatusCode::OK, Json(json!({ "count": count }))));
+}
+
+pub async fn mark_item_read_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+    Path(item_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    db::inbox::mark_read(&state.db, &user_id, Some(&[item_id]), &Utc::now())
+        .await
+        .context("error marking notification read")?;
+
+    return Ok(StatusCode::NO_CONTENT);
+}
+
+#[derive(serde::Deserialize)]
+pub struct MarkReadBody {
+    /// Leaving the ids out marks everything read.
+    pub ids: Option<Vec<String>>,
+}
+
+pub async fn mark_read_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+    Json(body): Json<MarkReadBody>,
+) -> Result<impl IntoResponse, ApiError> {
+    if body
+        .ids
+        .as_ref()
+        .is_some_and(|ids| ids.len() > MAX_MARK_READ_IDS)
+    {
+        return Err(ApiError::BadRequest(format!(
+            "can't mark more than {MAX_MARK_READ_IDS} notifications at once"
+        )));
+    }
+
+    let marked = db::inbox::mark_read(&state.db, &user_id, body.ids.as_deref(), &Utc::now())
+        .await
+        .context("error marking notifications read")?;
+
+    return Ok((StatusCode::OK, Json(json!({ "marked": marked }))));
+}
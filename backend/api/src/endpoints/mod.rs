@@ -1,12 +1,20 @@
 use axum::{
-    routing::{delete, get, post},
+    routing::{delete, get, patch, post, put},
     Router,
 };
 
 use crate::state::RequestStateStruct;
 
+mod api_tokens;
 mod auth;
+mod inbox;
 mod notif_subs;
+mod notification_actions;
+mod notification_channels;
+mod notification_settings;
+mod oidc;
+mod preferences;
+mod reminders;
 mod stats;
 mod tags;
 mod tasks;
@@ -17,7 +25,29 @@ pub fn router() -> Router<RequestStateStruct> {
         .route("/google-init", get(auth::auth_init_endpoint))
         .route("/google-verify", post(auth::auth_verify_endpoint))
         .route("/me", get(auth::auth_me_endpoint))
-        .route("/logout", get(auth::auth_logout_endpoint));
+        .route("/logout", get(auth::auth_logout_endpoint))
+        .route(
+            "/sessions",
+            get(auth::get_sessions_endpoint).delete(auth::delete_other_sessions_endpoint),
+        )
+        .route(
+            "/sessions/:session_id",
+            delete(auth::delete_session_endpoint),
+        )
+        .route(
+            "/tokens",
+            get(api_tokens::get_api_tokens_endpoint).post(api_tokens::add_api_token_endpoint),
+        )
+        .route(
+            "/tokens/:token_id",
+            delete(api_tokens::delete_api_token_endpoint),
+        )
+        .route("/oidc", get(oidc::get_oidc_providers_endpoint))
+        .route("/oidc/:provider_id/init", get(oidc::oidc_init_endpoint))
+        .route(
+            "/oidc/:provider_id/verify",
+            post(oidc::oidc_verify_endpoint),
+        );
 
     #[cfg(debug_assertions)]
     {
@@ -26,12 +56,86 @@ pub fn router() -> Router<RequestStateStruct> {
             .to_owned();
     }
 
-    let v1_notif_subs_routes = Router::new().route(
+    let v1_notif_subs_routes = Router::new()
+        .route(
+            "/",
+            get(notif_subs::get_notif_subs_endpoint)
+                .post(notif_subs::add_notif_sub_endpoint)
+                .delete(notif_subs::delete_notif_sub_endpoint),
+        )
+        .route(
+            "/vapid-public-key",
+            get(notif_subs::get_vapid_public_key_endpoint),
+        )
+        .route(
+            "/:sub_id",
+            patch(notif_subs::update_notif_sub_endpoint)
+                .delete(notif_subs::delete_notif_sub_by_id_endpoint),
+        )
+        .route("/:sub_id/test", post(notif_subs::test_notif_sub_endpoint));
+
+    let v1_notification_actions_routes = Router::new().route(
         "/",
-        post(notif_subs::add_notif_sub_endpoint).delete(notif_subs::delete_notif_sub_endpoint),
+        post(notification_actions::notification_action_endpoint),
     );
 
-    let v1_users_routes = Router::new().route("/me", delete(users::users_me_delete_endpoint));
+    let v1_users_routes = Router::new()
+        .route("/me", delete(users::users_me_delete_endpoint))
+        .route(
+            "/me/notification-settings",
+            get(notification_settings::get_notification_settings_endpoint)
+                .put(notification_settings::update_notification_settings_endpoint),
+        )
+        .route(
+            "/me/daily-notifications",
+            put(notification_settings::update_daily_notifications_endpoint),
+        )
+        .route(
+            "/me/digest",
+            put(notification_settings::update_digest_endpoint),
+        )
+        .route(
+            "/me/dnd",
+            put(notification_settings::start_dnd_endpoint)
+                .delete(notification_settings::stop_dnd_endpoint),
+        )
+        .route(
+            "/me/preferences",
+            get(preferences::get_preferences_endpoint)
+                .put(preferences::update_preferences_endpoint),
+        );
+
+    let v1_notification_channels_routes = Router::new()
+        .route(
+            "/",
+            get(notification_channels::get_notification_channels_endpoint)
+                .post(notification_channels::add_notification_channel_endpoint),
+        )
+        .route(
+            "/:channel_id",
+            delete(notification_channels::delete_notification_channel_endpoint),
+        )
+        .route(
+            "/:channel_id/verify",
+            post(notification_channels::verify_notification_channel_endpoint),
+        )
+        .route(
+            "/:channel_id/resend-verification",
+            post(notification_channels::resend_verification_endpoint),
+        );
+
+    let v1_inbox_routes = Router::new()
+        .route("/", get(inbox::get_inbox_endpoint))
+        .route("/unread-count", get(inbox::get_unread_count_endpoint))
+        .route("/read", post(inbox::mark_read_endpoint))
+        .route("/:item_id/read", post(inbox::mark_item_read_endpoint));
+
+    let v1_reminders_routes = Router::new()
+        .route(
+            "/",
+            get(reminders::get_reminders).post(reminders::add_reminder),
+        )
+        .route("/:reminder_id", delete(reminders::delete_reminder));
 
     let v1_tags_routes = Router::new()
         .route("/", get(tags::get_tags).post(tags::add_tag))
@@ -52,12 +156,20 @@ pub fn router() -> Router<RequestStateStruct> {
         .route(
             "/tag-distribution",
             get(stats::get_tag_distribution_stats_endpoint),
-        );
+        )
+        .route("/weekday-hour", get(stats::get_weekday_hour_stats_endpoint))
+        .route("/calendar", get(stats::get_calendar_stats_endpoint))
+        .route("/sessions", get(stats::get_session_stats_endpoint))
+        .route("/tags/:tag_id", get(stats::get_tag_stats_endpoint));
 
     let v1_routes = Router::new()
         .nest("/auth", v1_auth_routes)
         .nest("/notif-subs", v1_notif_subs_routes)
+        .nest("/notification-actions", v1_notification_actions_routes)
+        .nest("/notification-channels", v1_notification_channels_routes)
         .nest("/users", v1_users_routes)
+        .nest("/inbox", v1_inbox_routes)
+        .nest("/reminders", v1_reminders_routes)
         .nest("/tags", v1_tags_routes)
         .nest("/tasks", v1_tasks_routes)
         .nest("/stats", v1_stats_routes);
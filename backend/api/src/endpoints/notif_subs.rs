@@ -1,14 +1,75 @@
-use std::collections::HashMap;
-
 use crate::{auth::user_id::UserId, error::ApiError, state::RequestState};
 use anyhow::Context;
 use axum::{
-    extract::{Query, State},
+    extract::{Path, State},
     response::IntoResponse,
     Json,
 };
-
+use axum_extra::{headers, TypedHeader};
+use chrono::{DateTime, Utc};
+use config::CONFIG;
+use db::notification_subs::NotificationSub;
 use hyper::StatusCode;
+use notifications::FailureKind;
+
+const MAX_NAME_LENGTH: usize = 100;
+const MAX_USER_AGENT_LENGTH: usize = 500;
+
+/// A subscribed device, without the endpoint and keys.
+#[derive(serde::Serialize)]
+pub struct NotifSubResponse {
+    pub id: String,
+    pub name: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_delivered_at: Option<DateTime<Utc>>,
+    pub expired_at: Option<DateTime<Utc>>,
+    /// The VAPID keys were rotated, the browser has to subscribe again.
+    pub needs_resubscribe: bool,
+}
+
+impl From<NotificationSub> for NotifSubResponse {
+    fn from(sub: NotificationSub) -> Self {
+        return Self {
+            id: sub.id,
+            name: sub.name,
+            user_agent: sub.user_agent,
+            created_at: sub.created_at,
+            last_delivered_at: sub.last_success_at,
+            expired_at: sub.expired_at,
+            needs_resubscribe: sub.resubscribe_requested_at.is_some(),
+        };
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct VapidPublicKeyResponse {
+    pub public_key: String,
+}
+
+/// The key browsers subscribe with, public so the frontend doesn't have to be
+/// rebuilt when the keys are rotated.
+pub async fn get_vapid_public_key_endpoint() -> impl IntoResponse {
+    return Json(VapidPublicKeyResponse {
+        public_key: CONFIG.vapid_public_key.to_owned(),
+    });
+}
+
+pub async fn get_notif_subs_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+) -> Result<impl IntoResponse, ApiError> {
+    let notification_subs = db::notification_subs::get_by_user_id(&state.db, &user_id)
+        .await
+        .context("error fetching notification subs")?;
+
+    let notification_subs = notification_subs
+        .into_iter()
+        .map(NotifSubResponse::from)
+        .collect::<Vec<NotifSubResponse>>();
+
+    return Ok((StatusCode::OK, Json(notification_subs)));
+}
 
 #[derive(serde::Deserialize)]
 pub struct AddNotifSubEndpointBody {
@@ -18,9 +79,9 @@ pub struct AddNotifSubEndpointBody {
 }
 
 pub async fn add_notif_sub_endpoint(
-    Query(query): Query<HashMap<String, String>>,
     UserId(user_id): UserId,
     State(state): RequestState,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
     Json(body): Json<AddNotifSubEndpointBody>,
 ) -> Result<impl IntoResponse, ApiError> {
     if body.endpoint.len() > 2000 {
@@ -29,27 +90,126 @@ pub async fn add_notif_sub_endpoint(
         ));
     }
 
+    let user_agent = user_agent.map(|TypedHeader(user_agent)| {
+        user_agent
+            .as_str()
+            .chars()
+            .take(MAX_USER_AGENT_LENGTH)
+            .collect::<String>()
+    });
+
     let notification_sub = db::notification_subs::upsert(
         &state.db,
         &user_id,
         &body.endpoint,
         &body.p256dh,
         &body.auth,
+        user_agent.as_deref(),
+        &CONFIG.vapid_public_key,
     )
     .await
     .context("error inserting notification sub")?;
 
-    if query.get("send_test_notification").is_some() {
-        notifications::send_notification(
-            &notification_sub,
-            "Test notification",
-            "If you see this, notifications are working! 🥳",
-        )
+    return Ok((
+        StatusCode::CREATED,
+        Json(NotifSubResponse::from(notification_sub)),
+    ));
+}
+
+#[derive(serde::Deserialize)]
+pub struct UpdateNotifSubBody {
+    pub name: Option<String>,
+}
+
+pub async fn update_notif_sub_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+    Path(sub_id): Path<String>,
+    Json(body): Json<UpdateNotifSubBody>,
+) -> Result<impl IntoResponse, ApiError> {
+    let name = body
+        .name
+        .as_deref()
+        .map(|name| name.trim())
+        .filter(|name| !name.is_empty());
+
+    if name.is_some_and(|name| name.chars().count() > MAX_NAME_LENGTH) {
+        return Err(ApiError::BadRequest(format!(
+            "name must be at most {MAX_NAME_LENGTH} characters"
+        )));
+    }
+
+    let notification_sub = db::notification_subs::rename(&state.db, &user_id, &sub_id, name)
+        .await
+        .context("error renaming notification sub")?
+        .ok_or(ApiError::NotFound("notification sub not found".to_owned()))?;
+
+    return Ok((
+        StatusCode::OK,
+        Json(NotifSubResponse::from(notification_sub)),
+    ));
+}
+
+pub async fn delete_notif_sub_by_id_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+    Path(sub_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let deleted = db::notification_subs::delete_by_id(&state.db, &user_id, &sub_id)
+        .await
+        .context("error deleting notification sub")?;
+
+    if !deleted {
+        return Err(ApiError::NotFound("notification sub not found".to_owned()));
+    }
+
+    return Ok(StatusCode::NO_CONTENT);
+}
+
+/// Sends a test notification to one device, a device the push service
+/// no longer knows is expired like it would be by the notification service.
+pub async fn test_notif_sub_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+    Path(sub_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let notification_sub = db::notification_subs::get_one(&state.db, &user_id, &sub_id)
         .await
-        .context("error sending notification")?
+        .context("error fetching notification sub")?
+        .ok_or(ApiError::NotFound("notification sub not found".to_owned()))?;
+
+    if notification_sub.expired_at.is_some() {
+        return Err(ApiError::BadRequest(
+            "this device is no longer subscribed".to_owned(),
+        ));
     }
 
-    return Ok(StatusCode::CREATED);
+    let now = Utc::now();
+
+    match notifications::send_test_notification(&notification_sub).await {
+        Ok(()) => {
+            db::notification_subs::mark_success(&state.db, &notification_sub.id, &now)
+                .await
+                .context("error marking notification sub success")?;
+        }
+        Err(e) if FailureKind::classify(&e) == FailureKind::Permanent => {
+            db::notification_subs::expire(&state.db, &notification_sub.id, &now)
+                .await
+                .context("error expiring notification sub")?;
+
+            return Err(ApiError::BadRequest(
+                "this device is no longer subscribed".to_owned(),
+            ));
+        }
+        Err(e) => {
+            return Err(ApiError::UnexpectedError(anyhow::anyhow!(
+                "error sending test notification: {}",
+                e
+            )));
+        }
+    }
+
+    return Ok(StatusCode::NO_CONTENT);
 }
 
 #[derive(serde::Deserialize)]
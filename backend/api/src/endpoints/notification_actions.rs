@@ -0,0 +1,195 @@
+use super::{tags::VALID_TAG_COLORS, tasks::schedule_task_notifications};
+use crate::{error::ApiError, state::RequestState};
+use anyhow::Context;
+use auth::action_token::{verify_action_token, TaskAction, BREAK_MINUTES, EXTEND_BY_MINUTES};
+use axum::{extract::State, response::IntoResponse, Json};
+use chrono::{DateTime, Duration, Utc};
+use config::CONFIG;
+use db::{
+    create_id,
+    tasks::{TagColor, TagLabel, Task, TaskWithTag},
+    Db,
+};
+use hyper::StatusCode;
+
+static BREAK_TAG_LABEL: &str = "Break";
+
+#[derive(serde::Deserialize)]
+pub struct NotificationActionBody {
+    pub token: String,
+}
+
+/// Runs the action of a notification button. There's no session here,
+/// the signed token from the push payload is the authorization, and it
+/// works once.
+pub async fn notification_action_endpoint(
+    State(state): RequestState,
+    Json(body): Json<NotificationActionBody>,
+) -> Result<impl IntoResponse, ApiError> {
+    let now = Utc::now();
+
+    let token = verify_action_token(&CONFIG.secret, &body.token, &now).map_err(|e| {
+        tracing::debug!("invalid action token: {}", e);
+        ApiError::Unauthorized("invalid action token".to_owned())
+    })?;
+
+    let is_first_use = db::action_tokens::use_once(&state.db, &token.id, &token.expires_at)
+        .await
+        .context("error recording used action token")?;
+
+    if !is_first_use {
+        return Err(ApiError::Unauthorized(
+            "this action was already done".to_owned(),
+        ));
+    }
+
+    let task = db::tasks::get_one(&state.db, &token.user_id, &token.task_id)
+        .await
+        .context("error getting task")?
+        .ok_or(ApiError::NotFound("task not found".to_owned()))?;
+
+    let task = match token.action {
+        TaskAction::Stop => stop(&state.db, &task, &now).await?,
+        TaskAction::Extend => extend(&state.db, &task, &now).await?,
+        TaskAction::StartBreak => start_break(&state.db, &task, &now).await?,
+    };
+
+    return Ok((StatusCode::OK, Json(task)));
+}
+
+fn with_end_at(task: &TaskWithTag, end_at: &DateTime<Utc>) -> Task {
+    return Task {
+        id: task.id.to_owned(),
+        user_id: task.user_id.to_owned(),
+        tag_id: task.tag_id.to_owned(),
+        is_manual: task.is_manual,
+        seconds: end_at.signed_duration_since(task.start_at).num_seconds() as i32,
+        start_at: task.start_at,
+        end_at: *end_at,
+    };
+}
+
+/// Ends the task now, a task that already ended is left as is.
+async fn stop(db: &Db, task: &TaskWithTag, now: &DateTime<Utc>) -> Result<TaskWithTag, ApiError> {
+    if task.end_at <= *now {
+        return Ok(with_tag(&with_end_at(task, &task.end_at), task));
+    }
+
+    let stopped = with_end_at(task, now);
+
+    db::tasks::update(db, &stopped)
+        .await
+        .context("error updating task")?;
+
+    db::notifications::delete_by_task_id(db, &task.user_id, &task.id)
+        .await
+        .context("error deleting notifications")?;
+
+    return Ok(with_tag(&stopped, task));
+}
+
+/// Pushes the end of a running task back. A task that already ended is left
+/// as is and a new one with its tag is started, unless another task was started
+/// since, so the time in between isn't counted.
+async fn extend(db: &Db, task: &TaskWithTag, now: &DateTime<Utc>) -> Result<TaskWithTag, ApiError> {
+    if task.end_at > *now {
+        let end_at = task.end_at + Duration::minutes(EXTEND_BY_MINUTES);
+        let extended = with_end_at(task, &end_at);
+
+        db::tasks::update(db, &extended)
+            .await
+            .context("error updating task")?;
+
+        schedule_task_notifications(db, &extended).await?;
+
+        return Ok(with_tag(&extended, task));
+    }
+
+    let ongoing_task = db::tasks::get_ongoing(db, &task.user_id)
+        .await
+        .context("error getting ongoing task")?;
+
+    if ongoing_task.is_some() {
+        return Err(ApiError::BadRequest(
+            "you already have an ongoing task".to_owned(),
+        ));
+    }
+
+    let next_task = Task {
+        id: create_id(),
+        user_id: task.user_id.to_owned(),
+        tag_id: task.tag_id.to_owned(),
+        is_manual: false,
+        seconds: (EXTEND_BY_MINUTES * 60) as i32,
+        start_at: *now,
+        end_at: *now + Duration::minutes(EXTEND_BY_MINUTES),
+    };
+
+    db::tasks::insert(db, &next_task)
+        .await
+        .context("error inserting task")?;
+
+    schedule_task_notifications(db, &next_task).await?;
+
+    return Ok(with_tag(&next_task, task));
+}
+
+/// Stops the task if it's still going and starts a break right after it.
+async fn start_break(
+    db: &Db,
+    task: &TaskWithTag,
+    now: &DateTime<Utc>,
+) -> Result<TaskWithTag, ApiError> {
+    stop(db, task, now).await?;
+
+    let ongoing_task = db::tasks::get_ongoing(db, &task.user_id)
+        .await
+        .context("error getting ongoing task")?;
+
+    if ongoing_task.is_some() {
+        return Err(ApiError::BadRequest(
+            "you already have an ongoing task".to_owned(),
+        ));
+    }
+
+    let existing_tag = db::tags::get_by_label(db, &task.user_id, BREAK_TAG_LABEL)
+        .await
+        .context("error fetching tag")?;
+
+    let tag = match existing_tag {
+        Some(tag) => tag,
+        None => db::tags::insert(db, &task.user_id, BREAK_TAG_LABEL, VALID_TAG_COLORS[1])
+            .await
+            .context("error creating break tag")?,
+    };
+
+    let break_task = Task {
+        id: create_id(),
+        user_id: task.user_id.to_owned(),
+        tag_id: tag.id.to_owned(),
+        is_manual: false,
+        seconds: (BREAK_MINUTES * 60) as i32,
+        start_at: *now,
+        end_at: *now + Duration::minutes(BREAK_MINUTES),
+    };
+
+    db::tasks::insert(db, &break_task)
+        .await
+        .context("error inserting task")?;
+
+    schedule_task_notifications(db, &break_task).await?;
+
+    return Ok(TaskWithTag::from_task(
+        &break_task,
+        &TagColor(tag.color),
+        &TagLabel(tag.label),
+    ));
+}
+
+fn with_tag(task: &Task, tagged: &TaskWithTag) -> TaskWithTag {
+    return TaskWithTag::from_task(
+        task,
+        &TagColor(tagged.tag_color.to_owned()),
+        &TagLabel(tagged.tag_label.to_owned()),
+    );
+}
@@ -0,0 +1,262 @@
+use crate::{auth::user_id::UserId, error::ApiError, state::RequestState};
+use anyhow::Context;
+use auth::verification_code::{create_verification_code, hash_verification_code};
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use config::CONFIG;
+use db::notification_channels::{ChannelKind, NotificationChannel};
+use hyper::StatusCode;
+use notifications::{verification_message, Channel, CHANNELS};
+
+const MAX_CHANNELS: usize = 10;
+const MAX_TARGET_LENGTH: usize = 2000;
+/// Wrong codes a channel gets in total, sending a new code doesn't add more.
+const MAX_VERIFICATION_ATTEMPTS: i32 = 5;
+/// Codes sent to a channel in total, including the first one.
+const MAX_VERIFICATION_SENDS: i32 = 3;
+const RESEND_COOLDOWN_SECONDS: i64 = 60;
+const VERIFICATION_CODE_MINUTES: i64 = 15;
+
+pub async fn get_notification_channels_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+) -> Result<impl IntoResponse, ApiError> {
+    let channels = db::notification_channels::get_by_user_id(&state.db, &user_id)
+        .await
+        .context("error fetching notification channels")?;
+
+    return Ok((StatusCode::OK, Json(channels)));
+}
+
+/// A new code along with its hash and expiry, to store before sending the code.
+fn new_verification_code() -> (String, String, DateTime<Utc>) {
+    let code = create_verification_code();
+    let code_hash = hash_verification_code(&CONFIG.secret, &code);
+    let expires_at = Utc::now() + Duration::minutes(VERIFICATION_CODE_MINUTES);
+
+    return (code, code_hash, expires_at);
+}
+
+fn get_sender(kind: &ChannelKind) -> Result<&'static dyn Channel, ApiError> {
+    return CHANNELS.get(kind).ok_or(ApiError::BadRequest(format!(
+        "{} notifications aren't available",
+        kind.as_ref()
+    )));
+}
+
+/// Sends the code in the background, the response doesn't tell whether
+/// or how fast the target answered.
+fn send_verification_code(channel: &NotificationChannel, code: &str) -> Result<(), ApiError> {
+    let sender = get_sender(&channel.kind)?;
+    let channel_id = channel.id.to_owned();
+    let target = channel.target.to_owned();
+    let message = verification_message(code);
+
+    tokio::spawn(async move {
+        if let Err(e) = sender.send(&target, &message).await {
+            tracing::debug!(
+                "error sending verification code to channel {}: {:#}",
+                channel_id,
+                e
+            );
+        }
+    });
+
+    return Ok(());
+}
+
+#[derive(serde::Deserialize)]
+pub struct AddNotificationChannelBody {
+    pub kind: ChannelKind,
+    pub target: String,
+}
+
+/// Adds an unverified channel and sends it a verification code.
+pub async fn add_notification_channel_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+    Json(body): Json<AddNotificationChannelBody>,
+) -> Result<impl IntoResponse, ApiError> {
+    let target = body.target.trim();
+
+    if target.len() > MAX_TARGET_LENGTH {
+        return Err(ApiError::BadRequest(format!(
+            "target must be shorter than {MAX_TARGET_LENGTH} characters"
+        )));
+    }
+
+    get_sender(&body.kind)?
+        .validate_target(target)
+        .await
+        .map_err(|e| ApiError::BadRequest(format!("invalid target: {}", e)))?;
+
+    let channels = db::notification_channels::get_by_user_id(&state.db, &user_id)
+        .await
+        .context("error fetching notification channels")?;
+
+    if channels.len() >= MAX_CHANNELS {
+        return Err(ApiError::BadRequest(format!(
+            "you can't have more than {MAX_CHANNELS} notification channels"
+        )));
+    }
+
+    let (code, code_hash, expires_at) = new_verification_code();
+
+    let channel = db::notification_channels::insert(
+        &state.db,
+        &user_id,
+        &body.kind,
+        target,
+        &code_hash,
+        &expires_at,
+    )
+    .await
+    .context("error inserting notification channel")?
+    .ok_or(ApiError::BadRequest(
+        "you already have this notification channel".to_owned(),
+    ))?;
+
+    send_verification_code(&channel, &code)?;
+
+    return Ok((StatusCode::CREATED, Json(channel)));
+}
+
+async fn get_unverified_channel(
+    db: &db::Db,
+    user_id: &str,
+    channel_id: &str,
+) -> Result<NotificationChannel, ApiError> {
+    let channel = db::notification_channels::get_one(db, user_id, channel_id)
+        .await
+        .context("error fetching notification channel")?
+        .ok_or(ApiError::NotFound(
+            "notification channel not found".to_owned(),
+        ))?;
+
+    if channel.verified_at.is_some() {
+        return Err(ApiError::BadRequest(
+            "notification channel is already verified".to_owned(),
+        ));
+    }
+
+    return Ok(channel);
+}
+
+pub async fn resend_verification_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+    Path(channel_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let channel = get_unverified_channel(&state.db, &user_id, &channel_id).await?;
+    let now = Utc::now();
+    let cooldown_start = now - Duration::seconds(RESEND_COOLDOWN_SECONDS);
+
+    if channel.verification_sends >= MAX_VERIFICATION_SENDS
+        || channel.verification_attempts >= MAX_VERIFICATION_ATTEMPTS
+    {
+        return Err(ApiError::BadRequest(
+            "no more verification codes can be sent, remove the channel and add it again"
+                .to_owned(),
+        ));
+    }
+
+    let (code, code_hash, expires_at) = new_verification_code();
+
+    // checked again in the update, in case of concurrent resends
+    let replaced = db::notification_channels::resend_verification_code(
+        &state.db,
+        &channel.id,
+        &code_hash,
+        &expires_at,
+        &now,
+        &cooldown_start,
+        MAX_VERIFICATION_SENDS,
+    )
+    .await
+    .context("error setting verification code")?;
+
+    if !replaced {
+        return Err(ApiError::BadRequest(format!(
+            "wait {RESEND_COOLDOWN_SECONDS} seconds before asking for another verification code"
+        )));
+    }
+
+    send_verification_code(&channel, &code)?;
+
+    return Ok(StatusCode::NO_CONTENT);
+}
+
+#[derive(serde::Deserialize)]
+pub struct VerifyNotificationChannelBody {
+    pub code: String,
+}
+
+pub async fn verify_notification_channel_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+    Path(channel_id): Path<String>,
+    Json(body): Json<VerifyNotificationChannelBody>,
+) -> Result<impl IntoResponse, ApiError> {
+    let channel = get_unverified_channel(&state.db, &user_id, &channel_id).await?;
+    let now = Utc::now();
+
+    // the guess is counted before the code is compared, so parallel requests
+    // can't try more than the allowed codes
+    let attempt = db::notification_channels::claim_verification_attempt(
+        &state.db,
+        &channel.id,
+        MAX_VERIFICATION_ATTEMPTS,
+    )
+    .await
+    .context("error claiming verification attempt")?
+    .ok_or(ApiError::BadRequest(
+        "too many wrong verification codes, remove the channel and add it again".to_owned(),
+    ))?;
+
+    if attempt
+        .verification_expires_at
+        .is_none_or(|expires_at| expires_at <= now)
+    {
+        return Err(ApiError::BadRequest(
+            "verification code expired, ask for a new one".to_owned(),
+        ));
+    }
+
+    let code_hash = hash_verification_code(&CONFIG.secret, &body.code);
+
+    if attempt.verification_code_hash.as_deref() != Some(code_hash.as_str()) {
+        return Err(ApiError::BadRequest("invalid verification code".to_owned()));
+    }
+
+    db::notification_channels::mark_verified(&state.db, &channel.id, &now)
+        .await
+        .context("error verifying notification channel")?;
+
+    let channel = db::notification_channels::get_one(&state.db, &user_id, &channel.id)
+        .await
+        .context("error fetching notification channel")?;
+
+    return Ok((StatusCode::OK, Json(channel)));
+}
+
+pub async fn delete_notification_channel_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+    Path(channel_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let deleted = db::notification_channels::delete(&state.db, &user_id, &channel_id)
+        .await
+        .context("error deleting notification channel")?;
+
+    if !deleted {
+        return Err(ApiError::NotFound(
+            "notification channel not found".to_owned(),
+        ));
+    }
+
+    return Ok(StatusCode::NO_CONTENT);
+}
@@ -0,0 +1,193 @@
+use crate::{auth::user_id::UserId, error::ApiError, state::RequestState};
+use anyhow::Context;
+use axum::{extract::State, response::IntoResponse, Json};
+use chrono::{Duration, NaiveTime, Utc};
+use chrono_tz::Tz;
+use hyper::StatusCode;
+use notifications::Locale;
+
+/// Do not disturb can be turned on for up to a week at a time.
+const MAX_DND_MINUTES: i64 = 7 * 24 * 60;
+
+pub async fn get_notification_settings_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+) -> Result<impl IntoResponse, ApiError> {
+    let settings = db::notification_settings::get(&state.db, &user_id)
+        .await
+        .context("error fetching notification settings")?;
+
+    return Ok((StatusCode::OK, Json(settings)));
+}
+
+#[derive(serde::Deserialize)]
+pub struct QuietHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+#[derive(serde::Deserialize)]
+pub struct UpdateNotificationSettingsBody {
+    pub timezone: String,
+    pub quiet_hours: Option<QuietHours>,
+    /// Language of the notifications, unchanged when missing.
+    pub locale: Option<String>,
+}
+
+pub async fn update_notification_settings_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+    Json(body): Json<UpdateNotificationSettingsBody>,
+) -> Result<impl IntoResponse, ApiError> {
+    body.timezone
+        .parse::<Tz>()
+        .map_err(|_| ApiError::BadRequest("invalid timezone".to_string()))?;
+
+    if body
+        .quiet_hours
+        .as_ref()
+        .is_some_and(|quiet_hours| quiet_hours.start == quiet_hours.end)
+    {
+        return Err(ApiError::BadRequest(
+            "quiet hours must start and end at different times".to_string(),
+        ));
+    }
+
+    let locale = body
+        .locale
+        .as_deref()
+        .map(|locale| locale.parse::<Locale>())
+        .transpose()
+        .map_err(|_| ApiError::BadRequest("unsupported locale".to_string()))?;
+
+    db::notification_settings::set_quiet_hours(
+        &state.db,
+        &user_id,
+        &body.timezone,
+        body.quiet_hours
+            .map(|quiet_hours| (quiet_hours.start, quiet_hours.end)),
+    )
+    .await
+    .context("error updating notification settings")?;
+
+    if let Some(locale) = locale {
+        db::notification_settings::set_locale(&state.db, &user_id, locale.as_ref())
+            .await
+            .context("error updating notification locale")?;
+    }
+
+    let settings = db::notification_settings::get(&state.db, &user_id)
+        .await
+        .context("error fetching notification settings")?;
+
+    return Ok((StatusCode::OK, Json(settings)));
+}
+
+#[derive(serde::Deserialize)]
+pub struct UpdateDailyNotificationsBody {
+    /// Local time of the nudge sent when nothing was tracked yet, `None` turns it off.
+    pub nudge_at: Option<NaiveTime>,
+    /// Local time of the summary of the day, `None` turns it off.
+    pub summary_at: Option<NaiveTime>,
+}
+
+/// Both are sent in the timezone of the notification settings.
+pub async fn update_daily_notifications_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+    Json(body): Json<UpdateDailyNotificationsBody>,
+) -> Result<impl IntoResponse, ApiError> {
+    db::notification_settings::set_daily_times(
+        &state.db,
+        &user_id,
+        body.nudge_at.as_ref(),
+        body.summary_at.as_ref(),
+    )
+    .await
+    .context("error updating daily notifications")?;
+
+    let settings = db::notification_settings::get(&state.db, &user_id)
+        .await
+        .context("error fetching notification settings")?;
+
+    return Ok((StatusCode::OK, Json(settings)));
+}
+
+#[derive(serde::Deserialize)]
+pub struct UpdateDigestBody {
+    /// Local weekday of the weekly digest email, 0 is monday.
+    pub weekday: Option<i16>,
+    pub at: Option<NaiveTime>,
+}
+
+/// Sets when the weekly digest email is sent, leaving out both turns it off.
+pub async fn update_digest_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+    Json(body): Json<UpdateDigestBody>,
+) -> Result<impl IntoResponse, ApiError> {
+    let schedule = match (body.weekday, body.at) {
+        (Some(weekday), Some(at)) if (0..=6).contains(&weekday) => Some((weekday, at)),
+        (Some(_), Some(_)) => {
+            return Err(ApiError::BadRequest(
+                "weekday must be between 0 and 6".to_string(),
+            ))
+        }
+        (None, None) => None,
+        _ => {
+            return Err(ApiError::BadRequest(
+                "weekday and at must be set together".to_string(),
+            ))
+        }
+    };
+
+    db::notification_settings::set_digest_schedule(&state.db, &user_id, schedule)
+        .await
+        .context("error updating digest schedule")?;
+
+    let settings = db::notification_settings::get(&state.db, &user_id)
+        .await
+        .context("error fetching notification settings")?;
+
+    return Ok((StatusCode::OK, Json(settings)));
+}
+
+#[derive(serde::Deserialize)]
+pub struct StartDndBody {
+    pub minutes: i64,
+}
+
+pub async fn start_dnd_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+    Json(body): Json<StartDndBody>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !(1..=MAX_DND_MINUTES).contains(&body.minutes) {
+        return Err(ApiError::BadRequest(format!(
+            "minutes must be between 1 and {MAX_DND_MINUTES}"
+        )));
+    }
+
+    let dnd_until = Utc::now() + Duration::minutes(body.minutes);
+
+    db::notification_settings::set_dnd_until(&state.db, &user_id, Some(&dnd_until))
+        .await
+        .context("error starting do not disturb")?;
+
+    let settings = db::notification_settings::get(&state.db, &user_id)
+        .await
+        .context("error fetching notification settings")?;
+
+    return Ok((StatusCode::OK, Json(settings)));
+}
+
+pub async fn stop_dnd_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+) -> Result<impl IntoResponse, ApiError> {
+    db::notification_settings::set_dnd_until(&state.db, &user_id, None)
+        .await
+        .context("error stopping do not disturb")?;
+
+    return Ok(StatusCode::NO_CONTENT);
+}
@@ -0,0 +1,189 @@
+use crate::{auth::session::ClientInfo, error::ApiError, state::RequestState};
+use anyhow::Context;
+use auth::{
+    cookie::{create_cookie, create_empty_oidc_cookie, create_oidc_cookie},
+    oidc::{LoginState, OidcClient, OIDC_COOKIE_NAME, OIDC_LOGIN_MINUTES},
+    session_policy::SESSION_POLICY,
+    token::create_token,
+};
+use axum::{
+    extract::{Path, Query, State},
+    response::{IntoResponse, Redirect},
+    Json,
+};
+use axum_extra::{headers, TypedHeader};
+use chrono::Utc;
+use config::{OidcProviderConfig, CONFIG};
+use hyper::{header, HeaderMap, StatusCode};
+use once_cell::sync::Lazy;
+
+static OIDC_CLIENT: Lazy<OidcClient> = Lazy::new(OidcClient::new);
+
+fn get_provider(provider_id: &str) -> Result<&'static OidcProviderConfig, ApiError> {
+    return CONFIG
+        .oidc_providers
+        .iter()
+        .find(|provider| provider.id == provider_id)
+        .ok_or(ApiError::NotFound("login provider not found".to_owned()));
+}
+
+/// The frontend's callback page, it passes the code on to the verify endpoint.
+fn redirect_uri(provider: &OidcProviderConfig) -> String {
+    return format!("{}/auth/callback/{}", CONFIG.front_url, provider.id);
+}
+
+#[derive(serde::Serialize)]
+pub struct OidcProviderResponse {
+    pub id: String,
+    pub name: String,
+}
+
+pub async fn get_oidc_providers_endpoint() -> impl IntoResponse {
+    let providers = CONFIG
+        .oidc_providers
+        .iter()
+        .map(|provider| OidcProviderResponse {
+            id: provider.id.to_owned(),
+            name: provider.name.to_owned(),
+        })
+        .collect::<Vec<OidcProviderResponse>>();
+
+    return Json(providers);
+}
+
+pub async fn oidc_init_endpoint(
+    Path(provider_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let provider = get_provider(&provider_id)?;
+    let login_state = LoginState::new(&provider.id);
+
+    let url = OIDC_CLIENT
+        .authorization_url(provider, &redirect_uri(provider), &login_state)
+        .await
+        .context("error building authorization url")?;
+
+    let headers: HeaderMap = HeaderMap::from_iter(vec![(
+        header::SET_COOKIE,
+        create_oidc_cookie(
+            &login_state.to_cookie_value(&CONFIG.secret),
+            OIDC_LOGIN_MINUTES * 60,
+        )
+        .parse()
+        .context("error parsing cookie")?,
+    )]);
+
+    return Ok((headers, Redirect::to(&url)));
+}
+
+#[derive(serde::Deserialize)]
+pub struct OidcVerifyQuery {
+    pub code: String,
+    pub state: String,
+}
+
+pub async fn oidc_verify_endpoint(
+    State(state): RequestState,
+    client_info: ClientInfo,
+    Path(provider_id): Path<String>,
+    Query(query): Query<OidcVerifyQuery>,
+    cookies: Option<TypedHeader<headers::Cookie>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let provider = get_provider(&provider_id)?;
+
+    let login_state = cookies
+        .as_ref()
+        .and_then(|TypedHeader(cookies)| cookies.get(OIDC_COOKIE_NAME))
+        .and_then(|value| LoginState::from_cookie_value(&CONFIG.secret, value).ok())
+        .ok_or(ApiError::Unauthorized(
+            "login expired, please try again".to_owned(),
+        ))?;
+
+    if login_state.provider_id != provider.id || login_state.state != query.state {
+        return Err(ApiError::Unauthorized(
+            "login state doesn't match".to_owned(),
+        ));
+    }
+
+    let claims = OIDC_CLIENT
+        .verify_login(
+            provider,
+            &query.code,
+            &redirect_uri(provider),
+            &login_state.nonce,
+        )
+        .await
+        .map_err(|e| {
+            tracing::warn!("error verifying {} login: {:#}", provider.id, e);
+            ApiError::Unauthorized("the login couldn't be verified".to_owned())
+        })?;
+
+    let existing_user = db::user_identities::get_user(&state.db, &claims.iss, &claims.sub)
+        .await
+        .context("error getting user by identity")?;
+
+    let user = match existing_user {
+        Some(user) => user,
+        None => {
+            // the first login, an account with the same email is only taken
+            // over when the provider vouches for the email
+            let email = claims.verified_email().ok_or(ApiError::BadRequest(
+                "the provider didn't share a verified email".to_owned(),
+            ))?;
+
+            let user = match db::users::get_by_email(&state.db, email)
+                .await
+                .context("error getting existing user")?
+            {
+                Some(user) => user,
+                None => db::users::create(&state.db, email)
+                    .await
+                    .context("error creating user")?,
+            };
+
+            let is_linked =
+                db::user_identities::link(&state.db, &claims.iss, &claims.sub, &user.id)
+                    .await
+                    .context("error linking user identity")?;
+
+            if !is_linked {
+                return Err(ApiError::Unauthorized(
+                    "this account is already linked to another login at this provider".to_owned(),
+                ));
+            }
+
+            user
+        }
+    };
+
+    let now = Utc::now();
+    let expires_at = SESSION_POLICY.expires_at(&now, &now);
+
+    let session_id = db::sessions::insert(
+        &state.db,
+        &user.id,
+        &expires_at,
+        client_info.user_agent.as_deref(),
+        client_info.ip.as_deref(),
+    )
+    .await
+    .context("error creating session")?;
+
+    let mut headers = HeaderMap::new();
+    headers.append(
+        header::SET_COOKIE,
+        create_cookie(
+            &create_token(&CONFIG.secret, &user.id, &session_id),
+            &expires_at,
+        )
+        .parse()
+        .context("error parsing cookie")?,
+    );
+    headers.append(
+        header::SET_COOKIE,
+        create_empty_oidc_cookie()
+            .parse()
+            .context("error parsing cookie")?,
+    );
+
+    return Ok((StatusCode::OK, headers, Json(user)));
+}
@@ -0,0 +1,60 @@
+use crate::{auth::user_id::UserId, error::ApiError, state::RequestState};
+use anyhow::Context;
+use axum::{extract::State, response::IntoResponse, Json};
+use db::preferences::Preferences;
+use hyper::StatusCode;
+
+pub async fn get_preferences_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+) -> Result<impl IntoResponse, ApiError> {
+    let preferences = db::preferences::get(&state.db, &user_id)
+        .await
+        .context("error fetching preferences")?;
+
+    return Ok((StatusCode::OK, Json(preferences)));
+}
+
+/// Replaces all the preferences, devices have to be subscriptions or channels of the user.
+pub async fn update_preferences_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+    Json(preferences): Json<Preferences>,
+) -> Result<impl IntoResponse, ApiError> {
+    let subs = db::notification_subs::get_by_user_ids(&state.db, &vec![user_id.to_owned()])
+        .await
+        .context("error fetching notification subs")?;
+
+    let channels = db::notification_channels::get_by_user_id(&state.db, &user_id)
+        .await
+        .context("error fetching notification channels")?;
+
+    for (event, event_preferences) in preferences.events() {
+        let Some(devices) = &event_preferences.devices else {
+            continue;
+        };
+
+        if devices.is_empty() {
+            return Err(ApiError::BadRequest(format!(
+                "{} needs at least one device, turn it off instead",
+                event.as_ref()
+            )));
+        }
+
+        if devices.iter().any(|device| {
+            !subs.iter().any(|sub| sub.id == *device)
+                && !channels.iter().any(|channel| channel.id == *device)
+        }) {
+            return Err(ApiError::BadRequest(format!(
+                "{} has an unknown device",
+                event.as_ref()
+            )));
+        }
+    }
+
+    db::preferences::update(&state.db, &user_id, &preferences)
+        .await
+        .context("error updating preferences")?;
+
+    return Ok((StatusCode::OK, Json(preferences)));
+}
@@ -0,0 +1,80 @@
+use crate::{auth::user_id::UserId, error::ApiError, state::RequestState};
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Json,
+};
+use db::reminders::ReminderOffset;
+use hyper::StatusCode;
+
+const MAX_REMINDERS: usize = 5;
+
+/// Tasks can be up to 2 hours long, see `start_task`.
+const MAX_REMINDER_MINUTES: i32 = 120;
+
+pub async fn get_reminders(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+) -> Result<impl IntoResponse, ApiError> {
+    let reminders = db::reminders::get_all(&state.db, &user_id)
+        .await
+        .context("error fetching reminders")?;
+
+    return Ok((StatusCode::OK, Json(reminders)));
+}
+
+pub async fn add_reminder(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+    Json(offset): Json<ReminderOffset>,
+) -> Result<impl IntoResponse, ApiError> {
+    match offset {
+        ReminderOffset::BeforeEnd { minutes } if !(1..=MAX_REMINDER_MINUTES).contains(&minutes) => {
+            return Err(ApiError::BadRequest(format!(
+                "minutes must be between 1 and {MAX_REMINDER_MINUTES}"
+            )));
+        }
+        ReminderOffset::Progress { percent } if !(1..=99).contains(&percent) => {
+            return Err(ApiError::BadRequest(
+                "percent must be between 1 and 99".to_owned(),
+            ));
+        }
+        _ => {}
+    }
+
+    let reminders = db::reminders::get_all(&state.db, &user_id)
+        .await
+        .context("error fetching reminders")?;
+
+    if reminders.len() >= MAX_REMINDERS {
+        return Err(ApiError::BadRequest(format!(
+            "you can have at most {MAX_REMINDERS} reminders"
+        )));
+    }
+
+    let reminder = db::reminders::insert(&state.db, &user_id, &offset)
+        .await
+        .context("error inserting reminder")?
+        .ok_or(ApiError::BadRequest(
+            "you already have this reminder".to_owned(),
+        ))?;
+
+    return Ok((StatusCode::CREATED, Json(reminder)));
+}
+
+pub async fn delete_reminder(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+    Path(reminder_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let deleted = db::reminders::delete(&state.db, &user_id, &reminder_id)
+        .await
+        .context("error deleting reminder")?;
+
+    if !deleted {
+        return Err(ApiError::NotFound("reminder not found".to_owned()));
+    }
+
+    return Ok(StatusCode::NO_CONTENT);
+}
@@ -1,31 +1,29 @@
 use crate::{auth::user_id::UserId, date::start_of_day, error::ApiError, state::RequestState};
 use anyhow::Context;
 use axum::{
-    extract::{Query, State},
+    extract::{Path, Query, State},
     response::IntoResponse,
     Json,
 };
-use chrono::{DateTime, Duration, Months, NaiveDateTime, TimeZone, Utc};
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, NaiveDateTime, TimeZone, Utc};
 use chrono_tz::Tz;
 use db::tasks::{
-    get_hours_by_stats, get_tag_distribution_stats, StatByDate, StatByTag, StatsPrecision,
+    calendar_streaks, get_hours_by_stats, get_session_length_histogram, get_session_stats,
+    get_tag_distribution_stats, get_tag_summary_stats, get_weekday_hour_stats, StatByDate,
+    StatByTag, StatsPrecision, TagFilter,
 };
 use indexmap::IndexSet;
 use serde_json::json;
 use std::collections::HashMap;
 
-#[derive(serde::Serialize, Debug, Eq, Hash, PartialEq)]
-pub struct UniqueTag {
-    pub id: String,
-    pub label: String,
-    pub color: String,
+struct StatsRange {
+    tz: Tz,
+    start: DateTime<Tz>,
+    end: DateTime<Tz>,
 }
 
-pub async fn get_hours_by_stats_endpoint(
-    UserId(user_id): UserId,
-    State(state): RequestState,
-    Query(query): Query<HashMap<String, String>>,
-) -> Result<impl IntoResponse, ApiError> {
+/// Reads the `tz`, `start` and `end` query parameters shared by the stats endpoints.
+fn parse_stats_range(query: &HashMap<String, String>) -> Result<StatsRange, ApiError> {
     let tz = query.get("tz").map_or(Ok(Tz::UTC), |tz_str| {
         tz_str
             .parse::<Tz>()
@@ -48,12 +46,60 @@ pub async fn get_hours_by_stats_endpoint(
         .map_err(|_| ApiError::BadRequest("invalid end".to_string()))?
         .with_timezone(&tz);
 
+    return Ok(StatsRange { tz, start, end });
+}
+
+const MAX_FILTER_TAGS: usize = 100;
+
+fn parse_tag_ids(query: &HashMap<String, String>, key: &str) -> Result<Vec<String>, ApiError> {
+    let tag_ids = query.get(key).map_or(vec![], |tag_ids| {
+        tag_ids
+            .split(',')
+            .map(|tag_id| tag_id.trim())
+            .filter(|tag_id| !tag_id.is_empty())
+            .map(|tag_id| tag_id.to_owned())
+            .collect::<Vec<String>>()
+    });
+
+    if tag_ids.len() > MAX_FILTER_TAGS {
+        return Err(ApiError::BadRequest(format!(
+            "{key} must contain at most {MAX_FILTER_TAGS} tag ids"
+        )));
+    }
+
+    return Ok(tag_ids);
+}
+
+/// Reads the comma separated `tags` and `exclude_tags` query parameters.
+fn parse_tag_filter(query: &HashMap<String, String>) -> Result<TagFilter, ApiError> {
+    return Ok(TagFilter {
+        include: parse_tag_ids(query, "tags")?,
+        exclude: parse_tag_ids(query, "exclude_tags")?,
+    });
+}
+
+#[derive(serde::Serialize, Debug, Eq, Hash, PartialEq)]
+pub struct UniqueTag {
+    pub id: String,
+    pub label: String,
+    pub color: String,
+}
+
+pub async fn get_hours_by_stats_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let StatsRange { tz, start, end } = parse_stats_range(&query)?;
+
     let precision = query
         .get("precision")
         .ok_or(ApiError::BadRequest("no precision".to_string()))?
         .parse::<StatsPrecision>()
         .map_err(|_| ApiError::BadRequest("invalid precision".to_string()))?;
 
+    let tag_filter = parse_tag_filter(&query)?;
+
     let start_naive = start.naive_local();
     let end_naive = end.naive_local();
 
@@ -64,6 +110,7 @@ pub async fn get_hours_by_stats_endpoint(
         &start_naive,
         &end_naive,
         &tz,
+        &tag_filter,
     )
     .await
     .context("error getting stats")?;
@@ -106,7 +153,7 @@ fn fill_in_missing_days(
     precision: &StatsPrecision,
     start: &NaiveDateTime,
     end: &NaiveDateTime,
-    stats: &Vec<StatByDate>,
+    stats: &[StatByDate],
     tz: &Tz,
 ) -> anyhow::Result<(f64, f64, Vec<HoursByStatTz>)> {
     // turn start date to start of day
@@ -114,13 +161,18 @@ fn fill_in_missing_days(
     // from the database
     let mut date = start_of_day(start).context("error getting start of day")?;
 
+    let stats_by_date = stats
+        .iter()
+        .map(|stat| (stat.date, stat))
+        .collect::<HashMap<NaiveDateTime, &StatByDate>>();
+
     let mut new_stats: Vec<HoursByStatTz> = Vec::new();
     let mut most_hours = 0.0;
     let mut total_hours = 0.0;
 
     while date <= *end {
-        let stat = match stats.iter().find(|s| s.date == date) {
-            Some(stat) => stat.to_owned(),
+        let stat = match stats_by_date.get(&date) {
+            Some(stat) => (*stat).to_owned(),
             None => StatByDate {
                 date,
                 stats: vec![],
@@ -135,7 +187,7 @@ fn fill_in_missing_days(
         total_hours += total_hours_for_timeframe;
 
         new_stats.push(HoursByStatTz {
-            date: tz.from_local_datetime(&date).unwrap().to_rfc3339(),
+            date: local_to_tz(&date, tz).to_rfc3339(),
             stats: stat.stats,
         });
 
@@ -153,6 +205,15 @@ fn fill_in_missing_days(
     return Ok((most_hours, total_hours, new_stats));
 }
 
+/// Midnight doesn't exist on some DST transition days, fall back to
+/// reading the date as UTC instead of panicking.
+fn local_to_tz(date: &NaiveDateTime, tz: &Tz) -> DateTime<Tz> {
+    return tz
+        .from_local_datetime(date)
+        .earliest()
+        .unwrap_or_else(|| tz.from_utc_datetime(date));
+}
+
 #[derive(serde::Serialize)]
 pub struct TagDistributionStat {
     pub tag_label: String,
@@ -166,27 +227,7 @@ pub async fn get_tag_distribution_stats_endpoint(
     State(state): RequestState,
     Query(query): Query<HashMap<String, String>>,
 ) -> Result<impl IntoResponse, ApiError> {
-    let tz = query.get("tz").map_or(Ok(Tz::UTC), |tz_str| {
-        tz_str
-            .parse::<Tz>()
-            .map_err(|_| ApiError::BadRequest("invalid tz".to_string()))
-    })?;
-
-    // TODO: calls with_timezone even if tz is UTC
-    let start = query
-        .get("start")
-        .ok_or(ApiError::BadRequest("no start".to_string()))?
-        .parse::<DateTime<Utc>>()
-        .map_err(|_| ApiError::BadRequest("invalid start".to_string()))?
-        .with_timezone(&tz);
-
-    // TODO: calls with_timezone even if tz is UTC
-    let end = query
-        .get("end")
-        .ok_or(ApiError::BadRequest("no end".to_string()))?
-        .parse::<DateTime<Utc>>()
-        .map_err(|_| ApiError::BadRequest("invalid end".to_string()))?
-        .with_timezone(&tz);
+    let StatsRange { tz, start, end } = parse_stats_range(&query)?;
 
     let precision = query
         .get("precision")
@@ -194,12 +235,15 @@ pub async fn get_tag_distribution_stats_endpoint(
         .parse::<StatsPrecision>()
         .map_err(|_| ApiError::BadRequest("invalid precision".to_string()))?;
 
+    let tag_filter = parse_tag_filter(&query)?;
+
     let stats = get_tag_distribution_stats(
         &state.db,
         &user_id,
         &start.naive_local(),
         &end.naive_local(),
         &tz,
+        &tag_filter,
     )
     .await
     .context("error getting seconds by day")?;
@@ -243,3 +287,325 @@ pub async fn get_tag_distribution_stats_endpoint(
         "stats": stats,
     })));
 }
+
+pub async fn get_tag_stats_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+    Path(tag_id): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let StatsRange { tz, start, end } = parse_stats_range(&query)?;
+
+    let precision = query
+        .get("precision")
+        .ok_or(ApiError::BadRequest("no precision".to_string()))?
+        .parse::<StatsPrecision>()
+        .map_err(|_| ApiError::BadRequest("invalid precision".to_string()))?;
+
+    let tag = db::tags::get_one(&state.db, &user_id, &tag_id)
+        .await
+        .context("error fetching tag")?
+        .ok_or(ApiError::NotFound("tag not found".to_string()))?;
+
+    let start_naive = start.naive_local();
+    let end_naive = end.naive_local();
+
+    let tag_filter = TagFilter {
+        include: vec![tag.id.to_owned()],
+        exclude: vec![],
+    };
+
+    let stats = get_hours_by_stats(
+        &state.db,
+        &user_id,
+        &precision,
+        &start_naive,
+        &end_naive,
+        &tz,
+        &tag_filter,
+    )
+    .await
+    .context("error getting stats")?;
+
+    let (most_hours, _, stats_with_missing_days) =
+        fill_in_missing_days(&precision, &start_naive, &end_naive, &stats, &tz)
+            .context("error filling in missing days")?;
+
+    let summary =
+        get_tag_summary_stats(&state.db, &user_id, &tag.id, &start_naive, &end_naive, &tz)
+            .await
+            .context("error getting tag summary")?;
+
+    return Ok(Json(json!({
+        "precision": precision,
+        "start": start.to_rfc3339(),
+        "end": end.to_rfc3339(),
+        "tag": UniqueTag {
+            id: tag.id,
+            label: tag.label,
+            color: tag.color,
+        },
+        "most_hours": most_hours,
+        "total_seconds": summary.seconds.unwrap_or(0),
+        "sessions": summary.sessions,
+        "avg_session_seconds": summary.avg_session_seconds.unwrap_or(0.0).round() as i64,
+        "first_used_at": summary.first_used_at,
+        "last_used_at": summary.last_used_at,
+        "stats": stats_with_missing_days,
+    })));
+}
+
+pub async fn get_weekday_hour_stats_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let StatsRange { tz, start, end } = parse_stats_range(&query)?;
+
+    let tag_filter = parse_tag_filter(&query)?;
+
+    let matrix = get_weekday_hour_stats(
+        &state.db,
+        &user_id,
+        &start.with_timezone(&Utc),
+        &end.with_timezone(&Utc),
+        &tz,
+        &tag_filter,
+    )
+    .await
+    .context("error getting weekday hour stats")?;
+
+    let most_seconds = matrix.iter().flatten().max().copied().unwrap_or(0);
+    let total_seconds = matrix.iter().flatten().sum::<i64>();
+
+    return Ok(Json(json!({
+        "start": start.to_rfc3339(),
+        "end": end.to_rfc3339(),
+        "most_seconds": most_seconds,
+        "total_seconds": total_seconds,
+        "stats": matrix,
+    })));
+}
+
+#[derive(serde::Serialize)]
+pub struct CalendarDay {
+    pub date: String,
+    pub seconds: i64,
+    pub level: u8,
+    pub tag_color: Option<String>,
+}
+
+/// Quartiles of the days with tracked time, used to map each day
+/// to an intensity level between 1 and 4.
+fn calendar_quantiles(day_seconds: &[i64]) -> [i64; 3] {
+    let mut tracked = day_seconds
+        .iter()
+        .copied()
+        .filter(|seconds| *seconds > 0)
+        .collect::<Vec<i64>>();
+
+    if tracked.is_empty() {
+        return [0, 0, 0];
+    }
+
+    tracked.sort_unstable();
+
+    let nearest_rank = |percentile: usize| {
+        let rank = (percentile * tracked.len()).div_ceil(100);
+        return tracked[rank.max(1) - 1];
+    };
+
+    return [nearest_rank(25), nearest_rank(50), nearest_rank(75)];
+}
+
+fn calendar_level(seconds: i64, quantiles: &[i64; 3]) -> u8 {
+    if seconds <= 0 {
+        return 0;
+    }
+
+    let level = quantiles.iter().filter(|q| seconds > **q).count();
+
+    return level as u8 + 1;
+}
+
+pub async fn get_calendar_stats_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let tz = query.get("tz").map_or(Ok(Tz::UTC), |tz_str| {
+        tz_str
+            .parse::<Tz>()
+            .map_err(|_| ApiError::BadRequest("invalid tz".to_string()))
+    })?;
+
+    let year = query
+        .get("year")
+        .ok_or(ApiError::BadRequest("no year".to_string()))?
+        .parse::<i32>()
+        .map_err(|_| ApiError::BadRequest("invalid year".to_string()))?;
+
+    let min_seconds = query.get("min_seconds").map_or(Ok(1), |min_seconds| {
+        min_seconds
+            .parse::<i64>()
+            .map_err(|_| ApiError::BadRequest("invalid min_seconds".to_string()))
+    })?;
+
+    let tag_filter = parse_tag_filter(&query)?;
+
+    let first_day = NaiveDate::from_ymd_opt(year, 1, 1)
+        .ok_or(ApiError::BadRequest("invalid year".to_string()))?;
+    let last_day = NaiveDate::from_ymd_opt(year, 12, 31)
+        .ok_or(ApiError::BadRequest("invalid year".to_string()))?;
+
+    let start = first_day
+        .and_hms_opt(0, 0, 0)
+        .context("error and_hms_opt")?;
+    let end = last_day
+        .and_hms_opt(23, 59, 59)
+        .context("error and_hms_opt")?;
+
+    let stats = get_hours_by_stats(
+        &state.db,
+        &user_id,
+        &StatsPrecision::Day,
+        &start,
+        &end,
+        &tz,
+        &tag_filter,
+    )
+    .await
+    .context("error getting stats")?;
+
+    let (_, total_hours, stats_with_missing_days) =
+        fill_in_missing_days(&StatsPrecision::Day, &start, &end, &stats, &tz)
+            .context("error filling in missing days")?;
+
+    let day_seconds = stats_with_missing_days
+        .iter()
+        .map(|day| {
+            let hours = day.stats.iter().fold(0.0, |acc, stat| acc + stat.hours);
+            return (hours * 3600.0).round() as i64;
+        })
+        .collect::<Vec<i64>>();
+
+    let quantiles = calendar_quantiles(&day_seconds);
+
+    let today = Utc::now().with_timezone(&tz).date_naive();
+    // only the year that contains today has a current streak
+    let today_index = if today < first_day || today > last_day {
+        None
+    } else {
+        Some(today.signed_duration_since(first_day).num_days() as usize)
+    };
+
+    let (current_streak, longest_streak) =
+        calendar_streaks(&day_seconds, min_seconds.max(1), today_index);
+
+    let days = stats_with_missing_days
+        .into_iter()
+        .zip(day_seconds.iter())
+        .map(|(day, seconds)| {
+            let dominant_tag = day.stats.iter().max_by(|a, b| a.hours.total_cmp(&b.hours));
+
+            return CalendarDay {
+                date: day.date,
+                seconds: *seconds,
+                level: calendar_level(*seconds, &quantiles),
+                tag_color: dominant_tag.map(|tag| tag.tag_color.to_owned()),
+            };
+        })
+        .collect::<Vec<CalendarDay>>();
+
+    return Ok(Json(json!({
+        "year": first_day.year(),
+        "min_seconds": min_seconds,
+        "total_seconds": (total_hours * 3600.0).round() as i64,
+        "quantiles": quantiles,
+        "current_streak": current_streak,
+        "longest_streak": longest_streak,
+        "days": days,
+    })));
+}
+
+pub async fn get_session_stats_endpoint(
+    UserId(user_id): UserId,
+    State(state): RequestState,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let StatsRange { tz, start, end } = parse_stats_range(&query)?;
+
+    let tag_filter = parse_tag_filter(&query)?;
+
+    let start_naive = start.naive_local();
+    let end_naive = end.naive_local();
+
+    let session_stats = get_session_stats(
+        &state.db,
+        &user_id,
+        &start_naive,
+        &end_naive,
+        &tz,
+        &tag_filter,
+    )
+    .await
+    .context("error getting session stats")?;
+
+    let histogram = get_session_length_histogram(
+        &state.db,
+        &user_id,
+        &start_naive,
+        &end_naive,
+        &tz,
+        &tag_filter,
+    )
+    .await
+    .context("error getting session length histogram")?;
+
+    // a range ending at 23:59:59 counts as a full day
+    let days = ((end_naive - start_naive).num_seconds() as f64 / 86400.0)
+        .ceil()
+        .max(1.0);
+
+    let tasks_per_active_day = match session_stats.active_days {
+        0 => 0.0,
+        active_days => session_stats.sessions as f64 / active_days as f64,
+    };
+
+    return Ok(Json(json!({
+        "start": start.to_rfc3339(),
+        "end": end.to_rfc3339(),
+        "sessions": session_stats.sessions,
+        "manual_sessions": session_stats.manual_sessions,
+        "timer_sessions": session_stats.timer_sessions,
+        "active_days": session_stats.active_days,
+        "tasks_per_day": session_stats.sessions as f64 / days,
+        "tasks_per_active_day": tasks_per_active_day,
+        "median_seconds": session_stats.median_seconds.unwrap_or(0.0).round() as i64,
+        "p90_seconds": session_stats.p90_seconds.unwrap_or(0.0).round() as i64,
+        "longest_seconds": session_stats.longest_seconds.unwrap_or(0),
+        "histogram": histogram,
+    })));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calendar_quantiles_and_levels() {
+        let day_seconds = [0, 100, 200, 300, 400, 0, 500, 600, 700, 800];
+
+        let quantiles = calendar_quantiles(&day_seconds);
+        assert_eq!(quantiles, [200, 400, 600]);
+
+        assert_eq!(calendar_level(0, &quantiles), 0);
+        assert_eq!(calendar_level(100, &quantiles), 1);
+        assert_eq!(calendar_level(200, &quantiles), 1);
+        assert_eq!(calendar_level(300, &quantiles), 2);
+        assert_eq!(calendar_level(600, &quantiles), 3);
+        assert_eq!(calendar_level(800, &quantiles), 4);
+
+        assert_eq!(calendar_quantiles(&[0, 0]), [0, 0, 0]);
+    }
+}
@@ -24,7 +24,8 @@ pub struct AddTagBody {
     pub color: String,
 }
 
-static VALID_TAG_COLORS: [&str; 5] = ["#d13c4b", "#1287A8", "#33a02c", "#f28e2c", "#bc80bd"];
+pub(crate) static VALID_TAG_COLORS: [&str; 5] =
+    ["#d13c4b", "#1287A8", "#33a02c", "#f28e2c", "#bc80bd"];
 
 pub async fn add_tag(
     UserId(user_id): UserId,
@@ -8,6 +8,7 @@ use axum::{
 use chrono::{DateTime, Duration, Utc};
 use db::{
     create_id,
+    notifications::{DeepLink, NewNotification, NotificationKind},
     tasks::{TagColor, TagLabel, Task, TaskWithTag},
 };
 use hyper::StatusCode;
@@ -19,8 +20,8 @@ pub async fn get_tasks(
     State(ctx): RequestState,
     Query(query): Query<HashMap<String, String>>,
 ) -> Result<impl IntoResponse, ApiError> {
-    let last_id = query.get("last_id").map_or(None, |last_id| {
-        if last_id.len() == 0 {
+    let last_id = query.get("last_id").and_then(|last_id| {
+        if last_id.is_empty() {
             None
         } else {
             Some(last_id.as_str())
@@ -97,20 +98,63 @@ pub async fn start_task(
     let task_with_tag =
         TaskWithTag::from_task(&task, &TagColor(tag.color), &TagLabel(tag.label.to_owned()));
 
-    db::notifications::insert(
-        &state.db,
-        &user_id,
-        &task.id,
-        "Task finished",
-        &format!("Your task '{}' has finished", &tag.label),
-        &task.end_at,
-    )
-    .await
-    .context("error inserting notification")?;
+    schedule_task_notifications(&state.db, &task).await?;
 
     return Ok((StatusCode::CREATED, Json(task_with_tag)));
 }
 
+/// Schedules the notification sent when `task` ends along with the user's
+/// reminders during it, replacing whatever was scheduled for the task before.
+/// Their text is rendered when they're sent, in the user's language.
+pub(crate) async fn schedule_task_notifications(
+    db: &db::Db,
+    task: &Task,
+) -> Result<(), anyhow::Error> {
+    let reminders = db::reminders::get_all(db, &task.user_id)
+        .await
+        .context("error getting reminders")?;
+
+    let now = Utc::now();
+
+    let mut notifications = reminders
+        .iter()
+        .filter_map(|reminder| {
+            let send_at = reminder
+                .offset
+                .send_at(&task.start_at, &task.end_at)
+                .filter(|send_at| *send_at > now)?;
+
+            Some(NewNotification {
+                kind: NotificationKind::Reminder,
+                send_at,
+                link: Some(DeepLink::Task {
+                    task_id: task.id.to_owned(),
+                }),
+                reminder: Some(reminder.offset),
+            })
+        })
+        .collect::<Vec<NewNotification>>();
+
+    // two reminders going off at once would only be noise
+    notifications.sort_by_key(|notification| notification.send_at);
+    notifications.dedup_by_key(|notification| notification.send_at);
+
+    notifications.push(NewNotification {
+        kind: NotificationKind::Finished,
+        send_at: task.end_at,
+        link: Some(DeepLink::Task {
+            task_id: task.id.to_owned(),
+        }),
+        reminder: None,
+    });
+
+    db::notifications::replace_pending_for_task(db, &task.user_id, &task.id, &notifications)
+        .await
+        .context("error scheduling notifications")?;
+
+    return Ok(());
+}
+
 pub async fn stop_ongoing_task(
     UserId(user_id): UserId,
     State(state): RequestState,
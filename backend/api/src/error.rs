@@ -32,8 +32,8 @@ impl IntoResponse for ApiError {
                 (StatusCode::INTERNAL_SERVER_ERROR, "unexpected error".into())
             }
             ApiError::BadRequest(err) => (StatusCode::BAD_REQUEST, err),
-            ApiError::Unauthorized(err) => (StatusCode::UNAUTHORIZED, err.into()),
-            ApiError::NotFound(err) => (StatusCode::NOT_FOUND, err.into()),
+            ApiError::Unauthorized(err) => (StatusCode::UNAUTHORIZED, err),
+            ApiError::NotFound(err) => (StatusCode::NOT_FOUND, err),
             ApiError::Forbidden => (StatusCode::FORBIDDEN, "forbidden".into()),
         };
 
@@ -18,6 +18,7 @@ pub mod types;
 
 pub async fn start_api() -> () {
     let db = db::get_db().await;
+    tokio::spawn(auth::session::start_session_cleanup(db.clone()));
     let state = RequestStateStruct::new(db);
 
     let router = endpoints::router();
@@ -54,7 +55,12 @@ pub async fn start_api() -> () {
         listener.local_addr().expect("error getting local addr")
     );
 
-    axum::serve(listener, app).await.expect("error serving app");
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .await
+    .expect("error serving app");
 }
 
 fn cors() -> CorsLayer {
@@ -64,6 +70,7 @@ fn cors() -> CorsLayer {
             Method::POST,
             Method::OPTIONS,
             Method::PATCH,
+            Method::PUT,
             Method::DELETE,
             Method::HEAD,
         ])
@@ -0,0 +1,169 @@
+use crate::token::{create_signature, ID_SPLITTER, SIGNATURE_SPLITTER};
+use chrono::{DateTime, TimeZone, Utc};
+use std::str::FromStr;
+
+/// How much [`TaskAction::Extend`] adds to a task.
+pub const EXTEND_BY_MINUTES: i64 = 5;
+
+/// How long the break started by [`TaskAction::StartBreak`] lasts.
+pub const BREAK_MINUTES: i64 = 5;
+
+/// Something a notification lets the user do to a task without opening the app.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskAction {
+    Stop,
+    Extend,
+    StartBreak,
+}
+
+impl FromStr for TaskAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stop" => Ok(TaskAction::Stop),
+            "extend" => Ok(TaskAction::Extend),
+            "start-break" => Ok(TaskAction::StartBreak),
+            _ => Err(anyhow::anyhow!("invalid task action")),
+        }
+    }
+}
+
+impl AsRef<str> for TaskAction {
+    fn as_ref(&self) -> &str {
+        match self {
+            TaskAction::Stop => "stop",
+            TaskAction::Extend => "extend",
+            TaskAction::StartBreak => "start-break",
+        }
+    }
+}
+
+/// Allows one action on one task, once, until it expires.
+#[derive(Debug, PartialEq)]
+pub struct ActionToken {
+    /// Recorded when the token is used, so it can't be replayed.
+    pub id: String,
+    pub user_id: String,
+    pub task_id: String,
+    pub action: TaskAction,
+    pub expires_at: DateTime<Utc>,
+}
+
+pub fn create_action_token(
+    secret: &str,
+    user_id: &str,
+    task_id: &str,
+    action: &TaskAction,
+    expires_at: &DateTime<Utc>,
+) -> String {
+    let id = uuid::Uuid::new_v4().simple().to_string();
+
+    let data = [
+        &id,
+        user_id,
+        task_id,
+        action.as_ref(),
+        &expires_at.timestamp().to_string(),
+    ]
+    .join(ID_SPLITTER);
+
+    let signature = create_signature(secret, &data);
+
+    return format!("{data}{SIGNATURE_SPLITTER}{signature}");
+}
+
+pub fn verify_action_token(
+    secret: &str,
+    token: &str,
+    now: &DateTime<Utc>,
+) -> Result<ActionToken, anyhow::Error> {
+    let (data, signature) = token.split_once(SIGNATURE_SPLITTER).ok_or(anyhow::anyhow!(
+        "could not split action token into data and signature"
+    ))?;
+
+    if signature != create_signature(secret, data) {
+        return Err(anyhow::anyhow!("invalid signature"));
+    }
+
+    let parts: Vec<&str> = data.split(ID_SPLITTER).collect();
+
+    if parts.len() != 5 {
+        return Err(anyhow::anyhow!(
+            "could not split action token data into id, user_id, task_id, action and expiry"
+        ));
+    }
+
+    let expires_at = parts[4]
+        .parse::<i64>()
+        .ok()
+        .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single())
+        .ok_or(anyhow::anyhow!("invalid action token expiry"))?;
+
+    if expires_at < *now {
+        return Err(anyhow::anyhow!("action token expired"));
+    }
+
+    return Ok(ActionToken {
+        id: parts[0].to_string(),
+        user_id: parts[1].to_string(),
+        task_id: parts[2].to_string(),
+        action: parts[3].parse()?,
+        expires_at,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::create_token;
+    use chrono::Duration;
+
+    #[test]
+    fn test_action_token_round_trip() {
+        let expires_at = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let token = create_action_token("secret", "user", "task", &TaskAction::Extend, &expires_at);
+        let verified =
+            verify_action_token("secret", &token, &(expires_at - Duration::minutes(1))).unwrap();
+
+        assert_eq!(
+            verified,
+            ActionToken {
+                id: verified.id.to_owned(),
+                user_id: "user".to_owned(),
+                task_id: "task".to_owned(),
+                action: TaskAction::Extend,
+                expires_at,
+            }
+        );
+
+        // every token of the same action is told apart
+        let other = create_action_token("secret", "user", "task", &TaskAction::Extend, &expires_at);
+        assert_ne!(
+            verify_action_token("secret", &other, &(expires_at - Duration::minutes(1)))
+                .unwrap()
+                .id,
+            verified.id
+        );
+    }
+
+    #[test]
+    fn test_action_token_rejects_invalid_tokens() {
+        let expires_at = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let now = expires_at - Duration::minutes(1);
+        let token = create_action_token("secret", "user", "task", &TaskAction::Stop, &expires_at);
+
+        assert!(verify_action_token("other-secret", &token, &now).is_err());
+        assert!(
+            verify_action_token("secret", &token, &(expires_at + Duration::seconds(1))).is_err()
+        );
+
+        // the action is signed, it can't be swapped for another one
+        let swapped = token.replacen(".stop.", ".extend.", 1);
+        assert!(verify_action_token("secret", &swapped, &now).is_err());
+
+        // session tokens aren't action tokens
+        let session_token = create_token("secret", "user", "session");
+        assert!(verify_action_token("secret", &session_token, &now).is_err());
+    }
+}
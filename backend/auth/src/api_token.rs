@@ -0,0 +1,41 @@
+use crate::token::create_signature;
+
+/// Makes personal API tokens recognizable, like in a leaked config file.
+pub const API_TOKEN_PREFIX: &str = "tasks_";
+
+/// A random personal API token, only shown to the user once.
+pub fn create_api_token() -> String {
+    return format!(
+        "{API_TOKEN_PREFIX}{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+}
+
+/// The `token_hash` a request's token is looked up by. Keyed with the server
+/// secret instead of salted, so the same token always finds its row.
+pub fn hash_api_token(secret: &str, token: &str) -> String {
+    return create_signature(secret, &format!("api-token.{token}"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_api_token() {
+        let token = create_api_token();
+
+        assert!(token.starts_with(API_TOKEN_PREFIX));
+        assert_eq!(token.len(), API_TOKEN_PREFIX.len() + 64);
+        assert_ne!(token, create_api_token());
+        assert_eq!(
+            hash_api_token("secret", &token),
+            hash_api_token("secret", &token)
+        );
+        assert_ne!(
+            hash_api_token("secret", &token),
+            hash_api_token("other-secret", &token)
+        );
+    }
+}
@@ -1,6 +1,8 @@
 use chrono::{DateTime, Utc};
 use config::IS_PROD;
 
+use crate::oidc::OIDC_COOKIE_NAME;
+
 pub static COOKIE_NAME: &str = "token";
 
 pub fn create_cookie(token: &str, expiry: &DateTime<Utc>) -> String {
@@ -12,9 +14,21 @@ pub fn create_cookie(token: &str, expiry: &DateTime<Utc>) -> String {
     )
 }
 
-pub fn create_empty_cookie<'a>() -> String {
+pub fn create_empty_cookie() -> String {
     format!(
         "{COOKIE_NAME}=; Path=/; SameSite=Strict; HttpOnly;{}",
         if *IS_PROD { " Secure;" } else { "" }
     )
 }
+
+/// Holds the OIDC login state, `Lax` so it survives the redirect back from the provider.
+pub fn create_oidc_cookie(value: &str, max_age_seconds: i64) -> String {
+    format!(
+        "{OIDC_COOKIE_NAME}={value}; Max-Age={max_age_seconds}; Path=/; SameSite=Lax; HttpOnly;{}",
+        if *IS_PROD { " Secure;" } else { "" }
+    )
+}
+
+pub fn create_empty_oidc_cookie() -> String {
+    create_oidc_cookie("", 0)
+}
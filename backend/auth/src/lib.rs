@@ -1,2 +1,9 @@
+pub mod action_token;
+pub mod api_token;
 pub mod cookie;
+#[cfg(test)]
+mod mock_oidc;
+pub mod oidc;
+pub mod session_policy;
 pub mod token;
+pub mod verification_code;
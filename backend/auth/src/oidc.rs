@@ -0,0 +1,511 @@
+//! Logging in with any OpenID Connect provider, its endpoints and signing
+//! keys are discovered from the issuer.
+
+use crate::token::{create_signature, SIGNATURE_SPLITTER};
+use anyhow::Context;
+use config::OidcProviderConfig;
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use std::{collections::HashMap, sync::Mutex};
+
+/// Holds the [`LoginState`] between the redirect to the provider and the callback.
+pub const OIDC_COOKIE_NAME: &str = "oidc";
+
+/// How long the user has to log in at the provider.
+pub const OIDC_LOGIN_MINUTES: i64 = 10;
+
+const SCOPES: &str = "openid email";
+
+const STATE_SPLITTER: char = '.';
+
+/// The parts of the provider's `/.well-known/openid-configuration` the login needs.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct Discovery {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub email: Option<String>,
+    /// A bool, or a string with some providers.
+    pub email_verified: Option<serde_json::Value>,
+    pub nonce: Option<String>,
+}
+
+impl IdTokenClaims {
+    /// The email, only if the provider says it was verified.
+    pub fn verified_email(&self) -> Option<&str> {
+        let is_verified = match &self.email_verified {
+            Some(serde_json::Value::Bool(verified)) => *verified,
+            Some(serde_json::Value::String(verified)) => verified == "true",
+            _ => false,
+        };
+
+        if !is_verified {
+            return None;
+        }
+
+        return self.email.as_deref();
+    }
+}
+
+/// Ties the callback to the browser that started the login, kept in a signed cookie.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoginState {
+    pub provider_id: String,
+    /// Sent to the provider and back, it has to match the cookie.
+    pub state: String,
+    /// Ends up in the ID token, so a token issued for another login isn't accepted.
+    pub nonce: String,
+}
+
+impl LoginState {
+    pub fn new(provider_id: &str) -> Self {
+        return Self {
+            provider_id: provider_id.to_owned(),
+            state: uuid::Uuid::new_v4().simple().to_string(),
+            nonce: uuid::Uuid::new_v4().simple().to_string(),
+        };
+    }
+
+    pub fn to_cookie_value(&self, secret: &str) -> String {
+        let data = format!(
+            "{}{STATE_SPLITTER}{}{STATE_SPLITTER}{}",
+            self.provider_id, self.state, self.nonce
+        );
+        let signature = create_signature(secret, &format!("oidc.{data}"));
+
+        return format!("{data}{SIGNATURE_SPLITTER}{signature}");
+    }
+
+    pub fn from_cookie_value(secret: &str, value: &str) -> Result<Self, anyhow::Error> {
+        let (data, signature) = value
+            .rsplit_once(SIGNATURE_SPLITTER)
+            .context("could not split login state into data and signature")?;
+
+        if signature != create_signature(secret, &format!("oidc.{data}")) {
+            anyhow::bail!("invalid login state signature");
+        }
+
+        // the state and nonce are hex, the provider id may have dots
+        let mut parts = data.rsplitn(3, STATE_SPLITTER);
+        let (Some(nonce), Some(state), Some(provider_id)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            anyhow::bail!("could not split login state");
+        };
+
+        return Ok(Self {
+            provider_id: provider_id.to_owned(),
+            state: state.to_owned(),
+            nonce: nonce.to_owned(),
+        });
+    }
+}
+
+/// Where to send the user to log in at the provider.
+pub fn authorization_url(
+    discovery: &Discovery,
+    provider: &OidcProviderConfig,
+    redirect_uri: &str,
+    login_state: &LoginState,
+) -> Result<String, anyhow::Error> {
+    let url = reqwest::Url::parse_with_params(
+        &discovery.authorization_endpoint,
+        &[
+            ("response_type", "code"),
+            ("client_id", &provider.client_id),
+            ("redirect_uri", redirect_uri),
+            ("scope", SCOPES),
+            ("state", &login_state.state),
+            ("nonce", &login_state.nonce),
+        ],
+    )
+    .context("invalid authorization endpoint")?;
+
+    return Ok(url.to_string());
+}
+
+/// Checks the ID token's signature against the provider's keys, that it was
+/// issued by `issuer` for this client and that it belongs to this login.
+pub fn verify_id_token(
+    id_token: &str,
+    jwks: &JwkSet,
+    issuer: &str,
+    client_id: &str,
+    nonce: &str,
+) -> Result<IdTokenClaims, anyhow::Error> {
+    let header = jsonwebtoken::decode_header(id_token).context("invalid id token header")?;
+
+    // the client secret isn't a key the provider should sign with
+    if matches!(
+        header.alg,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    ) {
+        anyhow::bail!("id token signed with a symmetric algorithm");
+    }
+
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+    .context("id token signed with an unknown key")?;
+
+    let key = DecodingKey::from_jwk(jwk).context("invalid jwk")?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_audience(&[client_id]);
+    validation.set_issuer(&[issuer]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+    let claims = jsonwebtoken::decode::<IdTokenClaims>(id_token, &key, &validation)
+        .context("invalid id token")?
+        .claims;
+
+    if claims.nonce.as_deref() != Some(nonce) {
+        anyhow::bail!("id token nonce doesn't match");
+    }
+
+    return Ok(claims);
+}
+
+#[derive(serde::Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Talks to the providers, caching their discovery documents and keys.
+pub struct OidcClient {
+    client: reqwest::Client,
+    discoveries: Mutex<HashMap<String, Discovery>>,
+    jwks: Mutex<HashMap<String, JwkSet>>,
+}
+
+impl Default for OidcClient {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl OidcClient {
+    pub fn new() -> Self {
+        return Self {
+            client: reqwest::Client::new(),
+            discoveries: Mutex::new(HashMap::new()),
+            jwks: Mutex::new(HashMap::new()),
+        };
+    }
+
+    pub async fn discover(&self, issuer: &str) -> Result<Discovery, anyhow::Error> {
+        if let Some(discovery) = self.discoveries.lock().unwrap().get(issuer) {
+            return Ok(discovery.clone());
+        }
+
+        let discovery = self
+            .client
+            .get(format!(
+                "{}/.well-known/openid-configuration",
+                issuer.trim_end_matches('/')
+            ))
+            .send()
+            .await
+            .context("error sending discovery request")?
+            .error_for_status()
+            .context("error getting discovery document")?
+            .json::<Discovery>()
+            .await
+            .context("error parsing discovery document")?;
+
+        // the tokens are checked against the discovered issuer
+        if discovery.issuer.trim_end_matches('/') != issuer.trim_end_matches('/') {
+            anyhow::bail!(
+                "discovered issuer {} doesn't match {}",
+                discovery.issuer,
+                issuer
+            );
+        }
+
+        self.discoveries
+            .lock()
+            .unwrap()
+            .insert(issuer.to_owned(), discovery.clone());
+
+        return Ok(discovery);
+    }
+
+    /// The provider's keys, fetched again when the token is signed with a
+    /// key that isn't cached, like after the provider rotated its keys.
+    async fn get_jwks(
+        &self,
+        discovery: &Discovery,
+        kid: Option<&str>,
+    ) -> Result<JwkSet, anyhow::Error> {
+        if let Some(jwks) = self.jwks.lock().unwrap().get(&discovery.jwks_uri) {
+            if kid.is_none_or(|kid| jwks.find(kid).is_some()) {
+                return Ok(jwks.clone());
+            }
+        }
+
+        let jwks = self
+            .client
+            .get(&discovery.jwks_uri)
+            .send()
+            .await
+            .context("error sending jwks request")?
+            .error_for_status()
+            .context("error getting jwks")?
+            .json::<JwkSet>()
+            .await
+            .context("error parsing jwks")?;
+
+        self.jwks
+            .lock()
+            .unwrap()
+            .insert(discovery.jwks_uri.to_owned(), jwks.clone());
+
+        return Ok(jwks);
+    }
+
+    pub async fn authorization_url(
+        &self,
+        provider: &OidcProviderConfig,
+        redirect_uri: &str,
+        login_state: &LoginState,
+    ) -> Result<String, anyhow::Error> {
+        let discovery = self.discover(&provider.issuer).await?;
+
+        return authorization_url(&discovery, provider, redirect_uri, login_state);
+    }
+
+    /// Exchanges the code from the callback for an ID token and verifies it.
+    pub async fn verify_login(
+        &self,
+        provider: &OidcProviderConfig,
+        code: &str,
+        redirect_uri: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, anyhow::Error> {
+        let discovery = self.discover(&provider.issuer).await?;
+
+        let token_response = self
+            .client
+            .post(&discovery.token_endpoint)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", redirect_uri),
+                ("client_id", &provider.client_id),
+                ("client_secret", &provider.client_secret),
+            ])
+            .send()
+            .await
+            .context("error sending token request")?;
+
+        if !token_response.status().is_success() {
+            let status = token_response.status();
+            let body = token_response.text().await.unwrap_or_default();
+            tracing::error!(
+                "error getting id token - status: {}, body: {}",
+                status,
+                body
+            );
+
+            anyhow::bail!("error getting id token - status: {}", status);
+        }
+
+        let id_token = token_response
+            .json::<TokenResponse>()
+            .await
+            .context("error parsing token response")?
+            .id_token;
+
+        let kid = jsonwebtoken::decode_header(&id_token)
+            .context("invalid id token header")?
+            .kid;
+        let jwks = self.get_jwks(&discovery, kid.as_deref()).await?;
+
+        return verify_id_token(
+            &id_token,
+            &jwks,
+            &discovery.issuer,
+            &provider.client_id,
+            nonce,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_oidc::{sign_id_token, MockOidcIssuer, TEST_KID};
+    use serde_json::json;
+
+    fn provider(issuer: &str) -> OidcProviderConfig {
+        return OidcProviderConfig {
+            id: "sso".to_owned(),
+            name: "Company SSO".to_owned(),
+            issuer: issuer.to_owned(),
+            client_id: "tasks".to_owned(),
+            client_secret: "client-secret".to_owned(),
+        };
+    }
+
+    fn claims(issuer: &str, nonce: &str) -> serde_json::Value {
+        return json!({
+            "iss": issuer,
+            "aud": "tasks",
+            "sub": "user-1",
+            "email": "sso@test.local",
+            "email_verified": true,
+            "nonce": nonce,
+            "exp": chrono::Utc::now().timestamp() + 300,
+        });
+    }
+
+    #[test]
+    fn test_login_state_cookie() {
+        let login_state = LoginState::new("gitlab.example");
+        let value = login_state.to_cookie_value("secret");
+
+        assert_eq!(
+            LoginState::from_cookie_value("secret", &value).unwrap(),
+            login_state
+        );
+        assert!(LoginState::from_cookie_value("other-secret", &value).is_err());
+        assert!(
+            LoginState::from_cookie_value("secret", &value.replace("gitlab", "other")).is_err()
+        );
+    }
+
+    #[test]
+    fn test_verified_email() {
+        let claims = |email_verified: serde_json::Value| {
+            return serde_json::from_value::<IdTokenClaims>(json!({
+                "iss": "https://sso.test.local",
+                "sub": "user-1",
+                "email": "sso@test.local",
+                "email_verified": email_verified,
+            }))
+            .unwrap();
+        };
+
+        assert_eq!(claims(json!(true)).verified_email(), Some("sso@test.local"));
+        assert_eq!(
+            claims(json!("true")).verified_email(),
+            Some("sso@test.local")
+        );
+        assert_eq!(claims(json!(false)).verified_email(), None);
+        assert_eq!(claims(json!("false")).verified_email(), None);
+        assert_eq!(claims(json!(null)).verified_email(), None);
+
+        let missing = serde_json::from_value::<IdTokenClaims>(json!({
+            "iss": "https://sso.test.local",
+            "sub": "user-1",
+            "email": "sso@test.local",
+        }))
+        .unwrap();
+        assert_eq!(missing.verified_email(), None);
+    }
+
+    #[tokio::test]
+    async fn test_login_with_mock_issuer() {
+        let issuer = MockOidcIssuer::start().await;
+        let provider = provider(&issuer.url);
+        let client = OidcClient::new();
+        let login_state = LoginState::new(&provider.id);
+
+        let url = client
+            .authorization_url(
+                &provider,
+                "http://front.local/auth/callback/sso",
+                &login_state,
+            )
+            .await
+            .unwrap();
+        assert!(url.starts_with(&format!("{}/authorize?", issuer.url)));
+        assert!(url.contains("client_id=tasks"));
+        assert!(url.contains("scope=openid+email"));
+        assert!(url.contains(&format!("nonce={}", login_state.nonce)));
+
+        issuer
+            .set_id_token(&sign_id_token(
+                &claims(&issuer.url, &login_state.nonce),
+                TEST_KID,
+            ))
+            .await;
+
+        let claims = client
+            .verify_login(
+                &provider,
+                "code-1",
+                "http://front.local/auth/callback/sso",
+                &login_state.nonce,
+            )
+            .await
+            .unwrap();
+        assert_eq!(claims.sub, "user-1");
+        assert_eq!(claims.verified_email(), Some("sso@test.local"));
+
+        let token_requests = issuer.token_requests().await;
+        assert_eq!(token_requests.len(), 1);
+        assert!(token_requests[0].contains("code=code-1"));
+        assert!(token_requests[0].contains("client_secret=client-secret"));
+
+        // a token for another login
+        assert!(client
+            .verify_login(
+                &provider,
+                "code-2",
+                "http://front.local/auth/callback/sso",
+                "other-nonce",
+            )
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reject_invalid_id_tokens() {
+        let issuer = MockOidcIssuer::start().await;
+        let client = OidcClient::new();
+        let discovery = client.discover(&issuer.url).await.unwrap();
+        let jwks = client.get_jwks(&discovery, None).await.unwrap();
+        let verify = |claims: serde_json::Value, kid: &str| {
+            return verify_id_token(
+                &sign_id_token(&claims, kid),
+                &jwks,
+                &issuer.url,
+                "tasks",
+                "nonce",
+            );
+        };
+
+        verify(claims(&issuer.url, "nonce"), TEST_KID).unwrap();
+
+        let mut other_audience = claims(&issuer.url, "nonce");
+        other_audience["aud"] = json!("other-client");
+        assert!(verify(other_audience, TEST_KID).is_err());
+
+        let mut expired = claims(&issuer.url, "nonce");
+        expired["exp"] = json!(chrono::Utc::now().timestamp() - 600);
+        assert!(verify(expired, TEST_KID).is_err());
+
+        assert!(verify(claims("https://evil.local", "nonce"), TEST_KID).is_err());
+        assert!(verify(claims(&issuer.url, "nonce"), "unknown-key").is_err());
+
+        let hmac_signed = jsonwebtoken::encode(
+            &jsonwebtoken::Header {
+                kid: Some(TEST_KID.to_owned()),
+                ..jsonwebtoken::Header::new(Algorithm::HS256)
+            },
+            &claims(&issuer.url, "nonce"),
+            &jsonwebtoken::EncodingKey::from_secret(b"client-secret"),
+        )
+        .unwrap();
+        assert!(verify_id_token(&hmac_signed, &jwks, &issuer.url, "tasks", "nonce").is_err());
+    }
+}
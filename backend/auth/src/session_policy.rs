@@ -0,0 +1,53 @@
+use chrono::{DateTime, Duration, Utc};
+use config::CONFIG;
+use once_cell::sync::Lazy;
+
+/// How long a session lasts, the earlier of the two limits wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionPolicy {
+    /// Counted from the login, using the session doesn't extend it.
+    pub max_age: Duration,
+    /// Counted from the last request made with the session.
+    pub idle_timeout: Duration,
+}
+
+impl SessionPolicy {
+    /// When a session created at `created_at` and last used at `now` expires.
+    pub fn expires_at(&self, created_at: &DateTime<Utc>, now: &DateTime<Utc>) -> DateTime<Utc> {
+        return (*now + self.idle_timeout).min(*created_at + self.max_age);
+    }
+}
+
+pub static SESSION_POLICY: Lazy<SessionPolicy> = Lazy::new(|| SessionPolicy {
+    max_age: Duration::days(CONFIG.session_max_age_days),
+    idle_timeout: Duration::days(CONFIG.session_idle_timeout_days),
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_expires_at() {
+        let policy = SessionPolicy {
+            max_age: Duration::days(90),
+            idle_timeout: Duration::days(30),
+        };
+        let created_at = Utc.with_ymd_and_hms(2026, 1, 1, 12, 0, 0).unwrap();
+
+        assert_eq!(
+            policy.expires_at(&created_at, &created_at),
+            created_at + Duration::days(30)
+        );
+        assert_eq!(
+            policy.expires_at(&created_at, &(created_at + Duration::days(10))),
+            created_at + Duration::days(40)
+        );
+        // used every day, still ends at the max age
+        assert_eq!(
+            policy.expires_at(&created_at, &(created_at + Duration::days(80))),
+            created_at + Duration::days(90)
+        );
+    }
+}
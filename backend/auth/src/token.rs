@@ -6,12 +6,12 @@ pub struct Token {
     pub session_id: String,
 }
 
-static ID_SPLITTER: &str = ".";
-static SIGNATURE_SPLITTER: &str = ":";
+pub(crate) static ID_SPLITTER: &str = ".";
+pub(crate) static SIGNATURE_SPLITTER: &str = ":";
 
 type HmacSha256 = Hmac<Sha256>;
 
-fn create_signature(secret: &str, data_to_sign: &str) -> String {
+pub(crate) fn create_signature(secret: &str, data_to_sign: &str) -> String {
     let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("error creating hmac");
 
     mac.update(data_to_sign.as_bytes());
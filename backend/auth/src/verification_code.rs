@@ -0,0 +1,41 @@
+use crate::token::create_signature;
+
+/// How many digits a verification code has.
+pub const CODE_LENGTH: usize = 6;
+
+/// A random code the user copies back to prove they own a notification channel.
+pub fn create_verification_code() -> String {
+    let random = u128::from_le_bytes(*uuid::Uuid::new_v4().as_bytes());
+
+    return format!(
+        "{:0width$}",
+        random % 10u128.pow(CODE_LENGTH as u32),
+        width = CODE_LENGTH
+    );
+}
+
+/// What gets stored instead of the code, a leaked table doesn't give the codes away.
+pub fn hash_verification_code(secret: &str, code: &str) -> String {
+    return create_signature(secret, &format!("verification-code.{}", code.trim()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verification_code() {
+        let code = create_verification_code();
+
+        assert_eq!(code.len(), CODE_LENGTH);
+        assert!(code.chars().all(|c| c.is_ascii_digit()));
+        assert_eq!(
+            hash_verification_code("secret", &code),
+            hash_verification_code("secret", &format!(" {code}\n"))
+        );
+        assert_ne!(
+            hash_verification_code("secret", &code),
+            hash_verification_code("other-secret", &code)
+        );
+    }
+}
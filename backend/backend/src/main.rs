@@ -1,4 +1,6 @@
+use anyhow::Context;
 use api::start_api;
+use config::CONFIG;
 use notifications::start_notification_service;
 use tracing_subscriber::{
     prelude::__tracing_subscriber_SubscriberExt, util::SubscriberInitExt, EnvFilter,
@@ -13,5 +15,108 @@ pub async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    tokio::join!(start_api(), start_notification_service());
+    match std::env::args().nth(1).as_deref() {
+        None => {
+            if let Err(e) = validate_vapid_keys() {
+                eprintln!("{e:#}, generate a new pair with `backend vapid-keys generate`");
+                std::process::exit(1);
+            }
+
+            if let Err(e) = CONFIG.validate_session_days() {
+                eprintln!("{e:#}");
+                std::process::exit(1);
+            }
+
+            tokio::join!(start_api(), start_notification_service());
+        }
+        Some("backfill-rollups") => backfill_rollups().await,
+        Some("stale-subs") => stale_subs().await,
+        Some("vapid-keys") => match std::env::args().nth(2).as_deref() {
+            Some("generate") => generate_vapid_keys(),
+            Some("rotate") => rotate_vapid_keys().await,
+            _ => {
+                eprintln!("usage: vapid-keys generate|rotate");
+                std::process::exit(1);
+            }
+        },
+        Some(command) => {
+            eprintln!(
+                "unknown command '{command}', available commands: backfill-rollups, stale-subs, vapid-keys"
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+fn validate_vapid_keys() -> Result<(), anyhow::Error> {
+    notifications::vapid::validate(&CONFIG.vapid_public_key, &CONFIG.vapid_private_key)?;
+
+    if let Some(previous_private_key) = &CONFIG.vapid_previous_private_key {
+        notifications::vapid::public_key_of(previous_private_key)
+            .context("invalid VAPID_PREVIOUS_PRIVATE_KEY")?;
+    }
+
+    return Ok(());
+}
+
+fn generate_vapid_keys() {
+    let keys = notifications::vapid::generate();
+
+    println!("VAPID_PUBLIC_KEY={}", keys.public_key);
+    println!("VAPID_PRIVATE_KEY={}", keys.private_key);
+}
+
+/// Run once the new keys are deployed, with the old private key in
+/// `VAPID_PREVIOUS_PRIVATE_KEY` so the old subscriptions keep working meanwhile.
+async fn rotate_vapid_keys() {
+    validate_vapid_keys().expect("invalid vapid keys");
+    let db = db::get_db().await;
+
+    let flagged = db::notification_subs::request_resubscription(
+        &db,
+        &CONFIG.vapid_public_key,
+        &chrono::Utc::now(),
+    )
+    .await
+    .expect("error requesting resubscription");
+
+    println!(
+        "{flagged} notification subs flagged, browsers resubscribe with the new key when the app is opened"
+    );
+}
+
+async fn backfill_rollups() {
+    let db = db::get_db().await;
+
+    let rows = db::rollups::backfill(&db)
+        .await
+        .expect("error backfilling rollups");
+
+    println!("backfilled {rows} rollup rows");
+}
+
+async fn stale_subs() {
+    let db = db::get_db().await;
+    let cutoff = chrono::Utc::now() - *notifications::STALE_SUB_AGE;
+
+    let subs = db::notification_subs::get_stale(&db, &cutoff)
+        .await
+        .expect("error getting stale notification subs");
+
+    for sub in &subs {
+        let state = match sub.expired_at {
+            Some(expired_at) => format!("expired at {expired_at}"),
+            None => match sub.last_success_at {
+                Some(last_success_at) => format!("last success at {last_success_at}"),
+                None => format!("never succeeded, created at {}", sub.created_at),
+            },
+        };
+
+        println!("{}\t{}\t{}\t{}", sub.user_id, sub.id, state, sub.endpoint);
+    }
+
+    println!(
+        "{} stale notification subs, expired ones and the ones with failed deliveries are pruned by the notification service",
+        subs.len()
+    );
 }
@@ -0,0 +1,25 @@
+CREATE TABLE task_daily_rollups (
+    user_id VARCHAR(26) NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    tag_id VARCHAR(26) NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    seconds BIGINT NOT NULL,
+    sessions INTEGER NOT NULL,
+    PRIMARY KEY (user_id, tag_id, day)
+);
+CREATE INDEX idx_task_daily_rollups_user_id_day ON task_daily_rollups(user_id, day);
+
+CREATE INDEX idx_tasks_user_id_start_at ON tasks(user_id, start_at);
+
+INSERT INTO task_daily_rollups (user_id, tag_id, day, seconds, sessions)
+SELECT
+    user_id,
+    tag_id,
+    CAST(start_at AT TIME ZONE 'UTC' AS DATE) AS day,
+    SUM(seconds),
+    COUNT(*)
+FROM
+    tasks
+GROUP BY
+    user_id,
+    tag_id,
+    day;
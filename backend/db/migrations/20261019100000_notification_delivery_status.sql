@@ -0,0 +1,33 @@
+ALTER TABLE notifications
+ADD COLUMN status VARCHAR(16) NOT NULL DEFAULT 'pending';
+
+ALTER TABLE notifications
+ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
+
+ALTER TABLE notifications
+ADD COLUMN next_attempt_at TIMESTAMPTZ;
+
+UPDATE notifications
+SET next_attempt_at = send_at;
+
+ALTER TABLE notifications
+ALTER COLUMN next_attempt_at SET NOT NULL;
+
+ALTER TABLE notifications
+ADD COLUMN last_error TEXT;
+
+CREATE INDEX idx_notifications_status_next_attempt_at ON notifications(status, next_attempt_at);
+CREATE INDEX idx_notifications_task_id ON notifications(task_id);
+
+CREATE TABLE notification_deliveries (
+    id VARCHAR(26) PRIMARY KEY,
+    notification_id VARCHAR(26) NOT NULL REFERENCES notifications(id) ON DELETE CASCADE,
+    notification_sub_id VARCHAR(26) NOT NULL REFERENCES notification_subs(id) ON DELETE CASCADE,
+    status VARCHAR(16) NOT NULL,
+    attempts INTEGER NOT NULL,
+    last_error TEXT,
+    last_attempt_at TIMESTAMPTZ NOT NULL,
+    delivered_at TIMESTAMPTZ,
+    UNIQUE (notification_id, notification_sub_id)
+);
+CREATE INDEX idx_notification_deliveries_notification_sub_id ON notification_deliveries(notification_sub_id);
@@ -0,0 +1,8 @@
+ALTER TABLE notification_subs
+ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+ALTER TABLE notification_subs
+ADD COLUMN last_success_at TIMESTAMPTZ;
+
+ALTER TABLE notification_subs
+ADD COLUMN expired_at TIMESTAMPTZ;
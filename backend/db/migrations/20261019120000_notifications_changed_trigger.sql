@@ -0,0 +1,12 @@
+-- wakes the notification scheduler when a notification is added, moved or removed,
+-- delivery status updates don't touch send_at so they don't wake it
+CREATE FUNCTION notify_notifications_changed() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('notifications_changed', '');
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER notifications_changed
+AFTER INSERT OR DELETE OR UPDATE OF send_at ON notifications
+FOR EACH STATEMENT EXECUTE FUNCTION notify_notifications_changed();
@@ -0,0 +1,5 @@
+ALTER TABLE notifications
+ADD COLUMN claimed_by VARCHAR(26);
+
+ALTER TABLE notifications
+ADD COLUMN claimed_until TIMESTAMPTZ;
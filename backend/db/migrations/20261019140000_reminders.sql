@@ -0,0 +1,11 @@
+CREATE TABLE reminders (
+    id VARCHAR(26) PRIMARY KEY,
+    user_id VARCHAR(26) NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    kind VARCHAR(16) NOT NULL,
+    value INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    UNIQUE (user_id, kind, value)
+);
+
+ALTER TABLE notifications
+ADD COLUMN kind VARCHAR(16) NOT NULL DEFAULT 'finished';
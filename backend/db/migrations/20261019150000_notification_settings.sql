@@ -0,0 +1,7 @@
+CREATE TABLE notification_settings (
+    user_id VARCHAR(26) PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    timezone VARCHAR(64) NOT NULL DEFAULT 'UTC',
+    quiet_hours_start TIME,
+    quiet_hours_end TIME,
+    dnd_until TIMESTAMPTZ
+);
@@ -0,0 +1,14 @@
+-- the bitfield was never read, preferences live in their own table now
+ALTER TABLE users
+DROP COLUMN preferences;
+
+CREATE TABLE notification_preferences (
+    user_id VARCHAR(26) NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    event VARCHAR(32) NOT NULL,
+    enabled BOOLEAN NOT NULL,
+    -- notification_subs ids, NULL means every device
+    sub_ids TEXT[],
+    sound BOOLEAN NOT NULL,
+    urgency VARCHAR(16) NOT NULL,
+    PRIMARY KEY (user_id, event)
+);
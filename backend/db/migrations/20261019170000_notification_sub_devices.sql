@@ -0,0 +1,3 @@
+ALTER TABLE notification_subs
+ADD COLUMN name VARCHAR(100),
+ADD COLUMN user_agent VARCHAR(500);
@@ -0,0 +1,16 @@
+ALTER TABLE notifications
+ADD COLUMN link VARCHAR(255);
+
+-- what was sent to the user, kept after the task and its notifications are gone
+CREATE TABLE notification_inbox (
+    id VARCHAR(26) PRIMARY KEY,
+    user_id VARCHAR(26) NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    notification_id VARCHAR(26) UNIQUE REFERENCES notifications(id) ON DELETE SET NULL,
+    kind VARCHAR(16) NOT NULL,
+    title VARCHAR(255) NOT NULL,
+    message VARCHAR(255) NOT NULL,
+    link VARCHAR(255),
+    created_at TIMESTAMPTZ NOT NULL,
+    read_at TIMESTAMPTZ
+);
+CREATE INDEX idx_notification_inbox_user_id ON notification_inbox(user_id, id);
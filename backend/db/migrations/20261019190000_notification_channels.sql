@@ -0,0 +1,26 @@
+-- email addresses, webhooks and ntfy topics notifications also go out to
+CREATE TABLE notification_channels (
+    id VARCHAR(26) PRIMARY KEY,
+    user_id VARCHAR(26) NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    kind VARCHAR(16) NOT NULL,
+    target VARCHAR(2000) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    verified_at TIMESTAMPTZ,
+    verification_code_hash VARCHAR(64),
+    verification_expires_at TIMESTAMPTZ,
+    verification_attempts INTEGER NOT NULL DEFAULT 0,
+    UNIQUE (user_id, kind, target)
+);
+
+CREATE TABLE notification_channel_deliveries (
+    id VARCHAR(26) PRIMARY KEY,
+    notification_id VARCHAR(26) NOT NULL REFERENCES notifications(id) ON DELETE CASCADE,
+    notification_channel_id VARCHAR(26) NOT NULL REFERENCES notification_channels(id) ON DELETE CASCADE,
+    status VARCHAR(16) NOT NULL,
+    attempts INTEGER NOT NULL,
+    last_error TEXT,
+    last_attempt_at TIMESTAMPTZ NOT NULL,
+    delivered_at TIMESTAMPTZ,
+    UNIQUE (notification_id, notification_channel_id)
+);
+CREATE INDEX idx_notification_channel_deliveries_channel_id ON notification_channel_deliveries(notification_channel_id);
@@ -0,0 +1,7 @@
+-- NULL leaves the urgency to the kind of notification
+ALTER TABLE notification_preferences
+ALTER COLUMN urgency DROP NOT NULL;
+
+UPDATE notification_preferences
+SET urgency = NULL
+WHERE urgency = 'normal';
@@ -0,0 +1,12 @@
+ALTER TABLE notification_settings
+ADD COLUMN locale VARCHAR(16) NOT NULL DEFAULT 'en';
+
+-- text is rendered from templates when the notification is sent,
+-- rows only keep text that was written before that
+ALTER TABLE notifications
+ALTER COLUMN title DROP NOT NULL,
+ALTER COLUMN message DROP NOT NULL;
+
+ALTER TABLE notifications
+ADD COLUMN reminder_kind VARCHAR(16),
+ADD COLUMN reminder_value INTEGER;
@@ -0,0 +1,8 @@
+-- nudges and summaries aren't about a task
+ALTER TABLE notifications ALTER COLUMN task_id DROP NOT NULL;
+
+ALTER TABLE notification_settings
+    ADD COLUMN nudge_at TIME,
+    ADD COLUMN summary_at TIME,
+    ADD COLUMN nudged_on DATE,
+    ADD COLUMN summarized_on DATE;
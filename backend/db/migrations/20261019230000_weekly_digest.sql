@@ -0,0 +1,5 @@
+-- weekday 0 is monday, the digest is sent when both are set
+ALTER TABLE notification_settings
+    ADD COLUMN digest_weekday SMALLINT CHECK (digest_weekday BETWEEN 0 AND 6),
+    ADD COLUMN digest_at TIME,
+    ADD COLUMN digested_on DATE;
@@ -0,0 +1,5 @@
+-- the VAPID public key the browser subscribed with, NULL for subscriptions made
+-- before keys were recorded
+ALTER TABLE notification_subs
+    ADD COLUMN vapid_public_key VARCHAR(128),
+    ADD COLUMN resubscribe_requested_at TIMESTAMPTZ;
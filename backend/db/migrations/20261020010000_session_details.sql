@@ -0,0 +1,11 @@
+-- sessions from before this have no known creation time, their expiry is the
+-- closest thing
+ALTER TABLE sessions
+    ADD COLUMN created_at TIMESTAMPTZ,
+    ADD COLUMN last_seen_at TIMESTAMPTZ,
+    ADD COLUMN user_agent VARCHAR(500),
+    ADD COLUMN ip VARCHAR(45);
+
+UPDATE sessions SET created_at = expires_at - INTERVAL '30 days';
+
+ALTER TABLE sessions ALTER COLUMN created_at SET NOT NULL;
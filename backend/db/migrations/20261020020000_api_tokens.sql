@@ -0,0 +1,12 @@
+CREATE TABLE api_tokens (
+    id VARCHAR(26) PRIMARY KEY,
+    user_id VARCHAR(26) NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(100) NOT NULL,
+    -- keyed hash of the token, the token itself is only shown once
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    scopes VARCHAR(32)[] NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ,
+    last_used_at TIMESTAMPTZ
+);
+CREATE INDEX idx_api_tokens_user_id ON api_tokens(user_id);
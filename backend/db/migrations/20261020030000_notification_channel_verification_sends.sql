@@ -0,0 +1,10 @@
+-- limits how often a verification code is sent to a channel
+ALTER TABLE notification_channels
+ADD COLUMN verification_sends INTEGER NOT NULL DEFAULT 0;
+
+ALTER TABLE notification_channels
+ADD COLUMN verification_sent_at TIMESTAMPTZ;
+
+UPDATE notification_channels
+SET verification_sends = 1, verification_sent_at = created_at
+WHERE verified_at IS NULL;
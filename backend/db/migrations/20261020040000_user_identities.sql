@@ -0,0 +1,12 @@
+-- logins at OpenID Connect providers, a user is found by the provider's
+-- subject instead of by the email it sends
+CREATE TABLE user_identities (
+    issuer VARCHAR(255) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    user_id VARCHAR(26) NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (issuer, subject)
+);
+-- a user has one login per provider, so another account there with the
+-- same email can't be linked to them later
+CREATE UNIQUE INDEX idx_user_identities_user_id_issuer ON user_identities(user_id, issuer);
@@ -0,0 +1,8 @@
+-- rendered texts contain tag labels, which can be 255 characters on their own
+ALTER TABLE notification_inbox
+ALTER COLUMN title TYPE TEXT,
+ALTER COLUMN message TYPE TEXT;
+
+ALTER TABLE notifications
+ALTER COLUMN title TYPE TEXT,
+ALTER COLUMN message TYPE TEXT;
@@ -0,0 +1,6 @@
+-- notification action tokens that were used, kept until they expire so
+-- the same button can't be replayed
+CREATE TABLE used_action_tokens (
+    id VARCHAR(32) PRIMARY KEY,
+    expires_at TIMESTAMPTZ NOT NULL
+);
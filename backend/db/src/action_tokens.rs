@@ -0,0 +1,66 @@
+use crate::Db;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+
+/// Records that the action token `id` was used, false if it already was.
+pub async fn use_once(
+    db: &Db,
+    id: &str,
+    expires_at: &DateTime<Utc>,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+            INSERT INTO used_action_tokens (id, expires_at)
+            VALUES ($1, $2)
+            ON CONFLICT (id) DO NOTHING
+        "#,
+        id,
+        expires_at
+    )
+    .execute(db)
+    .await
+    .context("error recording used action token")?;
+
+    return Ok(result.rows_affected() == 1);
+}
+
+/// Expired tokens are rejected anyway, they don't need to be remembered.
+pub async fn delete_expired(db: &Db, now: &DateTime<Utc>) -> Result<u64, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+            DELETE FROM used_action_tokens
+            WHERE expires_at < $1
+        "#,
+        now
+    )
+    .execute(db)
+    .await
+    .context("error deleting used action tokens")?;
+
+    return Ok(result.rows_affected());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_tokens_are_used_once(db: Db) {
+        let now = Utc::now();
+        let expires_at = now + chrono::Duration::minutes(30);
+
+        assert!(use_once(&db, "token", &expires_at).await.unwrap());
+        // a replayed token
+        assert!(!use_once(&db, "token", &expires_at).await.unwrap());
+        assert!(use_once(&db, "other-token", &now).await.unwrap());
+
+        assert_eq!(
+            delete_expired(&db, &(now + chrono::Duration::minutes(1)))
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(!use_once(&db, "token", &expires_at).await.unwrap());
+    }
+}
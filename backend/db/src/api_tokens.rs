@@ -0,0 +1,232 @@
+use crate::{create_id, Db};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use std::str::FromStr;
+
+/// What a personal API token may be used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ApiTokenScope {
+    /// Reading anything.
+    #[serde(rename = "read-only")]
+    ReadOnly,
+    /// Starting, stopping, adding and deleting tasks.
+    #[serde(rename = "tasks:write")]
+    TasksWrite,
+    /// Reading stats.
+    #[serde(rename = "stats:read")]
+    StatsRead,
+}
+
+impl FromStr for ApiTokenScope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read-only" => Ok(ApiTokenScope::ReadOnly),
+            "tasks:write" => Ok(ApiTokenScope::TasksWrite),
+            "stats:read" => Ok(ApiTokenScope::StatsRead),
+            _ => Err(anyhow::anyhow!("invalid api token scope")),
+        }
+    }
+}
+
+impl AsRef<str> for ApiTokenScope {
+    fn as_ref(&self) -> &str {
+        match self {
+            ApiTokenScope::ReadOnly => "read-only",
+            ApiTokenScope::TasksWrite => "tasks:write",
+            ApiTokenScope::StatsRead => "stats:read",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ApiToken {
+    pub id: String,
+    pub user_id: String,
+    pub name: String,
+    pub scopes: Vec<ApiTokenScope>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+struct ApiTokenRow {
+    id: String,
+    user_id: String,
+    name: String,
+    scopes: Vec<String>,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+impl From<ApiTokenRow> for ApiToken {
+    fn from(row: ApiTokenRow) -> Self {
+        return Self {
+            id: row.id,
+            user_id: row.user_id,
+            name: row.name,
+            // a scope that was since removed grants nothing
+            scopes: row
+                .scopes
+                .iter()
+                .filter_map(|scope| scope.parse().ok())
+                .collect(),
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+            last_used_at: row.last_used_at,
+        };
+    }
+}
+
+pub async fn get_by_user_id(db: &Db, user_id: &str) -> Result<Vec<ApiToken>, anyhow::Error> {
+    let rows = sqlx::query_as!(
+        ApiTokenRow,
+        r#"
+            SELECT id, user_id, name, scopes, created_at, expires_at, last_used_at
+            FROM api_tokens
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+    .context("error fetching api tokens")?;
+
+    return Ok(rows.into_iter().map(ApiToken::from).collect());
+}
+
+pub async fn insert(
+    db: &Db,
+    user_id: &str,
+    name: &str,
+    token_hash: &str,
+    scopes: &[ApiTokenScope],
+    expires_at: Option<&DateTime<Utc>>,
+) -> Result<ApiToken, anyhow::Error> {
+    let scopes = scopes
+        .iter()
+        .map(|scope| scope.as_ref().to_owned())
+        .collect::<Vec<String>>();
+
+    let row = sqlx::query_as!(
+        ApiTokenRow,
+        r#"
+            INSERT INTO api_tokens (id, user_id, name, token_hash, scopes, created_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id, user_id, name, scopes, created_at, expires_at, last_used_at
+        "#,
+        create_id(),
+        user_id,
+        name,
+        token_hash,
+        &scopes,
+        Utc::now(),
+        expires_at
+    )
+    .fetch_one(db)
+    .await
+    .context("error inserting api token")?;
+
+    return Ok(ApiToken::from(row));
+}
+
+/// The unexpired token with `token_hash`, recording that it was used at `now`.
+pub async fn use_token(
+    db: &Db,
+    token_hash: &str,
+    now: &DateTime<Utc>,
+) -> Result<Option<ApiToken>, anyhow::Error> {
+    let row = sqlx::query_as!(
+        ApiTokenRow,
+        r#"
+            UPDATE api_tokens
+            SET last_used_at = $2
+            WHERE token_hash = $1 AND (expires_at IS NULL OR expires_at > $2)
+            RETURNING id, user_id, name, scopes, created_at, expires_at, last_used_at
+        "#,
+        token_hash,
+        now
+    )
+    .fetch_optional(db)
+    .await
+    .context("error using api token")?;
+
+    return Ok(row.map(ApiToken::from));
+}
+
+pub async fn delete(db: &Db, token_id: &str, user_id: &str) -> Result<bool, anyhow::Error> {
+    let res = sqlx::query!(
+        r#"
+            DELETE FROM api_tokens
+            WHERE id = $1 AND user_id = $2
+        "#,
+        token_id,
+        user_id
+    )
+    .execute(db)
+    .await
+    .context("error deleting api token")?;
+
+    return Ok(res.rows_affected() == 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, SubsecRound};
+
+    #[sqlx::test]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_api_tokens(db: Db) {
+        let user = crate::users::create(&db, "tokens@test.local")
+            .await
+            .unwrap();
+        let other = crate::users::create(&db, "other@test.local").await.unwrap();
+        let now = Utc::now().trunc_subsecs(6);
+
+        let token = insert(
+            &db,
+            &user.id,
+            "timer script",
+            "hash",
+            &[ApiTokenScope::ReadOnly, ApiTokenScope::TasksWrite],
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            token.scopes,
+            vec![ApiTokenScope::ReadOnly, ApiTokenScope::TasksWrite]
+        );
+        assert!(token.last_used_at.is_none());
+
+        let expired = insert(
+            &db,
+            &user.id,
+            "expired",
+            "expired-hash",
+            &[ApiTokenScope::StatsRead],
+            Some(&(now - Duration::minutes(1))),
+        )
+        .await
+        .unwrap();
+
+        let used = use_token(&db, "hash", &now).await.unwrap().unwrap();
+        assert_eq!(used.id, token.id);
+        assert_eq!(used.last_used_at, Some(now));
+        assert!(use_token(&db, "expired-hash", &now)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(use_token(&db, "unknown", &now).await.unwrap().is_none());
+
+        assert_eq!(get_by_user_id(&db, &user.id).await.unwrap().len(), 2);
+        assert!(!delete(&db, &token.id, &other.id).await.unwrap());
+        assert!(delete(&db, &token.id, &user.id).await.unwrap());
+        assert!(use_token(&db, "hash", &now).await.unwrap().is_none());
+        assert_eq!(get_by_user_id(&db, &user.id).await.unwrap(), vec![expired]);
+    }
+}
@@ -0,0 +1,190 @@
+use crate::{
+    create_id,
+    notifications::{Notification, NotificationKind},
+    Db,
+};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+
+const ITEMS_PER_PAGE: i64 = 30;
+
+/// A notification as the user sees it in their history.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct InboxItem {
+    pub id: String,
+    #[serde(skip)]
+    pub user_id: String,
+    #[serde(skip)]
+    pub notification_id: Option<String>,
+    pub kind: NotificationKind,
+    pub title: String,
+    pub message: String,
+    pub link: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub read_at: Option<DateTime<Utc>>,
+}
+
+/// Adds `notification` to the history of its user, once, with the text it was sent with.
+pub async fn insert_for_notification(
+    db: &Db,
+    notification: &Notification,
+    title: &str,
+    message: &str,
+    created_at: &DateTime<Utc>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO notification_inbox (id, user_id, notification_id, kind, title, message, link, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (notification_id) DO NOTHING
+        "#,
+        create_id(),
+        notification.user_id,
+        notification.id,
+        notification.kind.as_ref(),
+        title,
+        message,
+        notification.link,
+        created_at,
+    )
+    .execute(db)
+    .await
+    .context("error inserting inbox item")?;
+
+    return Ok(());
+}
+
+/// A page of the history, newest first, starting after `last_id`.
+pub async fn get_many(
+    db: &Db,
+    user_id: &str,
+    last_id: Option<&str>,
+    unread_only: bool,
+) -> Result<Vec<InboxItem>, anyhow::Error> {
+    let items = sqlx::query_as!(
+        InboxItem,
+        r#"
+            SELECT
+                id,
+                user_id,
+                notification_id,
+                kind AS "kind: NotificationKind",
+                title,
+                message,
+                link,
+                created_at,
+                read_at
+            FROM notification_inbox
+            WHERE user_id = $1
+            AND ($2::VARCHAR IS NULL OR id < $2)
+            AND (NOT $3 OR read_at IS NULL)
+            ORDER BY id DESC
+            LIMIT $4
+        "#,
+        user_id,
+        last_id,
+        unread_only,
+        ITEMS_PER_PAGE,
+    )
+    .fetch_all(db)
+    .await
+    .context("error fetching inbox items")?;
+
+    return Ok(items);
+}
+
+pub async fn count_unread(db: &Db, user_id: &str) -> Result<i64, anyhow::Error> {
+    let count = sqlx::query_scalar!(
+        r#"
+            SELECT COUNT(*) AS "count!"
+            FROM notification_inbox
+            WHERE user_id = $1
+            AND read_at IS NULL
+        "#,
+        user_id
+    )
+    .fetch_one(db)
+    .await
+    .context("error counting unread inbox items")?;
+
+    return Ok(count);
+}
+
+/// Marks the items with `ids` read, or every item when `ids` is `None`.
+/// Items that were already read keep their read time. Returns how many were marked.
+pub async fn mark_read(
+    db: &Db,
+    user_id: &str,
+    ids: Option<&[String]>,
+    read_at: &DateTime<Utc>,
+) -> Result<u64, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+            UPDATE notification_inbox
+            SET read_at = $3
+            WHERE user_id = $1
+            AND ($2::VARCHAR[] IS NULL OR id = ANY($2))
+            AND read_at IS NULL
+        "#,
+        user_id,
+        ids,
+        read_at
+    )
+    .execute(db)
+    .await
+    .context("error marking inbox items read")?;
+
+    return Ok(result.rows_affected());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::insert_notification;
+
+    #[sqlx::test]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_inbox(db: Db) {
+        let notification = insert_notification(&db, "inbox@test.local").await;
+        let user_id = notification.user_id.to_owned();
+        let now = Utc::now();
+
+        // retried deliveries don't show up twice
+        insert_for_notification(&db, &notification, "title", "message", &now)
+            .await
+            .unwrap();
+        insert_for_notification(&db, &notification, "title", "message", &now)
+            .await
+            .unwrap();
+
+        let items = get_many(&db, &user_id, None, true).await.unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(count_unread(&db, &user_id).await.unwrap(), 1);
+
+        // the history outlives the task
+        crate::tasks::delete(&db, &user_id, notification.task_id.as_deref().unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            mark_read(&db, &user_id, Some(&[items[0].id.to_owned()]), &now)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(mark_read(&db, &user_id, None, &now).await.unwrap(), 0);
+
+        assert!(get_many(&db, &user_id, None, true)
+            .await
+            .unwrap()
+            .is_empty());
+        let items = get_many(&db, &user_id, None, false).await.unwrap();
+        assert_eq!(items.len(), 1);
+        assert!(items[0].read_at.is_some());
+        assert!(items[0].notification_id.is_none());
+        assert!(get_many(&db, &user_id, Some(&items[0].id), false)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
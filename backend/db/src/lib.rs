@@ -2,11 +2,23 @@ use config::CONFIG;
 use sqlx::PgPool;
 use ulid::Ulid;
 
+pub mod action_tokens;
+pub mod api_tokens;
+pub mod inbox;
+pub mod notification_channels;
+pub mod notification_deliveries;
+pub mod notification_settings;
 pub mod notification_subs;
 pub mod notifications;
+pub mod preferences;
+pub mod reminders;
+pub mod rollups;
 pub mod sessions;
 pub mod tags;
 pub mod tasks;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+pub mod user_identities;
 pub mod users;
 
 pub type Db = PgPool;
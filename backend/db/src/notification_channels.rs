@@ -0,0 +1,491 @@
+use crate::{create_id, notification_deliveries::DeliveryStatus, Db};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use std::str::FromStr;
+
+/// Where a notification goes besides push subscriptions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "varchar", rename_all = "lowercase")]
+pub enum ChannelKind {
+    /// `target` is an email address.
+    Email,
+    /// `target` is a URL that receives the notification as JSON.
+    Webhook,
+    /// `target` is the URL of an ntfy topic.
+    Ntfy,
+}
+
+impl FromStr for ChannelKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "email" => Ok(ChannelKind::Email),
+            "webhook" => Ok(ChannelKind::Webhook),
+            "ntfy" => Ok(ChannelKind::Ntfy),
+            _ => Err(anyhow::anyhow!("invalid channel kind")),
+        }
+    }
+}
+
+impl AsRef<str> for ChannelKind {
+    fn as_ref(&self) -> &str {
+        match self {
+            ChannelKind::Email => "email",
+            ChannelKind::Webhook => "webhook",
+            ChannelKind::Ntfy => "ntfy",
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct NotificationChannel {
+    pub id: String,
+    #[serde(skip)]
+    pub user_id: String,
+    pub kind: ChannelKind,
+    pub target: String,
+    pub created_at: DateTime<Utc>,
+    /// Channels are only sent to once the user proved they own them.
+    pub verified_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    pub verification_code_hash: Option<String>,
+    #[serde(skip)]
+    pub verification_expires_at: Option<DateTime<Utc>>,
+    /// Codes entered, they aren't reset when a new code is sent.
+    #[serde(skip)]
+    pub verification_attempts: i32,
+    /// How many codes were sent, the first one when the channel was added.
+    #[serde(skip)]
+    pub verification_sends: i32,
+    #[serde(skip)]
+    pub verification_sent_at: Option<DateTime<Utc>>,
+}
+
+/// Adds an unverified channel, `None` if the user already has it.
+pub async fn insert(
+    db: &Db,
+    user_id: &str,
+    kind: &ChannelKind,
+    target: &str,
+    verification_code_hash: &str,
+    verification_expires_at: &DateTime<Utc>,
+) -> Result<Option<NotificationChannel>, anyhow::Error> {
+    let channel = sqlx::query_as!(
+        NotificationChannel,
+        r#"
+            INSERT INTO notification_channels (id, user_id, kind, target, created_at, verification_code_hash, verification_expires_at, verification_sends, verification_sent_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, 1, $5)
+            ON CONFLICT (user_id, kind, target) DO NOTHING
+            RETURNING
+                id,
+                user_id,
+                kind AS "kind: ChannelKind",
+                target,
+                created_at,
+                verified_at,
+                verification_code_hash,
+                verification_expires_at,
+                verification_attempts,
+                verification_sends,
+                verification_sent_at
+        "#,
+        create_id(),
+        user_id,
+        kind.as_ref(),
+        target,
+        Utc::now(),
+        verification_code_hash,
+        verification_expires_at,
+    )
+    .fetch_optional(db)
+    .await
+    .context("error inserting notification channel")?;
+
+    return Ok(channel);
+}
+
+pub async fn get_one(
+    db: &Db,
+    user_id: &str,
+    id: &str,
+) -> Result<Option<NotificationChannel>, anyhow::Error> {
+    let channel = sqlx::query_as!(
+        NotificationChannel,
+        r#"
+            SELECT
+                id,
+                user_id,
+                kind AS "kind: ChannelKind",
+                target,
+                created_at,
+                verified_at,
+                verification_code_hash,
+                verification_expires_at,
+                verification_attempts,
+                verification_sends,
+                verification_sent_at
+            FROM notification_channels
+            WHERE user_id = $1
+            AND id = $2
+        "#,
+        user_id,
+        id
+    )
+    .fetch_optional(db)
+    .await
+    .context("error getting notification channel")?;
+
+    return Ok(channel);
+}
+
+pub async fn get_by_user_id(
+    db: &Db,
+    user_id: &str,
+) -> Result<Vec<NotificationChannel>, anyhow::Error> {
+    let channels = sqlx::query_as!(
+        NotificationChannel,
+        r#"
+            SELECT
+                id,
+                user_id,
+                kind AS "kind: ChannelKind",
+                target,
+                created_at,
+                verified_at,
+                verification_code_hash,
+                verification_expires_at,
+                verification_attempts,
+                verification_sends,
+                verification_sent_at
+            FROM notification_channels
+            WHERE user_id = $1
+            ORDER BY created_at, id
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+    .context("error getting notification channels")?;
+
+    return Ok(channels);
+}
+
+/// The channels notifications can be sent to.
+pub async fn get_verified_by_user_ids(
+    db: &Db,
+    user_ids: &Vec<String>,
+) -> Result<Vec<NotificationChannel>, anyhow::Error> {
+    let channels = sqlx::query_as!(
+        NotificationChannel,
+        r#"
+            SELECT
+                id,
+                user_id,
+                kind AS "kind: ChannelKind",
+                target,
+                created_at,
+                verified_at,
+                verification_code_hash,
+                verification_expires_at,
+                verification_attempts,
+                verification_sends,
+                verification_sent_at
+            FROM notification_channels
+            WHERE user_id = ANY($1)
+            AND verified_at IS NOT NULL
+        "#,
+        user_ids
+    )
+    .fetch_all(db)
+    .await
+    .context("error getting notification channels")?;
+
+    return Ok(channels);
+}
+
+pub async fn delete(db: &Db, user_id: &str, id: &str) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+            DELETE FROM notification_channels
+            WHERE user_id = $1
+            AND id = $2
+        "#,
+        user_id,
+        id
+    )
+    .execute(db)
+    .await
+    .context("error deleting notification channel")?;
+
+    return Ok(result.rows_affected() == 1);
+}
+
+/// Replaces the verification code of a channel, unless `max_sends` codes were
+/// already sent or the last one was sent after `cooldown_start`. Entered wrong
+/// codes still count against the channel. Returns false if the code wasn't replaced.
+pub async fn resend_verification_code(
+    db: &Db,
+    id: &str,
+    verification_code_hash: &str,
+    verification_expires_at: &DateTime<Utc>,
+    sent_at: &DateTime<Utc>,
+    cooldown_start: &DateTime<Utc>,
+    max_sends: i32,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+            UPDATE notification_channels
+            SET verification_code_hash = $2, verification_expires_at = $3,
+                verification_sends = verification_sends + 1, verification_sent_at = $4
+            WHERE id = $1
+            AND verification_sends < $6
+            AND (verification_sent_at IS NULL OR verification_sent_at <= $5)
+        "#,
+        id,
+        verification_code_hash,
+        verification_expires_at,
+        sent_at,
+        cooldown_start,
+        max_sends
+    )
+    .execute(db)
+    .await
+    .context("error setting verification code")?;
+
+    return Ok(result.rows_affected() == 1);
+}
+
+/// The code a guess is checked against, see [`claim_verification_attempt`].
+pub struct VerificationAttempt {
+    pub verification_code_hash: Option<String>,
+    pub verification_expires_at: Option<DateTime<Utc>>,
+}
+
+/// Uses up one of the `max_attempts` guesses of an unverified channel, `None`
+/// if none are left. Counted before the code is checked, so parallel guesses
+/// can't go over the limit.
+pub async fn claim_verification_attempt(
+    db: &Db,
+    id: &str,
+    max_attempts: i32,
+) -> Result<Option<VerificationAttempt>, anyhow::Error> {
+    let attempt = sqlx::query_as!(
+        VerificationAttempt,
+        r#"
+            UPDATE notification_channels
+            SET verification_attempts = verification_attempts + 1
+            WHERE id = $1
+            AND verified_at IS NULL
+            AND verification_attempts < $2
+            RETURNING verification_code_hash, verification_expires_at
+        "#,
+        id,
+        max_attempts
+    )
+    .fetch_optional(db)
+    .await
+    .context("error claiming verification attempt")?;
+
+    return Ok(attempt);
+}
+
+pub async fn mark_verified(
+    db: &Db,
+    id: &str,
+    verified_at: &DateTime<Utc>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE notification_channels
+            SET verified_at = $2, verification_code_hash = NULL, verification_expires_at = NULL
+            WHERE id = $1
+        "#,
+        id,
+        verified_at
+    )
+    .execute(db)
+    .await
+    .context("error marking notification channel verified")?;
+
+    return Ok(());
+}
+
+/// Ids of the channels that already received `notification_id`.
+pub async fn get_delivered_channel_ids(
+    db: &Db,
+    notification_id: &str,
+) -> Result<Vec<String>, anyhow::Error> {
+    let channel_ids = sqlx::query_scalar!(
+        r#"
+            SELECT notification_channel_id
+            FROM notification_channel_deliveries
+            WHERE notification_id = $1
+            AND status = 'sent'
+        "#,
+        notification_id
+    )
+    .fetch_all(db)
+    .await
+    .context("error getting notification channel deliveries")?;
+
+    return Ok(channel_ids);
+}
+
+/// Records one attempt like [`crate::notification_deliveries::record_attempt`] does for subscriptions.
+pub async fn record_delivery_attempt(
+    db: &Db,
+    notification_id: &str,
+    notification_channel_id: &str,
+    status: &DeliveryStatus,
+    error: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    let now = Utc::now();
+    let delivered_at = match status {
+        DeliveryStatus::Sent => Some(now),
+        DeliveryStatus::Failed => None,
+    };
+
+    sqlx::query!(
+        r#"
+            INSERT INTO notification_channel_deliveries (id, notification_id, notification_channel_id, status, attempts, last_error, last_attempt_at, delivered_at)
+            VALUES ($1, $2, $3, $4, 1, $5, $6, $7)
+            ON CONFLICT (notification_id, notification_channel_id) DO UPDATE
+            SET status = $4,
+                attempts = notification_channel_deliveries.attempts + 1,
+                last_error = $5,
+                last_attempt_at = $6,
+                delivered_at = $7
+        "#,
+        create_id(),
+        notification_id,
+        notification_channel_id,
+        status.as_ref(),
+        error,
+        now,
+        delivered_at,
+    )
+    .execute(db)
+    .await
+    .context("error recording notification channel delivery")?;
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_channel_verification(db: Db) {
+        let user = crate::users::create(&db, "channels@test.local")
+            .await
+            .unwrap();
+        let expires_at = Utc::now() + chrono::Duration::minutes(15);
+
+        let channel = insert(
+            &db,
+            &user.id,
+            &ChannelKind::Webhook,
+            "https://hooks.test.local",
+            "hash",
+            &expires_at,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        // the same target can't be added twice
+        assert!(insert(
+            &db,
+            &user.id,
+            &ChannelKind::Webhook,
+            "https://hooks.test.local",
+            "hash",
+            &expires_at,
+        )
+        .await
+        .unwrap()
+        .is_none());
+
+        let user_ids = vec![user.id.to_owned()];
+        assert!(get_verified_by_user_ids(&db, &user_ids)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let attempt = claim_verification_attempt(&db, &channel.id, 2)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(attempt.verification_code_hash.as_deref(), Some("hash"));
+        let channel = get_one(&db, &user.id, &channel.id).await.unwrap().unwrap();
+        assert_eq!(channel.verification_attempts, 1);
+        assert_eq!(channel.verification_sends, 1);
+
+        // a new code needs the cooldown to pass, and doesn't give more guesses
+        let now = Utc::now();
+        let minute = chrono::Duration::minutes(1);
+        assert!(!resend_verification_code(
+            &db,
+            &channel.id,
+            "hash",
+            &expires_at,
+            &now,
+            &(now - minute),
+            2
+        )
+        .await
+        .unwrap());
+        assert!(
+            resend_verification_code(&db, &channel.id, "hash", &expires_at, &now, &now, 2)
+                .await
+                .unwrap()
+        );
+        let channel = get_one(&db, &user.id, &channel.id).await.unwrap().unwrap();
+        assert_eq!(channel.verification_attempts, 1);
+        assert_eq!(channel.verification_sends, 2);
+
+        // out of codes
+        assert!(!resend_verification_code(
+            &db,
+            &channel.id,
+            "hash",
+            &expires_at,
+            &(now + minute),
+            &(now + minute),
+            2
+        )
+        .await
+        .unwrap());
+
+        // parallel guesses share the same attempts
+        let guesses = (0..4)
+            .map(|_| {
+                let db = db.clone();
+                let id = channel.id.to_owned();
+
+                return tokio::spawn(async move {
+                    claim_verification_attempt(&db, &id, 3).await.unwrap()
+                });
+            })
+            .collect::<Vec<_>>();
+        let mut claimed = 0;
+        for guess in guesses {
+            if guess.await.unwrap().is_some() {
+                claimed += 1;
+            }
+        }
+        assert_eq!(claimed, 2);
+
+        mark_verified(&db, &channel.id, &Utc::now()).await.unwrap();
+        let verified = get_verified_by_user_ids(&db, &user_ids).await.unwrap();
+        assert_eq!(verified.len(), 1);
+        assert!(verified[0].verification_code_hash.is_none());
+
+        assert!(delete(&db, &user.id, &channel.id).await.unwrap());
+        assert!(get_by_user_id(&db, &user.id).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,115 @@
+use crate::{create_id, Db};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "varchar", rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Sent,
+    Failed,
+}
+
+impl FromStr for DeliveryStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sent" => Ok(DeliveryStatus::Sent),
+            "failed" => Ok(DeliveryStatus::Failed),
+            _ => Err(anyhow::anyhow!("invalid delivery status")),
+        }
+    }
+}
+
+impl AsRef<str> for DeliveryStatus {
+    fn as_ref(&self) -> &str {
+        match self {
+            DeliveryStatus::Sent => "sent",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+}
+
+/// The outcome of sending one notification to one subscription.
+#[derive(Debug, serde::Serialize)]
+pub struct NotificationDelivery {
+    pub id: String,
+    pub notification_id: String,
+    pub notification_sub_id: String,
+    pub status: DeliveryStatus,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub last_attempt_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+pub async fn get_by_notification_id(
+    db: &Db,
+    notification_id: &str,
+) -> Result<Vec<NotificationDelivery>, anyhow::Error> {
+    let deliveries = sqlx::query_as!(
+        NotificationDelivery,
+        r#"
+            SELECT
+                id,
+                notification_id,
+                notification_sub_id,
+                status AS "status: DeliveryStatus",
+                attempts,
+                last_error,
+                last_attempt_at,
+                delivered_at
+            FROM notification_deliveries
+            WHERE notification_id = $1
+        "#,
+        notification_id
+    )
+    .fetch_all(db)
+    .await
+    .context("error getting notification deliveries")?;
+
+    return Ok(deliveries);
+}
+
+/// Records one attempt, counting it on top of the earlier attempts
+/// for the same notification and subscription.
+pub async fn record_attempt(
+    db: &Db,
+    notification_id: &str,
+    notification_sub_id: &str,
+    status: &DeliveryStatus,
+    error: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    let now = Utc::now();
+    let delivered_at = match status {
+        DeliveryStatus::Sent => Some(now),
+        DeliveryStatus::Failed => None,
+    };
+
+    sqlx::query!(
+        r#"
+            INSERT INTO notification_deliveries (id, notification_id, notification_sub_id, status, attempts, last_error, last_attempt_at, delivered_at)
+            VALUES ($1, $2, $3, $4, 1, $5, $6, $7)
+            ON CONFLICT (notification_id, notification_sub_id) DO UPDATE
+            SET status = $4,
+                attempts = notification_deliveries.attempts + 1,
+                last_error = $5,
+                last_attempt_at = $6,
+                delivered_at = $7
+        "#,
+        create_id(),
+        notification_id,
+        notification_sub_id,
+        status.as_ref(),
+        error,
+        now,
+        delivered_at,
+    )
+    .execute(db)
+    .await
+    .context("error recording notification delivery")?;
+
+    return Ok(());
+}
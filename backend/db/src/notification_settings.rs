@@ -0,0 +1,410 @@
+use crate::Db;
+use anyhow::Context;
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+
+/// When a user doesn't want to be disturbed. Quiet hours repeat daily in
+/// `timezone` and may wrap past midnight, do not disturb is a one-off.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct NotificationSettings {
+    pub user_id: String,
+    pub timezone: String,
+    pub quiet_hours_start: Option<NaiveTime>,
+    pub quiet_hours_end: Option<NaiveTime>,
+    pub dnd_until: Option<DateTime<Utc>>,
+    /// Language of the notifications, like `en`.
+    pub locale: String,
+    /// Local time of the nudge sent when nothing was tracked yet that day, if wanted.
+    pub nudge_at: Option<NaiveTime>,
+    /// Local time of the summary of the day, if wanted.
+    pub summary_at: Option<NaiveTime>,
+    /// Local day the last nudge was sent for, so it goes out once a day.
+    #[serde(skip)]
+    pub nudged_on: Option<NaiveDate>,
+    #[serde(skip)]
+    pub summarized_on: Option<NaiveDate>,
+    /// Local weekday of the weekly digest email, 0 is monday.
+    pub digest_weekday: Option<i16>,
+    /// Local time of the weekly digest email, sent when this and `digest_weekday` are set.
+    pub digest_at: Option<NaiveTime>,
+    #[serde(skip)]
+    pub digested_on: Option<NaiveDate>,
+}
+
+impl NotificationSettings {
+    pub fn new(user_id: &str) -> Self {
+        return Self {
+            user_id: user_id.to_owned(),
+            timezone: "UTC".to_owned(),
+            quiet_hours_start: None,
+            quiet_hours_end: None,
+            dnd_until: None,
+            locale: "en".to_owned(),
+            nudge_at: None,
+            summary_at: None,
+            nudged_on: None,
+            summarized_on: None,
+            digest_weekday: None,
+            digest_at: None,
+            digested_on: None,
+        };
+    }
+}
+
+pub async fn get(db: &Db, user_id: &str) -> Result<NotificationSettings, anyhow::Error> {
+    let settings = sqlx::query_as!(
+        NotificationSettings,
+        r#"
+            SELECT * FROM notification_settings
+            WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(db)
+    .await
+    .context("error fetching notification settings")?;
+
+    return Ok(settings.unwrap_or_else(|| NotificationSettings::new(user_id)));
+}
+
+/// Settings of the users that have any, the others use the defaults.
+pub async fn get_by_user_ids(
+    db: &Db,
+    user_ids: &Vec<String>,
+) -> Result<Vec<NotificationSettings>, anyhow::Error> {
+    let settings = sqlx::query_as!(
+        NotificationSettings,
+        r#"
+            SELECT * FROM notification_settings
+            WHERE user_id = ANY($1)
+        "#,
+        user_ids
+    )
+    .fetch_all(db)
+    .await
+    .context("error fetching notification settings")?;
+
+    return Ok(settings);
+}
+
+pub async fn set_quiet_hours(
+    db: &Db,
+    user_id: &str,
+    timezone: &str,
+    quiet_hours: Option<(NaiveTime, NaiveTime)>,
+) -> Result<(), anyhow::Error> {
+    let (start, end) = quiet_hours.unzip();
+    let mut tx = db.begin().await.context("error starting transaction")?;
+
+    sqlx::query!(
+        r#"
+            INSERT INTO notification_settings (user_id, timezone, quiet_hours_start, quiet_hours_end)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id) DO UPDATE
+            SET timezone = $2, quiet_hours_start = $3, quiet_hours_end = $4
+        "#,
+        user_id,
+        timezone,
+        start,
+        end,
+    )
+    .execute(&mut *tx)
+    .await
+    .context("error setting quiet hours")?;
+
+    crate::notifications::release_held(&mut tx, user_id, &Utc::now()).await?;
+
+    tx.commit().await.context("error committing transaction")?;
+
+    return Ok(());
+}
+
+pub async fn set_dnd_until(
+    db: &Db,
+    user_id: &str,
+    dnd_until: Option<&DateTime<Utc>>,
+) -> Result<(), anyhow::Error> {
+    let mut tx = db.begin().await.context("error starting transaction")?;
+
+    sqlx::query!(
+        r#"
+            INSERT INTO notification_settings (user_id, dnd_until)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE
+            SET dnd_until = $2
+        "#,
+        user_id,
+        dnd_until,
+    )
+    .execute(&mut *tx)
+    .await
+    .context("error setting do not disturb")?;
+
+    crate::notifications::release_held(&mut tx, user_id, &Utc::now()).await?;
+
+    tx.commit().await.context("error committing transaction")?;
+
+    return Ok(());
+}
+
+pub async fn set_locale(db: &Db, user_id: &str, locale: &str) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO notification_settings (user_id, locale)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE
+            SET locale = $2
+        "#,
+        user_id,
+        locale
+    )
+    .execute(db)
+    .await
+    .context("error setting locale")?;
+
+    return Ok(());
+}
+
+pub async fn set_daily_times(
+    db: &Db,
+    user_id: &str,
+    nudge_at: Option<&NaiveTime>,
+    summary_at: Option<&NaiveTime>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO notification_settings (user_id, nudge_at, summary_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id) DO UPDATE
+            SET nudge_at = $2, summary_at = $3
+        "#,
+        user_id,
+        nudge_at,
+        summary_at,
+    )
+    .execute(db)
+    .await
+    .context("error setting daily notification times")?;
+
+    return Ok(());
+}
+
+/// Settings of the users that want a nudge or a summary.
+pub async fn get_with_daily_times(db: &Db) -> Result<Vec<NotificationSettings>, anyhow::Error> {
+    let settings = sqlx::query_as!(
+        NotificationSettings,
+        r#"
+            SELECT * FROM notification_settings
+            WHERE nudge_at IS NOT NULL
+            OR summary_at IS NOT NULL
+        "#,
+    )
+    .fetch_all(db)
+    .await
+    .context("error fetching notification settings")?;
+
+    return Ok(settings);
+}
+
+/// Records that the nudge of the local day `day` is being sent, false if it
+/// already was, so only one of several workers sends it.
+pub async fn claim_nudge(db: &Db, user_id: &str, day: &NaiveDate) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+            UPDATE notification_settings
+            SET nudged_on = $2
+            WHERE user_id = $1
+            AND nudged_on IS DISTINCT FROM $2
+        "#,
+        user_id,
+        day,
+    )
+    .execute(db)
+    .await
+    .context("error claiming nudge")?;
+
+    return Ok(result.rows_affected() == 1);
+}
+
+/// Like [`claim_nudge`] for the summary.
+pub async fn claim_summary(db: &Db, user_id: &str, day: &NaiveDate) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+            UPDATE notification_settings
+            SET summarized_on = $2
+            WHERE user_id = $1
+            AND summarized_on IS DISTINCT FROM $2
+        "#,
+        user_id,
+        day,
+    )
+    .execute(db)
+    .await
+    .context("error claiming summary")?;
+
+    return Ok(result.rows_affected() == 1);
+}
+
+pub async fn set_digest_schedule(
+    db: &Db,
+    user_id: &str,
+    schedule: Option<(i16, NaiveTime)>,
+) -> Result<(), anyhow::Error> {
+    let (weekday, at) = schedule.unzip();
+
+    sqlx::query!(
+        r#"
+            INSERT INTO notification_settings (user_id, digest_weekday, digest_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id) DO UPDATE
+            SET digest_weekday = $2, digest_at = $3
+        "#,
+        user_id,
+        weekday,
+        at,
+    )
+    .execute(db)
+    .await
+    .context("error setting digest schedule")?;
+
+    return Ok(());
+}
+
+/// A user that wants the weekly digest, along with where to send it.
+#[derive(Debug)]
+pub struct DigestRecipient {
+    pub email: String,
+    pub settings: NotificationSettings,
+}
+
+pub async fn get_digest_recipients(db: &Db) -> Result<Vec<DigestRecipient>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+            SELECT users.email, notification_settings.*
+            FROM notification_settings
+            JOIN users ON users.id = notification_settings.user_id
+            WHERE digest_weekday IS NOT NULL
+            AND digest_at IS NOT NULL
+        "#,
+    )
+    .fetch_all(db)
+    .await
+    .context("error fetching digest recipients")?;
+
+    let recipients = rows
+        .into_iter()
+        .map(|row| DigestRecipient {
+            email: row.email,
+            settings: NotificationSettings {
+                user_id: row.user_id,
+                timezone: row.timezone,
+                quiet_hours_start: row.quiet_hours_start,
+                quiet_hours_end: row.quiet_hours_end,
+                dnd_until: row.dnd_until,
+                locale: row.locale,
+                nudge_at: row.nudge_at,
+                summary_at: row.summary_at,
+                nudged_on: row.nudged_on,
+                summarized_on: row.summarized_on,
+                digest_weekday: row.digest_weekday,
+                digest_at: row.digest_at,
+                digested_on: row.digested_on,
+            },
+        })
+        .collect();
+
+    return Ok(recipients);
+}
+
+/// Like [`claim_nudge`] for the weekly digest, `day` is the local day it's sent on.
+pub async fn claim_digest(db: &Db, user_id: &str, day: &NaiveDate) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+            UPDATE notification_settings
+            SET digested_on = $2
+            WHERE user_id = $1
+            AND digested_on IS DISTINCT FROM $2
+        "#,
+        user_id,
+        day,
+    )
+    .execute(db)
+    .await
+    .context("error claiming digest")?;
+
+    return Ok(result.rows_affected() == 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_changing_settings_releases_held_notifications(db: Db) {
+        let held = crate::test_utils::insert_notification(&db, "held@test.local").await;
+        let until = Utc::now() + chrono::Duration::hours(2);
+        let next_attempt_at = || {
+            return sqlx::query_scalar!(
+                "SELECT next_attempt_at FROM notifications WHERE id = $1",
+                held.id
+            )
+            .fetch_one(&db);
+        };
+
+        // held by do not disturb, like the service does
+        set_dnd_until(&db, &held.user_id, Some(&until))
+            .await
+            .unwrap();
+        sqlx::query!(
+            "UPDATE notifications SET next_attempt_at = $2 WHERE id = $1",
+            held.id,
+            until
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        set_dnd_until(&db, &held.user_id, None).await.unwrap();
+        assert!(next_attempt_at().await.unwrap() <= Utc::now());
+
+        // held by quiet hours that were changed since
+        sqlx::query!(
+            "UPDATE notifications SET next_attempt_at = $2 WHERE id = $1",
+            held.id,
+            until
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        set_quiet_hours(&db, &held.user_id, "UTC", None)
+            .await
+            .unwrap();
+        assert!(next_attempt_at().await.unwrap() <= Utc::now());
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_daily_notifications_are_claimed_once_a_day(db: Db) {
+        let user = crate::users::create(&db, "daily@test.local").await.unwrap();
+        let nudge_at = NaiveTime::from_hms_opt(10, 0, 0).unwrap();
+        let today = NaiveDate::from_ymd_opt(2026, 10, 19).unwrap();
+
+        assert!(get_with_daily_times(&db).await.unwrap().is_empty());
+
+        set_daily_times(&db, &user.id, Some(&nudge_at), None)
+            .await
+            .unwrap();
+
+        let settings = get_with_daily_times(&db).await.unwrap();
+        assert_eq!(settings.len(), 1);
+        assert_eq!(settings[0].nudge_at, Some(nudge_at));
+
+        assert!(claim_nudge(&db, &user.id, &today).await.unwrap());
+        assert!(!claim_nudge(&db, &user.id, &today).await.unwrap());
+        assert!(claim_nudge(&db, &user.id, &today.succ_opt().unwrap())
+            .await
+            .unwrap());
+        assert!(claim_summary(&db, &user.id, &today).await.unwrap());
+    }
+}
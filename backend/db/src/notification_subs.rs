@@ -1,45 +1,64 @@
 use crate::{create_id, Db};
 use anyhow::Context;
+use chrono::{DateTime, Utc};
 
+#[derive(Debug, Clone)]
 pub struct NotificationSub {
     pub id: String,
     pub user_id: String,
     pub endpoint: String,
     pub p256dh: String,
     pub auth: String,
+    pub created_at: DateTime<Utc>,
+    pub last_success_at: Option<DateTime<Utc>>,
+    /// Set once the push service reported the subscription as gone,
+    /// expired subscriptions aren't sent to anymore.
+    pub expired_at: Option<DateTime<Utc>>,
+    /// Given by the user to tell their devices apart.
+    pub name: Option<String>,
+    pub user_agent: Option<String>,
+    /// The VAPID public key the browser subscribed with, `None` for old subscriptions.
+    pub vapid_public_key: Option<String>,
+    /// Set when the VAPID keys were rotated, the browser should subscribe again with the new key.
+    pub resubscribe_requested_at: Option<DateTime<Utc>>,
 }
 
+/// Inserts a subscription, or refreshes the keys of an existing one with the
+/// same endpoint. Subscribing again with an expired endpoint revives it, and
+/// subscribing with the current `vapid_public_key` settles a requested resubscription.
+/// A browser shared with another user moves to whoever subscribed last, without
+/// the name the previous user gave it.
 pub async fn upsert(
     db: &Db,
     user_id: &str,
     endpoint: &str,
     p256dh: &str,
     auth: &str,
+    user_agent: Option<&str>,
+    vapid_public_key: &str,
 ) -> Result<NotificationSub, anyhow::Error> {
-    let id = create_id();
-
-    let notification_sub = NotificationSub {
-        id: id.to_owned(),
-        user_id: user_id.to_owned(),
-        endpoint: endpoint.to_owned(),
-        p256dh: p256dh.to_owned(),
-        auth: auth.to_owned(),
-    };
-
-    sqlx::query!(
+    let notification_sub = sqlx::query_as!(
+        NotificationSub,
         r#"
-            INSERT INTO notification_subs (id, user_id, endpoint, p256dh, auth)
-            VALUES ($1, $2, $3, $4, $5)
+            INSERT INTO notification_subs (id, user_id, endpoint, p256dh, auth, created_at, user_agent, vapid_public_key)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
             ON CONFLICT (endpoint) DO UPDATE
-            SET p256dh = $4, auth = $5
+            SET user_id = $2,
+                name = CASE WHEN notification_subs.user_id = $2 THEN notification_subs.name END,
+                p256dh = $4, auth = $5, expired_at = NULL, user_agent = COALESCE($7, notification_subs.user_agent),
+                vapid_public_key = $8, resubscribe_requested_at = NULL
+            RETURNING *
         "#,
-        notification_sub.id,
-        notification_sub.user_id,
-        notification_sub.endpoint,
-        notification_sub.p256dh,
-        notification_sub.auth,
+        create_id(),
+        user_id,
+        endpoint,
+        p256dh,
+        auth,
+        Utc::now(),
+        user_agent,
+        vapid_public_key,
     )
-    .execute(db)
+    .fetch_one(db)
     .await
     .context("error inserting notification sub")?;
 
@@ -63,11 +82,94 @@ pub async fn delete(db: &Db, user_id: &str, endpoint: &str) -> Result<bool, anyh
     return Ok(result.rows_affected() == 1);
 }
 
+pub async fn delete_by_id(db: &Db, user_id: &str, id: &str) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+            DELETE FROM notification_subs
+            WHERE user_id = $1
+            AND id = $2
+        "#,
+        user_id,
+        id
+    )
+    .execute(db)
+    .await
+    .context("error deleting notification sub")?;
+
+    return Ok(result.rows_affected() == 1);
+}
+
+pub async fn get_one(
+    db: &Db,
+    user_id: &str,
+    id: &str,
+) -> Result<Option<NotificationSub>, anyhow::Error> {
+    let notification_sub = sqlx::query_as!(
+        NotificationSub,
+        r#"
+            SELECT * FROM notification_subs
+            WHERE user_id = $1
+            AND id = $2
+        "#,
+        user_id,
+        id
+    )
+    .fetch_optional(db)
+    .await
+    .context("error getting notification sub")?;
+
+    return Ok(notification_sub);
+}
+
+/// All the subscriptions of a user, including expired ones, oldest first.
+pub async fn get_by_user_id(db: &Db, user_id: &str) -> Result<Vec<NotificationSub>, anyhow::Error> {
+    let notification_subs = sqlx::query_as!(
+        NotificationSub,
+        r#"
+            SELECT * FROM notification_subs
+            WHERE user_id = $1
+            ORDER BY created_at, id
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+    .context("error getting notification subs")?;
+
+    return Ok(notification_subs);
+}
+
+pub async fn rename(
+    db: &Db,
+    user_id: &str,
+    id: &str,
+    name: Option<&str>,
+) -> Result<Option<NotificationSub>, anyhow::Error> {
+    let notification_sub = sqlx::query_as!(
+        NotificationSub,
+        r#"
+            UPDATE notification_subs
+            SET name = $3
+            WHERE user_id = $1
+            AND id = $2
+            RETURNING *
+        "#,
+        user_id,
+        id,
+        name
+    )
+    .fetch_optional(db)
+    .await
+    .context("error renaming notification sub")?;
+
+    return Ok(notification_sub);
+}
+
 pub async fn get_by_endpoint(
     db: &Db,
     endpoint: &str,
 ) -> Result<Option<NotificationSub>, anyhow::Error> {
-    return Ok(sqlx::query_as!(
+    let notification_sub = sqlx::query_as!(
         NotificationSub,
         r#"
             SELECT * FROM notification_subs
@@ -77,22 +179,339 @@ pub async fn get_by_endpoint(
     )
     .fetch_optional(db)
     .await
-    .context("error getting notification sub")?);
+    .context("error getting notification sub")?;
+
+    return Ok(notification_sub);
 }
 
+/// Subscriptions that can still be sent to, expired ones are left out.
 pub async fn get_by_user_ids(
     db: &Db,
     user_ids: &Vec<String>,
 ) -> Result<Vec<NotificationSub>, anyhow::Error> {
-    return Ok(sqlx::query_as!(
+    let notification_subs = sqlx::query_as!(
         NotificationSub,
         r#"
             SELECT * FROM notification_subs
             WHERE user_id = ANY($1)
+            AND expired_at IS NULL
         "#,
         user_ids
     )
     .fetch_all(db)
     .await
-    .context("error getting notification subs")?);
+    .context("error getting notification subs")?;
+
+    return Ok(notification_subs);
+}
+
+pub async fn mark_success(
+    db: &Db,
+    id: &str,
+    success_at: &DateTime<Utc>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE notification_subs
+            SET last_success_at = $2
+            WHERE id = $1
+        "#,
+        id,
+        success_at
+    )
+    .execute(db)
+    .await
+    .context("error marking notification sub success")?;
+
+    return Ok(());
+}
+
+pub async fn expire(db: &Db, id: &str, expired_at: &DateTime<Utc>) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE notification_subs
+            SET expired_at = $2
+            WHERE id = $1
+            AND expired_at IS NULL
+        "#,
+        id,
+        expired_at
+    )
+    .execute(db)
+    .await
+    .context("error expiring notification sub")?;
+
+    return Ok(());
+}
+
+/// Subscriptions that expired, or haven't received anything since `cutoff`.
+/// Subscriptions that never received anything are judged by their creation date,
+/// a subscription nothing was sent to may still work.
+pub async fn get_stale(
+    db: &Db,
+    cutoff: &DateTime<Utc>,
+) -> Result<Vec<NotificationSub>, anyhow::Error> {
+    let notification_subs = sqlx::query_as!(
+        NotificationSub,
+        r#"
+            SELECT * FROM notification_subs
+            WHERE expired_at IS NOT NULL
+            OR COALESCE(last_success_at, created_at) < $1
+            ORDER BY user_id, created_at
+        "#,
+        cutoff
+    )
+    .fetch_all(db)
+    .await
+    .context("error getting stale notification subs")?;
+
+    return Ok(notification_subs);
+}
+
+/// Deletes and returns the subscriptions that expired, or that failed a
+/// delivery and haven't received anything since `cutoff`. Subscriptions
+/// nothing was sent to are kept, they are only reported by [`get_stale`].
+pub async fn delete_stale(
+    db: &Db,
+    cutoff: &DateTime<Utc>,
+) -> Result<Vec<NotificationSub>, anyhow::Error> {
+    let notification_subs = sqlx::query_as!(
+        NotificationSub,
+        r#"
+            DELETE FROM notification_subs
+            WHERE expired_at IS NOT NULL
+            OR (
+                COALESCE(last_success_at, created_at) < $1
+                AND EXISTS (
+                    SELECT 1 FROM notification_deliveries
+                    WHERE notification_deliveries.notification_sub_id = notification_subs.id
+                    AND notification_deliveries.status = 'failed'
+                    AND notification_deliveries.last_attempt_at > COALESCE(notification_subs.last_success_at, notification_subs.created_at)
+                )
+            )
+            RETURNING *
+        "#,
+        cutoff
+    )
+    .fetch_all(db)
+    .await
+    .context("error deleting stale notification subs")?;
+
+    return Ok(notification_subs);
+}
+
+/// Flags the subscriptions that weren't made with `vapid_public_key` for
+/// resubscription, returns how many were flagged.
+pub async fn request_resubscription(
+    db: &Db,
+    vapid_public_key: &str,
+    requested_at: &DateTime<Utc>,
+) -> Result<u64, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+            UPDATE notification_subs
+            SET resubscribe_requested_at = $2
+            WHERE vapid_public_key IS DISTINCT FROM $1
+            AND resubscribe_requested_at IS NULL
+        "#,
+        vapid_public_key,
+        requested_at
+    )
+    .execute(db)
+    .await
+    .context("error requesting resubscription")?;
+
+    return Ok(result.rows_affected());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_manage_devices(db: Db) {
+        let user = crate::users::create(&db, "devices@test.local")
+            .await
+            .unwrap();
+        let other = crate::users::create(&db, "other@test.local").await.unwrap();
+
+        let sub = upsert(
+            &db,
+            &user.id,
+            "https://push.test.local/1",
+            "p256dh",
+            "auth",
+            Some("Firefox"),
+            "old-key",
+        )
+        .await
+        .unwrap();
+
+        // rotating the keys flags subscriptions made with another key
+        assert_eq!(
+            request_resubscription(&db, "new-key", &Utc::now())
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            request_resubscription(&db, "new-key", &Utc::now())
+                .await
+                .unwrap(),
+            0
+        );
+        let flagged = get_one(&db, &user.id, &sub.id).await.unwrap().unwrap();
+        assert!(flagged.resubscribe_requested_at.is_some());
+
+        // subscribing again without a user agent keeps the known one
+        let resubscribed = upsert(
+            &db,
+            &user.id,
+            &sub.endpoint,
+            "p256dh",
+            "auth",
+            None,
+            "new-key",
+        )
+        .await
+        .unwrap();
+        assert_eq!(resubscribed.id, sub.id);
+        assert_eq!(resubscribed.user_agent.as_deref(), Some("Firefox"));
+        assert_eq!(resubscribed.vapid_public_key.as_deref(), Some("new-key"));
+        assert!(resubscribed.resubscribe_requested_at.is_none());
+
+        assert!(rename(&db, &other.id, &sub.id, Some("Phone"))
+            .await
+            .unwrap()
+            .is_none());
+        let renamed = rename(&db, &user.id, &sub.id, Some("Phone"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(renamed.name.as_deref(), Some("Phone"));
+
+        let subs = get_by_user_id(&db, &user.id).await.unwrap();
+        assert_eq!(subs.len(), 1);
+        assert_eq!(subs[0].name.as_deref(), Some("Phone"));
+
+        assert!(!delete_by_id(&db, &other.id, &sub.id).await.unwrap());
+        assert!(delete_by_id(&db, &user.id, &sub.id).await.unwrap());
+        assert!(get_one(&db, &user.id, &sub.id).await.unwrap().is_none());
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_shared_browser_moves_to_last_subscriber(db: Db) {
+        let user = crate::users::create(&db, "first@test.local").await.unwrap();
+        let other = crate::users::create(&db, "second@test.local")
+            .await
+            .unwrap();
+        let endpoint = "https://push.test.local/shared";
+
+        let sub = upsert(&db, &user.id, endpoint, "p256dh", "auth", None, "key")
+            .await
+            .unwrap();
+        rename(&db, &user.id, &sub.id, Some("Laptop"))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let moved = upsert(&db, &other.id, endpoint, "p256dh", "auth", None, "key")
+            .await
+            .unwrap();
+        assert_eq!(moved.user_id, other.id);
+        assert!(moved.name.is_none());
+
+        assert!(get_by_user_id(&db, &user.id).await.unwrap().is_empty());
+        assert!(get_one(&db, &other.id, &moved.id).await.unwrap().is_some());
+        assert!(rename(&db, &other.id, &moved.id, Some("Shared"))
+            .await
+            .unwrap()
+            .is_some());
+
+        // the same user subscribing again keeps the name
+        let resubscribed = upsert(&db, &other.id, endpoint, "p256dh", "auth", None, "key")
+            .await
+            .unwrap();
+        assert_eq!(resubscribed.name.as_deref(), Some("Shared"));
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_delete_stale_keeps_idle_subs(db: Db) {
+        let notification = crate::test_utils::insert_notification(&db, "stale@test.local").await;
+        let now = Utc::now();
+        let cutoff = now - chrono::Duration::days(60);
+
+        let mut subs = vec![];
+        for endpoint in ["idle", "failing", "expired", "fresh"] {
+            let sub = upsert(
+                &db,
+                &notification.user_id,
+                &format!("https://push.test.local/{endpoint}"),
+                "p256dh",
+                "auth",
+                None,
+                "key",
+            )
+            .await
+            .unwrap();
+            subs.push(sub);
+        }
+        let [idle, failing, expired, fresh] = &subs[..] else {
+            unreachable!()
+        };
+
+        sqlx::query!(
+            "UPDATE notification_subs SET created_at = $1 WHERE id <> $2",
+            now - chrono::Duration::days(90),
+            fresh.id
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        expire(&db, &expired.id, &now).await.unwrap();
+        for sub in [failing, fresh] {
+            crate::notification_deliveries::record_attempt(
+                &db,
+                &notification.id,
+                &sub.id,
+                &crate::notification_deliveries::DeliveryStatus::Failed,
+                Some("500"),
+            )
+            .await
+            .unwrap();
+        }
+
+        // the idle one is reported, nothing was sent to it that could have failed
+        let mut stale = get_stale(&db, &cutoff)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|sub| sub.id)
+            .collect::<Vec<String>>();
+        stale.sort();
+        let mut expected = vec![
+            idle.id.to_owned(),
+            failing.id.to_owned(),
+            expired.id.to_owned(),
+        ];
+        expected.sort();
+        assert_eq!(stale, expected);
+
+        let mut deleted = delete_stale(&db, &cutoff)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|sub| sub.id)
+            .collect::<Vec<String>>();
+        deleted.sort();
+        let mut expected = vec![failing.id.to_owned(), expired.id.to_owned()];
+        expected.sort();
+        assert_eq!(deleted, expected);
+
+        let left = get_by_user_id(&db, &notification.user_id).await.unwrap();
+        assert_eq!(left.len(), 2);
+    }
 }
@@ -1,21 +1,142 @@
-use crate::{create_id, Db};
+use crate::{create_id, reminders::ReminderOffset, Db};
 use anyhow::Context;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use sqlx::{postgres::PgListener, PgConnection};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "varchar", rename_all = "lowercase")]
+pub enum NotificationStatus {
+    Pending,
+    Sent,
+    Failed,
+    /// Skipped because the user didn't want to be disturbed.
+    Dropped,
+}
+
+impl FromStr for NotificationStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(NotificationStatus::Pending),
+            "sent" => Ok(NotificationStatus::Sent),
+            "failed" => Ok(NotificationStatus::Failed),
+            "dropped" => Ok(NotificationStatus::Dropped),
+            _ => Err(anyhow::anyhow!("invalid notification status")),
+        }
+    }
+}
+
+impl AsRef<str> for NotificationStatus {
+    fn as_ref(&self) -> &str {
+        match self {
+            NotificationStatus::Pending => "pending",
+            NotificationStatus::Sent => "sent",
+            NotificationStatus::Failed => "failed",
+            NotificationStatus::Dropped => "dropped",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "varchar", rename_all = "lowercase")]
+pub enum NotificationKind {
+    /// The task ended.
+    Finished,
+    /// One of the user's reminders during the task.
+    Reminder,
+    /// Nothing was tracked yet today, at the time the user picked.
+    Nudge,
+    /// What was tracked today, at the time the user picked.
+    Summary,
+}
+
+impl FromStr for NotificationKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "finished" => Ok(NotificationKind::Finished),
+            "reminder" => Ok(NotificationKind::Reminder),
+            "nudge" => Ok(NotificationKind::Nudge),
+            "summary" => Ok(NotificationKind::Summary),
+            _ => Err(anyhow::anyhow!("invalid notification kind")),
+        }
+    }
+}
+
+impl AsRef<str> for NotificationKind {
+    fn as_ref(&self) -> &str {
+        match self {
+            NotificationKind::Finished => "finished",
+            NotificationKind::Reminder => "reminder",
+            NotificationKind::Nudge => "nudge",
+            NotificationKind::Summary => "summary",
+        }
+    }
+}
+
+/// Where in the app a notification leads when it's opened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeepLink {
+    Task { task_id: String },
+    Tasks,
+    Stats { from: NaiveDate, to: NaiveDate },
+}
+
+impl DeepLink {
+    /// The app path, stored with the notification and sent in the payload.
+    pub fn to_path(&self) -> String {
+        return match self {
+            DeepLink::Task { task_id } => format!("/app/tasks?task_id={task_id}"),
+            DeepLink::Tasks => "/app/tasks".to_owned(),
+            DeepLink::Stats { from, to } => format!("/app/stats?from={from}&to={to}"),
+        };
+    }
+}
 
 #[derive(Debug)]
 pub struct Notification {
     pub id: String,
     pub user_id: String,
-    pub task_id: String,
-    pub title: String,
-    pub message: String,
+    /// `None` for nudges and summaries.
+    pub task_id: Option<String>,
+    pub kind: NotificationKind,
+    /// Set for notifications written before templates and for the ones that
+    /// aren't about a task, see `notifications::templates`.
+    pub title: Option<String>,
+    pub message: Option<String>,
     pub send_at: DateTime<Utc>,
+    pub status: NotificationStatus,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    /// The worker delivering the notification, until its lease runs out.
+    pub claimed_by: Option<String>,
+    pub claimed_until: Option<DateTime<Utc>>,
+    /// A [`DeepLink`] path.
+    pub link: Option<String>,
+    pub reminder_kind: Option<String>,
+    pub reminder_value: Option<i32>,
+}
+
+impl Notification {
+    /// The reminder this notification is for, if it's one.
+    pub fn reminder_offset(&self) -> Option<ReminderOffset> {
+        let (kind, value) = self.reminder_kind.as_deref().zip(self.reminder_value)?;
+
+        return ReminderOffset::from_row(kind, value).ok();
+    }
 }
 
 pub async fn insert(
     db: &Db,
     user_id: &str,
     task_id: &str,
+    kind: &NotificationKind,
     title: &str,
     message: &str,
     send_at: &DateTime<Utc>,
@@ -25,23 +146,37 @@ pub async fn insert(
     let notification = Notification {
         id: id.to_owned(),
         user_id: user_id.to_owned(),
-        task_id: task_id.to_owned(),
-        title: title.to_owned(),
-        message: message.to_owned(),
+        task_id: Some(task_id.to_owned()),
+        kind: *kind,
+        title: Some(title.to_owned()),
+        message: Some(message.to_owned()),
         send_at: send_at.to_owned(),
+        status: NotificationStatus::Pending,
+        attempts: 0,
+        next_attempt_at: send_at.to_owned(),
+        last_error: None,
+        claimed_by: None,
+        claimed_until: None,
+        link: None,
+        reminder_kind: None,
+        reminder_value: None,
     };
 
     sqlx::query!(
         r#"
-            INSERT INTO notifications (id, user_id, task_id, title, message, send_at)
-            VALUES ($1, $2, $3, $4, $5, $6)
+            INSERT INTO notifications (id, user_id, task_id, kind, title, message, send_at, status, attempts, next_attempt_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
         "#,
         notification.id,
         notification.user_id,
         notification.task_id,
+        notification.kind.as_ref(),
         notification.title,
         notification.message,
         notification.send_at,
+        notification.status.as_ref(),
+        notification.attempts,
+        notification.next_attempt_at,
     )
     .execute(db)
     .await
@@ -50,39 +185,261 @@ pub async fn insert(
     return Ok(notification);
 }
 
-pub async fn get_to_send(db: &Db) -> Result<Vec<Notification>, anyhow::Error> {
-    return Ok(sqlx::query_as!(
+/// Queues a notification that isn't about a task, with its text already written.
+pub async fn insert_for_user(
+    db: &Db,
+    user_id: &str,
+    kind: &NotificationKind,
+    title: &str,
+    message: &str,
+    link: &DeepLink,
+    send_at: &DateTime<Utc>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO notifications (id, user_id, kind, title, message, send_at, status, attempts, next_attempt_at, link)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, 0, $6, $8)
+        "#,
+        create_id(),
+        user_id,
+        kind.as_ref(),
+        title,
+        message,
+        send_at,
+        NotificationStatus::Pending.as_ref(),
+        link.to_path(),
+    )
+    .execute(db)
+    .await
+    .context("error inserting notification")?;
+
+    return Ok(());
+}
+
+/// Makes the user's due notifications that were held back by quiet hours or do
+/// not disturb due now, the service holds them again if the new settings still
+/// say so. Pending retries are sent early too. Called in the same transaction
+/// that changes the settings.
+pub async fn release_held(
+    conn: &mut PgConnection,
+    user_id: &str,
+    now: &DateTime<Utc>,
+) -> Result<u64, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+            UPDATE notifications
+            SET next_attempt_at = $2
+            WHERE user_id = $1
+            AND status = 'pending'
+            AND send_at <= $2
+            AND next_attempt_at > $2
+            AND (claimed_until IS NULL OR claimed_until <= $2)
+        "#,
+        user_id,
+        now
+    )
+    .execute(&mut *conn)
+    .await
+    .context("error releasing held notifications")?;
+
+    return Ok(result.rows_affected());
+}
+
+/// Claims up to `limit` notifications that are due at `now` for `worker_id`.
+/// Rows locked by another worker are skipped, and the claim lasts until
+/// `lease` runs out so a crashed worker's notifications are picked up again.
+pub async fn claim_due(
+    db: &Db,
+    worker_id: &str,
+    now: &DateTime<Utc>,
+    lease: &Duration,
+    limit: i64,
+) -> Result<Vec<Notification>, anyhow::Error> {
+    let notifications = sqlx::query_as!(
         Notification,
         r#"
-            SELECT * FROM notifications
-            WHERE send_at <= NOW()
+            UPDATE notifications
+            SET claimed_by = $1, claimed_until = $2
+            WHERE id IN (
+                SELECT id FROM notifications
+                WHERE status = 'pending'
+                AND next_attempt_at <= $3
+                AND (claimed_until IS NULL OR claimed_until <= $3)
+                ORDER BY next_attempt_at
+                LIMIT $4
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING
+                id,
+                user_id,
+                task_id,
+                kind AS "kind: NotificationKind",
+                title,
+                message,
+                send_at,
+                status AS "status: NotificationStatus",
+                attempts,
+                next_attempt_at,
+                last_error,
+                claimed_by,
+                claimed_until,
+                link,
+                reminder_kind,
+                reminder_value
         "#,
+        worker_id,
+        *now + *lease,
+        now,
+        limit,
     )
     .fetch_all(db)
     .await
-    .context("error getting notifications")?);
+    .context("error claiming notifications")?;
+
+    return Ok(notifications);
 }
 
-pub async fn delete_by_ids(db: &Db, ids: &Vec<String>) -> Result<(), anyhow::Error> {
+/// When the next pending notification is due, if there is one.
+pub async fn get_next_attempt_at(db: &Db) -> Result<Option<DateTime<Utc>>, anyhow::Error> {
+    let next_attempt_at = sqlx::query_scalar!(
+        r#"
+            SELECT MIN(GREATEST(next_attempt_at, claimed_until))
+            FROM notifications
+            WHERE status = 'pending'
+        "#,
+    )
+    .fetch_one(db)
+    .await
+    .context("error getting next notification attempt")?;
+
+    return Ok(next_attempt_at);
+}
+
+/// Postgres channel notified whenever a notification is inserted, moved or deleted.
+pub const CHANGES_CHANNEL: &str = "notifications_changed";
+
+/// Listens on [`CHANGES_CHANNEL`], the listener reconnects by itself
+/// but notifications sent while it's disconnected are lost.
+pub async fn listen_for_changes(db: &Db) -> Result<PgListener, anyhow::Error> {
+    let mut listener = PgListener::connect_with(db)
+        .await
+        .context("error connecting notifications listener")?;
+
+    listener
+        .listen(CHANGES_CHANNEL)
+        .await
+        .context("error listening for notification changes")?;
+
+    return Ok(listener);
+}
+
+/// Records the outcome of a delivery attempt and releases the claim.
+/// `next_attempt_at` is only used while the notification stays pending.
+/// Returns false if the claim was lost to another worker in the meantime.
+pub async fn update_status(
+    db: &Db,
+    id: &str,
+    worker_id: &str,
+    status: &NotificationStatus,
+    attempts: i32,
+    next_attempt_at: &DateTime<Utc>,
+    last_error: Option<&str>,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+            UPDATE notifications
+            SET status = $3, attempts = $4, next_attempt_at = $5, last_error = $6,
+                claimed_by = NULL, claimed_until = NULL
+            WHERE id = $1
+            AND claimed_by = $2
+        "#,
+        id,
+        worker_id,
+        status.as_ref(),
+        attempts,
+        next_attempt_at,
+        last_error,
+    )
+    .execute(db)
+    .await
+    .context("error updating notification status")?;
+
+    return Ok(result.rows_affected() == 1);
+}
+
+/// A notification to schedule with [`replace_pending_for_task`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewNotification {
+    pub kind: NotificationKind,
+    pub send_at: DateTime<Utc>,
+    pub link: Option<DeepLink>,
+    /// Which of the user's reminders a [`NotificationKind::Reminder`] is for.
+    pub reminder: Option<ReminderOffset>,
+}
+
+/// Swaps the unsent notifications of a task for `notifications` in one transaction,
+/// used whenever the task is started or moved so its reminders stay in sync.
+pub async fn replace_pending_for_task(
+    db: &Db,
+    user_id: &str,
+    task_id: &str,
+    notifications: &[NewNotification],
+) -> Result<(), anyhow::Error> {
+    let mut tx = db.begin().await.context("error starting transaction")?;
+
     sqlx::query!(
         r#"
             DELETE FROM notifications
-            WHERE id = ANY($1)
+            WHERE user_id = $1 AND task_id = $2
+            AND status = 'pending'
         "#,
-        ids
+        user_id,
+        task_id
     )
-    .execute(db)
+    .execute(&mut *tx)
     .await
     .context("error deleting notifications")?;
 
+    for notification in notifications {
+        let (reminder_kind, reminder_value) = notification
+            .reminder
+            .map(|reminder| reminder.to_row())
+            .unzip();
+
+        sqlx::query!(
+            r#"
+                INSERT INTO notifications (id, user_id, task_id, kind, send_at, status, attempts, next_attempt_at, link, reminder_kind, reminder_value)
+                VALUES ($1, $2, $3, $4, $5, $6, 0, $5, $7, $8, $9)
+            "#,
+            create_id(),
+            user_id,
+            task_id,
+            notification.kind.as_ref(),
+            notification.send_at,
+            NotificationStatus::Pending.as_ref(),
+            notification.link.as_ref().map(|link| link.to_path()),
+            reminder_kind,
+            reminder_value,
+        )
+        .execute(&mut *tx)
+        .await
+        .context("error inserting notification")?;
+    }
+
+    tx.commit().await.context("error committing transaction")?;
+
     return Ok(());
 }
 
+/// Removes the notifications of a task that haven't been sent yet,
+/// sent and failed ones are kept along with their delivery records until
+/// [`delete_finished`] removes them.
 pub async fn delete_by_task_id(db: &Db, user_id: &str, task_id: &str) -> Result<(), anyhow::Error> {
     sqlx::query!(
         r#"
             DELETE FROM notifications
             WHERE user_id = $1 AND task_id = $2
+            AND status = 'pending'
         "#,
         user_id,
         task_id
@@ -93,3 +450,323 @@ pub async fn delete_by_task_id(db: &Db, user_id: &str, task_id: &str) -> Result<
 
     return Ok(());
 }
+
+/// Deletes the notifications that were sent, failed or dropped before `cutoff`
+/// along with their delivery records, their inbox items are kept.
+/// Returns how many were deleted.
+pub async fn delete_finished(db: &Db, cutoff: &DateTime<Utc>) -> Result<u64, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+            DELETE FROM notifications
+            WHERE status <> 'pending'
+            AND send_at < $1
+        "#,
+        cutoff
+    )
+    .execute(db)
+    .await
+    .context("error deleting finished notifications")?;
+
+    return Ok(result.rows_affected());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::insert_task;
+
+    async fn changed(listener: &mut PgListener) -> bool {
+        let notification =
+            tokio::time::timeout(std::time::Duration::from_millis(500), listener.recv()).await;
+
+        return notification.is_ok();
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_claims_are_exclusive_until_the_lease_runs_out(db: Db) {
+        let (user_id, task_id) = insert_task(&db, "claims@test.local").await;
+        let now = Utc::now();
+        let lease = Duration::minutes(5);
+
+        for _ in 0..3 {
+            insert(
+                &db,
+                &user_id,
+                &task_id,
+                &NotificationKind::Finished,
+                "title",
+                "message",
+                &now,
+            )
+            .await
+            .unwrap();
+        }
+
+        let (a, b) = tokio::join!(
+            claim_due(&db, "worker-a", &now, &lease, 2),
+            claim_due(&db, "worker-b", &now, &lease, 2),
+        );
+        let (a, b) = (a.unwrap(), b.unwrap());
+        let c = claim_due(&db, "worker-c", &now, &lease, 10).await.unwrap();
+
+        let mut claimed = a
+            .iter()
+            .chain(b.iter())
+            .chain(c.iter())
+            .map(|n| n.id.to_owned())
+            .collect::<Vec<String>>();
+        claimed.sort();
+        claimed.dedup();
+        assert_eq!(claimed.len(), 3);
+        assert_eq!(a.len() + b.len() + c.len(), 3);
+
+        // everything is claimed
+        let d = claim_due(&db, "worker-d", &now, &lease, 10).await.unwrap();
+        assert!(d.is_empty());
+        let next_attempt_at = get_next_attempt_at(&db).await.unwrap().unwrap();
+        assert!((next_attempt_at - (now + lease)).abs() < Duration::milliseconds(1));
+
+        // the workers crashed, their notifications come back once the lease runs out
+        let after_lease = now + lease + Duration::seconds(1);
+        let mut d = claim_due(&db, "worker-d", &after_lease, &lease, 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|n| n.id)
+            .collect::<Vec<String>>();
+        d.sort();
+        assert_eq!(d, claimed);
+
+        // a stale worker lost its claims and can't overwrite worker d's outcome
+        let updated = update_status(
+            &db,
+            &claimed[0],
+            "worker-a",
+            &NotificationStatus::Sent,
+            1,
+            &now,
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(!updated);
+
+        let updated = update_status(
+            &db,
+            &claimed[0],
+            "worker-d",
+            &NotificationStatus::Sent,
+            1,
+            &now,
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(updated);
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_changes_are_notified(db: Db) {
+        let (user_id, task_id) = insert_task(&db, "listen@test.local").await;
+
+        let mut listener = listen_for_changes(&db).await.unwrap();
+
+        let notification = insert(
+            &db,
+            &user_id,
+            &task_id,
+            &NotificationKind::Finished,
+            "title",
+            "message",
+            &Utc::now(),
+        )
+        .await
+        .unwrap();
+        assert!(changed(&mut listener).await);
+
+        // claims and delivery updates don't reschedule anything
+        claim_due(&db, "worker", &Utc::now(), &Duration::minutes(5), 10)
+            .await
+            .unwrap();
+        update_status(
+            &db,
+            &notification.id,
+            "worker",
+            &NotificationStatus::Pending,
+            1,
+            &Utc::now(),
+            Some("error"),
+        )
+        .await
+        .unwrap();
+        assert!(!changed(&mut listener).await);
+
+        delete_by_task_id(&db, &user_id, &task_id).await.unwrap();
+        assert!(changed(&mut listener).await);
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_replace_pending_for_task_keeps_sent_notifications(db: Db) {
+        let (user_id, task_id) = insert_task(&db, "replace@test.local").await;
+        let now = Utc::now();
+        let lease = Duration::minutes(5);
+
+        let notification = |kind, send_at| NewNotification {
+            kind,
+            send_at,
+            link: None,
+            reminder: None,
+        };
+
+        replace_pending_for_task(
+            &db,
+            &user_id,
+            &task_id,
+            &[
+                notification(NotificationKind::Reminder, now),
+                notification(NotificationKind::Finished, now + Duration::minutes(10)),
+            ],
+        )
+        .await
+        .unwrap();
+
+        // the reminder went out
+        let sent = claim_due(&db, "worker", &now, &lease, 10).await.unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].kind, NotificationKind::Reminder);
+        update_status(
+            &db,
+            &sent[0].id,
+            "worker",
+            &NotificationStatus::Sent,
+            1,
+            &now,
+            None,
+        )
+        .await
+        .unwrap();
+
+        // then the task was extended
+        replace_pending_for_task(
+            &db,
+            &user_id,
+            &task_id,
+            &[notification(
+                NotificationKind::Finished,
+                now + Duration::minutes(15),
+            )],
+        )
+        .await
+        .unwrap();
+
+        let rows = sqlx::query!(
+            r#"
+                SELECT kind, status, send_at FROM notifications
+                WHERE task_id = $1
+                ORDER BY send_at
+            "#,
+            task_id
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap();
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(
+            (rows[0].kind.as_str(), rows[0].status.as_str()),
+            ("reminder", "sent")
+        );
+        assert_eq!(
+            (rows[1].kind.as_str(), rows[1].status.as_str()),
+            ("finished", "pending")
+        );
+        assert!(
+            (rows[1].send_at - (now + Duration::minutes(15))).abs() < Duration::milliseconds(1)
+        );
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_delete_finished_keeps_pending_and_inbox(db: Db) {
+        let sent = crate::test_utils::insert_notification(&db, "retention@test.local").await;
+        let now = Utc::now();
+        let lease = Duration::minutes(5);
+
+        claim_due(&db, "worker", &now, &lease, 10).await.unwrap();
+        update_status(
+            &db,
+            &sent.id,
+            "worker",
+            &NotificationStatus::Sent,
+            1,
+            &now,
+            None,
+        )
+        .await
+        .unwrap();
+        let sub = crate::notification_subs::upsert(
+            &db,
+            &sent.user_id,
+            "https://push.test.local/1",
+            "p256dh",
+            "auth",
+            None,
+            "key",
+        )
+        .await
+        .unwrap();
+        crate::notification_deliveries::record_attempt(
+            &db,
+            &sent.id,
+            &sub.id,
+            &crate::notification_deliveries::DeliveryStatus::Sent,
+            None,
+        )
+        .await
+        .unwrap();
+        crate::inbox::insert_for_notification(&db, &sent, "title", "message", &now)
+            .await
+            .unwrap();
+
+        let pending = insert(
+            &db,
+            &sent.user_id,
+            sent.task_id.as_deref().unwrap(),
+            &NotificationKind::Finished,
+            "title",
+            "message",
+            &now,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(delete_finished(&db, &sent.send_at).await.unwrap(), 0);
+        assert_eq!(
+            delete_finished(&db, &(now + Duration::days(1)))
+                .await
+                .unwrap(),
+            1
+        );
+
+        let ids = sqlx::query_scalar!("SELECT id FROM notifications")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+        assert_eq!(ids, vec![pending.id]);
+        assert!(
+            crate::notification_deliveries::get_by_notification_id(&db, &sent.id)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        let items = crate::inbox::get_many(&db, &sent.user_id, None, false)
+            .await
+            .unwrap();
+        assert_eq!(items.len(), 1);
+        assert!(items[0].notification_id.is_none());
+    }
+}
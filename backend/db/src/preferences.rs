@@ -0,0 +1,288 @@
+use crate::{notifications::NotificationKind, Db};
+use anyhow::Context;
+use std::collections::HashMap;
+
+/// Something a user can get notified about.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize, sqlx::Type,
+)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+pub enum NotificationEvent {
+    TaskFinished,
+    Reminders,
+    /// The nudge and summary at the times the user picked.
+    Daily,
+    Digests,
+}
+
+impl From<&NotificationKind> for NotificationEvent {
+    fn from(kind: &NotificationKind) -> Self {
+        return match kind {
+            NotificationKind::Finished => NotificationEvent::TaskFinished,
+            NotificationKind::Reminder => NotificationEvent::Reminders,
+            NotificationKind::Nudge | NotificationKind::Summary => NotificationEvent::Daily,
+        };
+    }
+}
+
+impl AsRef<str> for NotificationEvent {
+    fn as_ref(&self) -> &str {
+        match self {
+            NotificationEvent::TaskFinished => "task_finished",
+            NotificationEvent::Reminders => "reminders",
+            NotificationEvent::Daily => "daily",
+            NotificationEvent::Digests => "digests",
+        }
+    }
+}
+
+/// The Web Push urgency, push services may hold back less urgent messages
+/// to save battery.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "kebab-case")]
+#[sqlx(type_name = "varchar", rename_all = "kebab-case")]
+pub enum Urgency {
+    VeryLow,
+    Low,
+    Normal,
+    High,
+}
+
+impl AsRef<str> for Urgency {
+    fn as_ref(&self) -> &str {
+        match self {
+            Urgency::VeryLow => "very-low",
+            Urgency::Low => "low",
+            Urgency::Normal => "normal",
+            Urgency::High => "high",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct EventPreferences {
+    pub enabled: bool,
+    /// Ids of the push subscriptions and notification channels to notify,
+    /// `None` notifies all of them.
+    pub devices: Option<Vec<String>>,
+    pub sound: bool,
+    /// `None` leaves it to the kind of notification.
+    pub urgency: Option<Urgency>,
+}
+
+impl Default for EventPreferences {
+    fn default() -> Self {
+        return Self {
+            // nudges, summaries and digests are opted into by picking a time
+            enabled: true,
+            devices: None,
+            sound: true,
+            urgency: None,
+        };
+    }
+}
+
+impl EventPreferences {
+    /// Whether the subscription or channel with `id` should be notified.
+    pub fn includes_device(&self, id: &str) -> bool {
+        return self
+            .devices
+            .as_ref()
+            .is_none_or(|devices| devices.iter().any(|device| device == id));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Preferences {
+    pub task_finished: EventPreferences,
+    pub reminders: EventPreferences,
+    pub daily: EventPreferences,
+    pub digests: EventPreferences,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        return Self {
+            task_finished: EventPreferences::default(),
+            reminders: EventPreferences::default(),
+            daily: EventPreferences::default(),
+            digests: EventPreferences::default(),
+        };
+    }
+}
+
+impl Preferences {
+    pub fn event(&self, event: &NotificationEvent) -> &EventPreferences {
+        return match event {
+            NotificationEvent::TaskFinished => &self.task_finished,
+            NotificationEvent::Reminders => &self.reminders,
+            NotificationEvent::Daily => &self.daily,
+            NotificationEvent::Digests => &self.digests,
+        };
+    }
+
+    fn event_mut(&mut self, event: &NotificationEvent) -> &mut EventPreferences {
+        return match event {
+            NotificationEvent::TaskFinished => &mut self.task_finished,
+            NotificationEvent::Reminders => &mut self.reminders,
+            NotificationEvent::Daily => &mut self.daily,
+            NotificationEvent::Digests => &mut self.digests,
+        };
+    }
+
+    pub fn events(&self) -> [(NotificationEvent, &EventPreferences); 4] {
+        return [
+            (NotificationEvent::TaskFinished, &self.task_finished),
+            (NotificationEvent::Reminders, &self.reminders),
+            (NotificationEvent::Daily, &self.daily),
+            (NotificationEvent::Digests, &self.digests),
+        ];
+    }
+}
+
+struct PreferenceRow {
+    user_id: String,
+    event: NotificationEvent,
+    enabled: bool,
+    sub_ids: Option<Vec<String>>,
+    sound: bool,
+    urgency: Option<Urgency>,
+}
+
+/// Preferences by user id, users that never changed them get the defaults.
+pub async fn get_by_user_ids(
+    db: &Db,
+    user_ids: &Vec<String>,
+) -> Result<HashMap<String, Preferences>, anyhow::Error> {
+    let rows = sqlx::query_as!(
+        PreferenceRow,
+        r#"
+            SELECT
+                user_id,
+                event AS "event: NotificationEvent",
+                enabled,
+                sub_ids,
+                sound,
+                urgency AS "urgency: Urgency"
+            FROM notification_preferences
+            WHERE user_id = ANY($1)
+        "#,
+        user_ids
+    )
+    .fetch_all(db)
+    .await
+    .context("error fetching notification preferences")?;
+
+    let mut preferences = user_ids
+        .iter()
+        .map(|user_id| (user_id.to_owned(), Preferences::default()))
+        .collect::<HashMap<String, Preferences>>();
+
+    for row in rows {
+        if let Some(user_preferences) = preferences.get_mut(&row.user_id) {
+            *user_preferences.event_mut(&row.event) = EventPreferences {
+                enabled: row.enabled,
+                devices: row.sub_ids,
+                sound: row.sound,
+                urgency: row.urgency,
+            };
+        }
+    }
+
+    return Ok(preferences);
+}
+
+pub async fn get(db: &Db, user_id: &str) -> Result<Preferences, anyhow::Error> {
+    let preferences = get_by_user_ids(db, &vec![user_id.to_owned()])
+        .await?
+        .remove(user_id)
+        .unwrap_or_default();
+
+    return Ok(preferences);
+}
+
+pub async fn update(
+    db: &Db,
+    user_id: &str,
+    preferences: &Preferences,
+) -> Result<(), anyhow::Error> {
+    let mut tx = db.begin().await.context("error starting transaction")?;
+
+    for (event, event_preferences) in preferences.events() {
+        sqlx::query!(
+            r#"
+                INSERT INTO notification_preferences (user_id, event, enabled, sub_ids, sound, urgency)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                ON CONFLICT (user_id, event) DO UPDATE
+                SET enabled = $3, sub_ids = $4, sound = $5, urgency = $6
+            "#,
+            user_id,
+            event.as_ref(),
+            event_preferences.enabled,
+            event_preferences.devices.as_deref(),
+            event_preferences.sound,
+            event_preferences.urgency.as_ref().map(|urgency| urgency.as_ref()),
+        )
+        .execute(&mut *tx)
+        .await
+        .context("error updating notification preferences")?;
+    }
+
+    tx.commit().await.context("error committing transaction")?;
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preferences_json() {
+        let preferences = Preferences::default();
+        let json = serde_json::to_value(&preferences).unwrap();
+
+        assert_eq!(
+            json["task_finished"],
+            serde_json::json!({
+                "enabled": true,
+                "devices": null,
+                "sound": true,
+                "urgency": null
+            })
+        );
+        assert_eq!(json["digests"]["enabled"], true);
+        assert_eq!(
+            serde_json::from_value::<Preferences>(json).unwrap(),
+            preferences
+        );
+    }
+
+    #[test]
+    fn test_includes_device() {
+        let mut preferences = Preferences::default().task_finished;
+        assert!(preferences.includes_device("phone"));
+
+        preferences.devices = Some(vec!["laptop".to_owned()]);
+        assert!(!preferences.includes_device("phone"));
+        assert!(preferences.includes_device("laptop"));
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_update_preferences(db: Db) {
+        let user = crate::users::create(&db, "preferences@test.local")
+            .await
+            .unwrap();
+        assert_eq!(get(&db, &user.id).await.unwrap(), Preferences::default());
+
+        let mut preferences = Preferences::default();
+        preferences.reminders.enabled = false;
+        preferences.task_finished.devices = Some(vec!["phone".to_owned()]);
+        preferences.task_finished.urgency = Some(Urgency::High);
+
+        update(&db, &user.id, &preferences).await.unwrap();
+        assert_eq!(get(&db, &user.id).await.unwrap(), preferences);
+    }
+}
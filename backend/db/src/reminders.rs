@@ -0,0 +1,189 @@
+use crate::{create_id, Db};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+
+/// When a reminder goes off during a task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ReminderOffset {
+    /// `minutes` before the task ends.
+    BeforeEnd { minutes: i32 },
+    /// Once `percent` of the task has passed.
+    Progress { percent: i32 },
+}
+
+impl ReminderOffset {
+    pub(crate) fn from_row(kind: &str, value: i32) -> Result<Self, anyhow::Error> {
+        return match kind {
+            "before_end" => Ok(ReminderOffset::BeforeEnd { minutes: value }),
+            "progress" => Ok(ReminderOffset::Progress { percent: value }),
+            _ => Err(anyhow::anyhow!("invalid reminder kind '{kind}'")),
+        };
+    }
+
+    pub(crate) fn to_row(self) -> (&'static str, i32) {
+        return match self {
+            ReminderOffset::BeforeEnd { minutes } => ("before_end", minutes),
+            ReminderOffset::Progress { percent } => ("progress", percent),
+        };
+    }
+
+    /// When the reminder goes off for a task running from `start_at` to `end_at`,
+    /// or `None` if that isn't strictly within the task.
+    pub fn send_at(
+        &self,
+        start_at: &DateTime<Utc>,
+        end_at: &DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        let send_at = match self {
+            ReminderOffset::BeforeEnd { minutes } => {
+                *end_at - chrono::Duration::minutes((*minutes).into())
+            }
+            ReminderOffset::Progress { percent } => {
+                let elapsed = (*end_at - *start_at).num_milliseconds() * i64::from(*percent) / 100;
+                *start_at + chrono::Duration::milliseconds(elapsed)
+            }
+        };
+
+        if send_at <= *start_at || send_at >= *end_at {
+            return None;
+        }
+
+        return Some(send_at);
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Reminder {
+    pub id: String,
+    pub user_id: String,
+    #[serde(flatten)]
+    pub offset: ReminderOffset,
+    pub created_at: DateTime<Utc>,
+}
+
+pub async fn get_all(db: &Db, user_id: &str) -> Result<Vec<Reminder>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+            SELECT * FROM reminders
+            WHERE user_id = $1
+            ORDER BY created_at
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+    .context("error fetching reminders")?;
+
+    return rows
+        .into_iter()
+        .map(|row| {
+            Ok(Reminder {
+                offset: ReminderOffset::from_row(&row.kind, row.value)?,
+                id: row.id,
+                user_id: row.user_id,
+                created_at: row.created_at,
+            })
+        })
+        .collect();
+}
+
+/// Adds a reminder, or returns `None` if the user already has the same one.
+pub async fn insert(
+    db: &Db,
+    user_id: &str,
+    offset: &ReminderOffset,
+) -> Result<Option<Reminder>, anyhow::Error> {
+    let reminder = Reminder {
+        id: create_id(),
+        user_id: user_id.to_owned(),
+        offset: *offset,
+        created_at: Utc::now(),
+    };
+    let (kind, value) = offset.to_row();
+
+    let result = sqlx::query!(
+        r#"
+            INSERT INTO reminders (id, user_id, kind, value, created_at)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (user_id, kind, value) DO NOTHING
+        "#,
+        reminder.id,
+        reminder.user_id,
+        kind,
+        value,
+        reminder.created_at,
+    )
+    .execute(db)
+    .await
+    .context("error inserting reminder")?;
+
+    if result.rows_affected() == 0 {
+        return Ok(None);
+    }
+
+    return Ok(Some(reminder));
+}
+
+pub async fn delete(db: &Db, user_id: &str, reminder_id: &str) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+            DELETE FROM reminders
+            WHERE user_id = $1
+            AND id = $2
+        "#,
+        user_id,
+        reminder_id
+    )
+    .execute(db)
+    .await
+    .context("error deleting reminder")?;
+
+    return Ok(result.rows_affected() == 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    #[test]
+    fn test_reminder_send_at() {
+        let start_at = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        let end_at = start_at + Duration::minutes(30);
+
+        assert_eq!(
+            ReminderOffset::BeforeEnd { minutes: 5 }.send_at(&start_at, &end_at),
+            Some(start_at + Duration::minutes(25))
+        );
+        assert_eq!(
+            ReminderOffset::Progress { percent: 50 }.send_at(&start_at, &end_at),
+            Some(start_at + Duration::minutes(15))
+        );
+
+        // a reminder that would go off before the task started is skipped
+        assert_eq!(
+            ReminderOffset::BeforeEnd { minutes: 30 }.send_at(&start_at, &end_at),
+            None
+        );
+        assert_eq!(
+            ReminderOffset::BeforeEnd { minutes: 45 }.send_at(&start_at, &end_at),
+            None
+        );
+    }
+
+    #[test]
+    fn test_reminder_offset_json() {
+        assert_eq!(
+            serde_json::to_value(ReminderOffset::BeforeEnd { minutes: 5 }).unwrap(),
+            serde_json::json!({ "kind": "before_end", "minutes": 5 })
+        );
+        assert_eq!(
+            serde_json::from_value::<ReminderOffset>(
+                serde_json::json!({ "kind": "progress", "percent": 50 })
+            )
+            .unwrap(),
+            ReminderOffset::Progress { percent: 50 }
+        );
+    }
+}
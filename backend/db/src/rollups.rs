@@ -0,0 +1,591 @@
+use crate::{
+    tasks::{StatsPrecision, TagFilter},
+    Db,
+};
+use anyhow::Context;
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use sqlx::PgConnection;
+
+/// Recomputes the rollup rows of the given UTC days from the raw tasks.
+/// Called inside the same transaction that modifies the tasks.
+pub async fn refresh_days(
+    conn: &mut PgConnection,
+    user_id: &str,
+    days: &[NaiveDate],
+) -> Result<(), anyhow::Error> {
+    // refreshes of the same user wait for each other until the commit, otherwise
+    // both delete the old rows and the second insert hits the primary key
+    sqlx::query!(
+        r#"
+            SELECT pg_advisory_xact_lock(hashtext('task_daily_rollups:' || $1))
+        "#,
+        user_id,
+    )
+    .execute(&mut *conn)
+    .await
+    .context("error locking rollups")?;
+
+    sqlx::query!(
+        r#"
+            DELETE FROM task_daily_rollups
+            WHERE user_id = $1
+            AND day = ANY($2)
+        "#,
+        user_id,
+        days,
+    )
+    .execute(&mut *conn)
+    .await
+    .context("error deleting rollups")?;
+
+    sqlx::query!(
+        r#"
+            INSERT INTO task_daily_rollups (user_id, tag_id, day, seconds, sessions)
+            SELECT
+                user_id,
+                tag_id,
+                CAST(start_at AT TIME ZONE 'UTC' AS DATE) AS "day!",
+                SUM(seconds),
+                COUNT(*)
+            FROM
+                tasks
+            WHERE
+                user_id = $1
+                AND CAST(start_at AT TIME ZONE 'UTC' AS DATE) = ANY($2)
+            GROUP BY
+                user_id,
+                tag_id,
+                3
+        "#,
+        user_id,
+        days,
+    )
+    .execute(&mut *conn)
+    .await
+    .context("error inserting rollups")?;
+
+    return Ok(());
+}
+
+/// Rebuilds every rollup row from the raw tasks, returns the number of rows written.
+pub async fn backfill(db: &Db) -> Result<u64, anyhow::Error> {
+    let mut tx = db.begin().await.context("error starting transaction")?;
+
+    sqlx::query!("DELETE FROM task_daily_rollups")
+        .execute(&mut *tx)
+        .await
+        .context("error deleting rollups")?;
+
+    let result = sqlx::query!(
+        r#"
+            INSERT INTO task_daily_rollups (user_id, tag_id, day, seconds, sessions)
+            SELECT
+                user_id,
+                tag_id,
+                CAST(start_at AT TIME ZONE 'UTC' AS DATE) AS "day!",
+                SUM(seconds),
+                COUNT(*)
+            FROM
+                tasks
+            GROUP BY
+                user_id,
+                tag_id,
+                3
+        "#,
+    )
+    .execute(&mut *tx)
+    .await
+    .context("error inserting rollups")?;
+
+    tx.commit().await.context("error committing transaction")?;
+
+    return Ok(result.rows_affected());
+}
+
+pub fn utc_day(at: &DateTime<Utc>) -> NaiveDate {
+    return at.date_naive();
+}
+
+/// Midnight doesn't exist on some DST transition days,
+/// those read the local time with the offset from before the gap.
+pub fn local_to_utc(local: &NaiveDateTime, tz: &chrono_tz::Tz) -> DateTime<Utc> {
+    return match tz.from_local_datetime(local).earliest() {
+        Some(at) => at.with_timezone(&Utc),
+        None => tz
+            .from_local_datetime(&(*local - Duration::hours(1)))
+            .earliest()
+            .map_or_else(
+                || Utc.from_utc_datetime(local),
+                |at| at.with_timezone(&Utc) + Duration::hours(1),
+            ),
+    };
+}
+
+/// One bucket of a stats query. `date` is the local start of the bucket
+/// like `date_trunc` would return it, `start` and `end` are the UTC
+/// bounds of the part of the bucket inside the requested range.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatsBucket {
+    pub date: NaiveDateTime,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+fn truncate(precision: &StatsPrecision, local: &NaiveDateTime) -> NaiveDateTime {
+    let date = local.date();
+
+    let truncated = match precision {
+        StatsPrecision::Day => date,
+        StatsPrecision::Week => date - Duration::days(date.weekday().num_days_from_monday().into()),
+        StatsPrecision::Month => date.with_day(1).unwrap_or(date),
+    };
+
+    return truncated.and_time(chrono::NaiveTime::MIN);
+}
+
+fn next_bucket(precision: &StatsPrecision, date: &NaiveDateTime) -> anyhow::Result<NaiveDateTime> {
+    return match precision {
+        StatsPrecision::Day => Ok(*date + Duration::days(1)),
+        StatsPrecision::Week => Ok(*date + Duration::weeks(1)),
+        StatsPrecision::Month => date
+            .checked_add_months(Months::new(1))
+            .context("error getting next month"),
+    };
+}
+
+/// Splits the local range from `start` to `end` (inclusive, like the raw
+/// `start_at AT TIME ZONE` queries) into buckets of `precision`.
+pub fn buckets_for_range(
+    precision: &StatsPrecision,
+    start: &NaiveDateTime,
+    end: &NaiveDateTime,
+    tz: &chrono_tz::Tz,
+) -> anyhow::Result<Vec<StatsBucket>> {
+    // timestamptz has microsecond precision
+    let range_end = *end + Duration::microseconds(1);
+
+    let mut buckets = vec![];
+    let mut bucket_start = *start;
+
+    while bucket_start < range_end {
+        let date = truncate(precision, &bucket_start);
+        let next = next_bucket(precision, &date)?;
+        let bucket_end = next.min(range_end);
+
+        buckets.push(StatsBucket {
+            date,
+            start: local_to_utc(&bucket_start, tz),
+            end: local_to_utc(&bucket_end, tz),
+        });
+
+        bucket_start = next;
+    }
+
+    return Ok(buckets);
+}
+
+/// The parts of the buckets that are served from the rollups (whole UTC days)
+/// and from the raw tasks (partial days at the edges of each bucket).
+#[derive(Debug, Default, PartialEq)]
+pub struct Segments {
+    pub rollup_dates: Vec<NaiveDateTime>,
+    pub rollup_from: Vec<NaiveDate>,
+    pub rollup_to: Vec<NaiveDate>,
+    pub raw_dates: Vec<NaiveDateTime>,
+    pub raw_from: Vec<DateTime<Utc>>,
+    pub raw_to: Vec<DateTime<Utc>>,
+}
+
+impl Segments {
+    fn push_raw(&mut self, date: &NaiveDateTime, from: &DateTime<Utc>, to: &DateTime<Utc>) {
+        if from < to {
+            self.raw_dates.push(*date);
+            self.raw_from.push(*from);
+            self.raw_to.push(*to);
+        }
+    }
+}
+
+fn start_of_utc_day(day: &NaiveDate) -> DateTime<Utc> {
+    return Utc.from_utc_datetime(&day.and_time(chrono::NaiveTime::MIN));
+}
+
+pub fn segments_for_buckets(buckets: &[StatsBucket], use_rollups: bool) -> Segments {
+    let mut segments = Segments::default();
+
+    for bucket in buckets {
+        if !use_rollups {
+            segments.push_raw(&bucket.date, &bucket.start, &bucket.end);
+            continue;
+        }
+
+        // first whole UTC day inside the bucket and the day after the last one
+        let mut first_day = utc_day(&bucket.start);
+        if start_of_utc_day(&first_day) < bucket.start {
+            first_day += Duration::days(1);
+        }
+        let last_day = utc_day(&bucket.end);
+
+        if first_day >= last_day {
+            segments.push_raw(&bucket.date, &bucket.start, &bucket.end);
+            continue;
+        }
+
+        segments.rollup_dates.push(bucket.date);
+        segments.rollup_from.push(first_day);
+        segments.rollup_to.push(last_day);
+
+        segments.push_raw(&bucket.date, &bucket.start, &start_of_utc_day(&first_day));
+        segments.push_raw(&bucket.date, &start_of_utc_day(&last_day), &bucket.end);
+    }
+
+    return segments;
+}
+
+#[derive(Debug, PartialEq)]
+pub struct BucketSeconds {
+    pub date: NaiveDateTime,
+    pub tag_id: String,
+    pub tag_label: String,
+    pub tag_color: String,
+    pub seconds: i64,
+}
+
+async fn get_seconds_by_segment(
+    db: &Db,
+    user_id: &str,
+    segments: &Segments,
+    tag_filter: &TagFilter,
+) -> Result<Vec<BucketSeconds>, anyhow::Error> {
+    let seconds = sqlx::query_as!(
+        BucketSeconds,
+        r#"
+            WITH rollup_segments AS (
+                SELECT * FROM unnest($2::timestamp[], $3::date[], $4::date[])
+                AS segment(date, day_from, day_to)
+            ),
+            raw_segments AS (
+                SELECT * FROM unnest($5::timestamp[], $6::timestamptz[], $7::timestamptz[])
+                AS segment(date, from_at, to_at)
+            ),
+            segment_seconds AS (
+                SELECT
+                    rollup_segments.date,
+                    task_daily_rollups.tag_id,
+                    task_daily_rollups.seconds
+                FROM
+                    rollup_segments
+                JOIN
+                    task_daily_rollups ON task_daily_rollups.user_id = $1
+                    AND task_daily_rollups.day >= rollup_segments.day_from
+                    AND task_daily_rollups.day < rollup_segments.day_to
+                UNION ALL
+                SELECT
+                    raw_segments.date,
+                    tasks.tag_id,
+                    CAST(tasks.seconds AS BIGINT)
+                FROM
+                    raw_segments
+                JOIN
+                    tasks ON tasks.user_id = $1
+                    AND tasks.start_at >= raw_segments.from_at
+                    AND tasks.start_at < raw_segments.to_at
+            )
+            SELECT
+                segment_seconds.date AS "date!",
+                tags.id AS tag_id,
+                tags.label AS tag_label,
+                tags.color AS tag_color,
+                CAST(SUM(segment_seconds.seconds) AS BIGINT) AS "seconds!"
+            FROM
+                segment_seconds
+            JOIN
+                tags ON segment_seconds.tag_id = tags.id
+            WHERE
+                (cardinality($8::text[]) = 0 OR tags.id = ANY($8))
+                AND NOT (tags.id = ANY($9))
+            GROUP BY
+                segment_seconds.date,
+                tags.id,
+                tags.label,
+                tags.color
+            ORDER BY
+                segment_seconds.date ASC,
+                tags.id ASC;
+        "#,
+        user_id,
+        &segments.rollup_dates,
+        &segments.rollup_from,
+        &segments.rollup_to,
+        &segments.raw_dates,
+        &segments.raw_from,
+        &segments.raw_to,
+        &tag_filter.include,
+        &tag_filter.exclude,
+    )
+    .fetch_all(db)
+    .await
+    .context("error fetching seconds by bucket")?;
+
+    return Ok(seconds);
+}
+
+/// Tracked seconds per bucket and tag, served from the rollups
+/// with the raw tasks filling in the partial days.
+pub async fn get_seconds_by_bucket(
+    db: &Db,
+    user_id: &str,
+    buckets: &[StatsBucket],
+    tag_filter: &TagFilter,
+) -> Result<Vec<BucketSeconds>, anyhow::Error> {
+    let segments = segments_for_buckets(buckets, true);
+
+    return get_seconds_by_segment(db, user_id, &segments, tag_filter).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tasks::Task;
+
+    fn naive(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        s.parse::<DateTime<Utc>>().unwrap()
+    }
+
+    #[test]
+    fn test_buckets_for_range() {
+        let buckets = buckets_for_range(
+            &StatsPrecision::Month,
+            &naive("2024-01-15 00:00:00"),
+            &naive("2024-03-10 23:59:59"),
+            &chrono_tz::Europe::Helsinki,
+        )
+        .unwrap();
+
+        assert_eq!(buckets.len(), 3);
+        assert_eq!(buckets[0].date, naive("2024-01-01 00:00:00"));
+        assert_eq!(buckets[0].start, utc("2024-01-14T22:00:00Z"));
+        assert_eq!(buckets[0].end, utc("2024-01-31T22:00:00Z"));
+        assert_eq!(buckets[2].date, naive("2024-03-01 00:00:00"));
+        assert_eq!(buckets[2].end, utc("2024-03-10T21:59:59.000001Z"));
+    }
+
+    #[test]
+    fn test_segments_for_buckets() {
+        let buckets = vec![StatsBucket {
+            date: naive("2024-01-01 00:00:00"),
+            start: utc("2023-12-31T22:00:00Z"),
+            end: utc("2024-01-31T22:00:00Z"),
+        }];
+
+        let segments = segments_for_buckets(&buckets, true);
+
+        assert_eq!(
+            segments.rollup_from,
+            vec![NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()]
+        );
+        assert_eq!(
+            segments.rollup_to,
+            vec![NaiveDate::from_ymd_opt(2024, 1, 31).unwrap()]
+        );
+        assert_eq!(
+            segments.raw_from,
+            vec![utc("2023-12-31T22:00:00Z"), utc("2024-01-31T00:00:00Z")]
+        );
+        assert_eq!(
+            segments.raw_to,
+            vec![utc("2024-01-01T00:00:00Z"), utc("2024-01-31T22:00:00Z")]
+        );
+
+        let raw_only = segments_for_buckets(&buckets, false);
+        assert!(raw_only.rollup_dates.is_empty());
+        assert_eq!(raw_only.raw_from, vec![buckets[0].start]);
+        assert_eq!(raw_only.raw_to, vec![buckets[0].end]);
+    }
+
+    #[test]
+    fn test_segments_for_buckets_shorter_than_a_day() {
+        let buckets = vec![StatsBucket {
+            date: naive("2024-01-01 00:00:00"),
+            start: utc("2023-12-31T22:00:00Z"),
+            end: utc("2024-01-01T22:00:00Z"),
+        }];
+
+        let segments = segments_for_buckets(&buckets, true);
+
+        assert!(segments.rollup_dates.is_empty());
+        assert_eq!(segments.raw_from, vec![buckets[0].start]);
+        assert_eq!(segments.raw_to, vec![buckets[0].end]);
+    }
+
+    async fn insert_task(
+        db: &Db,
+        user_id: &str,
+        tag_id: &str,
+        start_at: &str,
+        seconds: i32,
+    ) -> Task {
+        let start_at = utc(start_at);
+
+        let task = Task {
+            id: crate::create_id(),
+            user_id: user_id.to_owned(),
+            tag_id: tag_id.to_owned(),
+            is_manual: true,
+            seconds,
+            start_at,
+            end_at: start_at + Duration::seconds(seconds.into()),
+        };
+
+        crate::tasks::insert(db, &task).await.unwrap();
+
+        return task;
+    }
+
+    async fn assert_paths_match(
+        db: &Db,
+        user_id: &str,
+        precision: &StatsPrecision,
+        tz: &chrono_tz::Tz,
+    ) {
+        let buckets = buckets_for_range(
+            precision,
+            &naive("2023-12-01 00:00:00"),
+            &naive("2024-03-31 23:59:59"),
+            tz,
+        )
+        .unwrap();
+
+        let from_rollups = get_seconds_by_segment(
+            db,
+            user_id,
+            &segments_for_buckets(&buckets, true),
+            &TagFilter::default(),
+        )
+        .await
+        .unwrap();
+
+        let from_tasks = get_seconds_by_segment(
+            db,
+            user_id,
+            &segments_for_buckets(&buckets, false),
+            &TagFilter::default(),
+        )
+        .await
+        .unwrap();
+
+        assert!(!from_tasks.is_empty());
+        assert_eq!(from_rollups, from_tasks, "{precision} in {tz}");
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_rollups_match_raw_tasks(db: Db) {
+        let user = crate::users::create(&db, "rollups@test.local")
+            .await
+            .unwrap();
+        let work = crate::tags::insert(&db, &user.id, "work", "#d13c4b")
+            .await
+            .unwrap();
+        let gym = crate::tags::insert(&db, &user.id, "gym", "#1287A8")
+            .await
+            .unwrap();
+
+        insert_task(&db, &user.id, &work.id, "2023-12-31T23:30:00Z", 3600).await;
+        insert_task(&db, &user.id, &work.id, "2024-01-01T00:10:00Z", 1800).await;
+        insert_task(&db, &user.id, &gym.id, "2024-01-15T12:00:00Z", 5400).await;
+        insert_task(&db, &user.id, &work.id, "2024-02-29T21:59:00Z", 600).await;
+        insert_task(&db, &user.id, &gym.id, "2024-03-31T22:30:00Z", 900).await;
+
+        let mut moved = insert_task(&db, &user.id, &work.id, "2024-02-10T10:00:00Z", 1200).await;
+        moved.start_at = utc("2024-02-11T23:45:00Z");
+        moved.tag_id = gym.id.to_owned();
+        crate::tasks::update(&db, &moved).await.unwrap();
+
+        let deleted = insert_task(&db, &user.id, &work.id, "2024-01-20T08:00:00Z", 999).await;
+        crate::tasks::delete(&db, &user.id, &deleted.id)
+            .await
+            .unwrap();
+
+        for tz in [
+            chrono_tz::Tz::UTC,
+            chrono_tz::Europe::Helsinki,
+            chrono_tz::America::Los_Angeles,
+        ] {
+            for precision in [
+                StatsPrecision::Day,
+                StatsPrecision::Week,
+                StatsPrecision::Month,
+            ] {
+                assert_paths_match(&db, &user.id, &precision, &tz).await;
+            }
+        }
+
+        // rollups kept current by insert, update and delete equal a fresh backfill
+        let kept_current = sqlx::query!("SELECT * FROM task_daily_rollups ORDER BY tag_id, day")
+            .fetch_all(&db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| (row.tag_id, row.day, row.seconds, row.sessions))
+            .collect::<Vec<_>>();
+
+        backfill(&db).await.unwrap();
+
+        let backfilled = sqlx::query!("SELECT * FROM task_daily_rollups ORDER BY tag_id, day")
+            .fetch_all(&db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| (row.tag_id, row.day, row.seconds, row.sessions))
+            .collect::<Vec<_>>();
+
+        assert_eq!(kept_current, backfilled);
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_concurrent_refreshes_of_a_day(db: Db) {
+        let user = crate::users::create(&db, "concurrent@test.local")
+            .await
+            .unwrap();
+        let work = crate::tags::insert(&db, &user.id, "work", "#d13c4b")
+            .await
+            .unwrap();
+
+        // like a notification action and an api token script adding tasks at once
+        let inserts = (0..8)
+            .map(|minute| {
+                let db = db.clone();
+                let user_id = user.id.to_owned();
+                let tag_id = work.id.to_owned();
+
+                return tokio::spawn(async move {
+                    insert_task(
+                        &db,
+                        &user_id,
+                        &tag_id,
+                        &format!("2024-01-15T12:0{minute}:00Z"),
+                        60,
+                    )
+                    .await
+                });
+            })
+            .collect::<Vec<_>>();
+
+        for insert in inserts {
+            insert.await.unwrap();
+        }
+
+        let rollup = sqlx::query!("SELECT seconds, sessions FROM task_daily_rollups")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(rollup.seconds, 8 * 60);
+        assert_eq!(rollup.sessions, 8);
+    }
+}
@@ -1,12 +1,17 @@
 use crate::{create_id, Db};
 use anyhow::Context;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
+use sqlx::postgres::types::PgInterval;
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct Session {
     pub id: String,
     pub user_id: String,
     pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
 }
 
 pub async fn get_one(
@@ -30,21 +35,49 @@ pub async fn get_one(
     return Ok(session);
 }
 
+/// Sessions that haven't expired at `now`, the most recently used first.
+pub async fn get_active(
+    db: &Db,
+    user_id: &str,
+    now: &DateTime<Utc>,
+) -> Result<Vec<Session>, anyhow::Error> {
+    let sessions = sqlx::query_as!(
+        Session,
+        r#"
+            SELECT * FROM sessions
+            WHERE user_id = $1 AND expires_at > $2
+            ORDER BY COALESCE(last_seen_at, created_at) DESC
+        "#,
+        user_id,
+        now
+    )
+    .fetch_all(db)
+    .await
+    .context("error fetching sessions")?;
+
+    return Ok(sessions);
+}
+
 pub async fn insert(
     db: &Db,
     user_id: &str,
     expires_at: &DateTime<Utc>,
+    user_agent: Option<&str>,
+    ip: Option<&str>,
 ) -> Result<String, anyhow::Error> {
     let session_id = create_id();
 
     sqlx::query!(
         r#"
-            INSERT INTO sessions (id, user_id, expires_at)
-            VALUES ($1, $2, $3)
+            INSERT INTO sessions (id, user_id, expires_at, created_at, user_agent, ip)
+            VALUES ($1, $2, $3, $4, $5, $6)
         "#,
         session_id,
         user_id,
-        expires_at
+        expires_at,
+        Utc::now(),
+        user_agent,
+        ip
     )
     .execute(db)
     .await
@@ -53,31 +86,43 @@ pub async fn insert(
     return Ok(session_id);
 }
 
+/// Records that the session was used at `now` and extends it by `idle_timeout`,
+/// never past `max_age` from its creation. Returns the new expiry, `None` when
+/// the session doesn't exist or has already expired.
 pub async fn update_expires_at(
     db: &Db,
     session_id: &str,
     user_id: &str,
-    expires_at: &DateTime<Utc>,
-) -> Result<bool, anyhow::Error> {
-    let res = sqlx::query!(
+    now: &DateTime<Utc>,
+    idle_timeout: &Duration,
+    max_age: &Duration,
+) -> Result<Option<DateTime<Utc>>, anyhow::Error> {
+    let max_age =
+        PgInterval::try_from(*max_age).map_err(|e| anyhow::anyhow!("invalid max age: {e}"))?;
+
+    let expires_at = sqlx::query_scalar!(
         r#"
             UPDATE sessions
-            SET expires_at = $1
-            WHERE id = $2 AND user_id = $3
+            SET expires_at = LEAST($4, created_at + $5), last_seen_at = $3
+            WHERE id = $1 AND user_id = $2
+            AND expires_at > $3 AND created_at + $5 > $3
+            RETURNING expires_at
         "#,
-        expires_at,
         session_id,
-        user_id
+        user_id,
+        now,
+        *now + *idle_timeout,
+        max_age
     )
-    .execute(db)
+    .fetch_optional(db)
     .await
     .context("error updating session")?;
 
-    return Ok(res.rows_affected() == 1);
+    return Ok(expires_at);
 }
 
-pub async fn delete(db: &Db, session_id: &str, user_id: &str) -> Result<(), anyhow::Error> {
-    sqlx::query!(
+pub async fn delete(db: &Db, session_id: &str, user_id: &str) -> Result<bool, anyhow::Error> {
+    let res = sqlx::query!(
         r#"
             DELETE FROM sessions
             WHERE id = $1 AND user_id = $2
@@ -89,5 +134,159 @@ pub async fn delete(db: &Db, session_id: &str, user_id: &str) -> Result<(), anyh
     .await
     .context("error deleting session")?;
 
-    return Ok(());
+    return Ok(res.rows_affected() == 1);
+}
+
+/// Deletes every session of the user except `session_id`, returns how many were deleted.
+pub async fn delete_others(db: &Db, session_id: &str, user_id: &str) -> Result<u64, anyhow::Error> {
+    let res = sqlx::query!(
+        r#"
+            DELETE FROM sessions
+            WHERE user_id = $1 AND id != $2
+        "#,
+        user_id,
+        session_id
+    )
+    .execute(db)
+    .await
+    .context("error deleting other sessions")?;
+
+    return Ok(res.rows_affected());
+}
+
+/// Deletes the sessions that expired by `now`, returns how many were deleted.
+pub async fn delete_expired(db: &Db, now: &DateTime<Utc>) -> Result<u64, anyhow::Error> {
+    let res = sqlx::query!(
+        r#"
+            DELETE FROM sessions
+            WHERE expires_at <= $1
+        "#,
+        now
+    )
+    .execute(db)
+    .await
+    .context("error deleting expired sessions")?;
+
+    return Ok(res.rows_affected());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::SubsecRound;
+
+    #[sqlx::test]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_list_and_revoke_sessions(db: Db) {
+        let user = crate::users::create(&db, "sessions@test.local")
+            .await
+            .unwrap();
+        let other = crate::users::create(&db, "other@test.local").await.unwrap();
+        // postgres keeps microseconds
+        let now = Utc::now().trunc_subsecs(6);
+        let expires_at = now + Duration::days(30);
+
+        let current = insert(
+            &db,
+            &user.id,
+            &expires_at,
+            Some("Firefox"),
+            Some("10.0.0.1"),
+        )
+        .await
+        .unwrap();
+        let phone = insert(&db, &user.id, &expires_at, None, None)
+            .await
+            .unwrap();
+        let expired = insert(&db, &user.id, &(now - Duration::days(1)), None, None)
+            .await
+            .unwrap();
+        let others = insert(&db, &other.id, &expires_at, None, None)
+            .await
+            .unwrap();
+
+        let seen_at = Utc::now().trunc_subsecs(6);
+        assert!(update_expires_at(
+            &db,
+            &current,
+            &user.id,
+            &seen_at,
+            &Duration::days(30),
+            &Duration::days(90)
+        )
+        .await
+        .unwrap()
+        .is_some());
+
+        let sessions = get_active(&db, &user.id, &now).await.unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].id, current);
+        assert_eq!(sessions[0].last_seen_at, Some(seen_at));
+        assert_eq!(sessions[0].user_agent.as_deref(), Some("Firefox"));
+        assert_eq!(sessions[0].ip.as_deref(), Some("10.0.0.1"));
+        assert_eq!(sessions[1].id, phone);
+
+        // only the owner can revoke a session
+        assert!(!delete(&db, &phone, &other.id).await.unwrap());
+        assert!(delete(&db, &phone, &user.id).await.unwrap());
+
+        assert_eq!(delete_others(&db, &current, &user.id).await.unwrap(), 1);
+        assert!(get_one(&db, &current, &user.id).await.unwrap().is_some());
+        assert!(get_one(&db, &expired, &user.id).await.unwrap().is_none());
+        assert!(get_one(&db, &others, &other.id).await.unwrap().is_some());
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_session_limits(db: Db) {
+        let user = crate::users::create(&db, "limits@test.local")
+            .await
+            .unwrap();
+        let now = Utc::now().trunc_subsecs(6);
+        let idle_timeout = Duration::days(30);
+        let max_age = Duration::days(90);
+
+        let session_id = insert(&db, &user.id, &(now + idle_timeout), None, None)
+            .await
+            .unwrap();
+        sqlx::query!(
+            "UPDATE sessions SET created_at = $1 WHERE id = $2",
+            now - Duration::days(80),
+            session_id
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        // extended up to the max age only
+        let expires_at =
+            update_expires_at(&db, &session_id, &user.id, &now, &idle_timeout, &max_age)
+                .await
+                .unwrap();
+        assert_eq!(expires_at, Some(now + Duration::days(10)));
+
+        // past the max age
+        let later = now + Duration::days(10);
+        assert_eq!(
+            update_expires_at(&db, &session_id, &user.id, &later, &idle_timeout, &max_age)
+                .await
+                .unwrap(),
+            None
+        );
+
+        // idle for too long
+        let idle = insert(&db, &user.id, &(now - Duration::seconds(1)), None, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            update_expires_at(&db, &idle, &user.id, &now, &idle_timeout, &max_age)
+                .await
+                .unwrap(),
+            None
+        );
+
+        assert_eq!(delete_expired(&db, &now).await.unwrap(), 1);
+        assert!(get_one(&db, &idle, &user.id).await.unwrap().is_none());
+        assert!(get_one(&db, &session_id, &user.id).await.unwrap().is_some());
+    }
 }
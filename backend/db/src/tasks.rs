@@ -1,6 +1,6 @@
-use crate::Db;
+use crate::{rollups, Db};
 use anyhow::Context;
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, Timelike, Utc};
 use std::str::FromStr;
 
 #[derive(Debug, serde::Serialize)]
@@ -139,7 +139,55 @@ pub async fn get_ongoing(db: &Db, user_id: &str) -> Result<Option<TaskWithTag>,
     return Ok(ongoing_task);
 }
 
+pub async fn get_one(
+    db: &Db,
+    user_id: &str,
+    task_id: &str,
+) -> Result<Option<TaskWithTag>, anyhow::Error> {
+    let task = sqlx::query_as!(
+        TaskWithTag,
+        r#"
+            SELECT tasks.*, tags.color AS tag_color, tags.label AS tag_label
+            FROM tasks
+            INNER JOIN tags ON tasks.tag_id = tags.id
+            WHERE tasks.user_id = $1
+            AND tasks.id = $2
+        "#,
+        user_id,
+        task_id,
+    )
+    .fetch_optional(db)
+    .await
+    .context("error fetching task")?;
+
+    return Ok(task);
+}
+
+/// Tasks with their current tag, for rendering notifications when they're sent.
+pub async fn get_by_ids(
+    db: &Db,
+    task_ids: &Vec<String>,
+) -> Result<Vec<TaskWithTag>, anyhow::Error> {
+    let tasks = sqlx::query_as!(
+        TaskWithTag,
+        r#"
+            SELECT tasks.*, tags.color AS tag_color, tags.label AS tag_label
+            FROM tasks
+            INNER JOIN tags ON tasks.tag_id = tags.id
+            WHERE tasks.id = ANY($1)
+        "#,
+        task_ids,
+    )
+    .fetch_all(db)
+    .await
+    .context("error fetching tasks")?;
+
+    return Ok(tasks);
+}
+
 pub async fn insert(db: &Db, task: &Task) -> Result<(), anyhow::Error> {
+    let mut tx = db.begin().await.context("error starting transaction")?;
+
     sqlx::query!(
         r#"
             INSERT INTO tasks (id, user_id, tag_id, is_manual, start_at, end_at, seconds)
@@ -153,14 +201,36 @@ pub async fn insert(db: &Db, task: &Task) -> Result<(), anyhow::Error> {
         task.end_at,
         task.seconds,
     )
-    .execute(db)
+    .execute(&mut *tx)
     .await
     .context("error inserting task")?;
 
+    rollups::refresh_days(&mut tx, &task.user_id, &[rollups::utc_day(&task.start_at)])
+        .await
+        .context("error refreshing rollups")?;
+
+    tx.commit().await.context("error committing transaction")?;
+
     return Ok(());
 }
 
 pub async fn update(db: &Db, task: &Task) -> Result<(), anyhow::Error> {
+    let mut tx = db.begin().await.context("error starting transaction")?;
+
+    let previous = sqlx::query!(
+        r#"
+            SELECT start_at FROM tasks
+            WHERE id = $1
+            AND user_id = $2
+            FOR UPDATE
+        "#,
+        task.id,
+        task.user_id,
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .context("error fetching task")?;
+
     sqlx::query!(
         r#"
             UPDATE tasks
@@ -176,27 +246,49 @@ pub async fn update(db: &Db, task: &Task) -> Result<(), anyhow::Error> {
         task.id,
         task.user_id,
     )
-    .execute(db)
+    .execute(&mut *tx)
     .await
     .context("error updating task")?;
 
+    let mut days = vec![rollups::utc_day(&task.start_at)];
+    if let Some(previous) = previous {
+        days.push(rollups::utc_day(&previous.start_at));
+    }
+
+    rollups::refresh_days(&mut tx, &task.user_id, &days)
+        .await
+        .context("error refreshing rollups")?;
+
+    tx.commit().await.context("error committing transaction")?;
+
     return Ok(());
 }
 
 pub async fn delete(db: &Db, user_id: &str, task_id: &str) -> Result<(), anyhow::Error> {
-    sqlx::query!(
+    let mut tx = db.begin().await.context("error starting transaction")?;
+
+    let deleted = sqlx::query!(
         r#"
             DELETE FROM tasks
             WHERE id = $1
             AND user_id = $2
+            RETURNING start_at
         "#,
         task_id,
         user_id
     )
-    .execute(db)
+    .fetch_optional(&mut *tx)
     .await
     .context("error deleting task")?;
 
+    if let Some(deleted) = deleted {
+        rollups::refresh_days(&mut tx, user_id, &[rollups::utc_day(&deleted.start_at)])
+            .await
+            .context("error refreshing rollups")?;
+    }
+
+    tx.commit().await.context("error committing transaction")?;
+
     return Ok(());
 }
 
@@ -231,9 +323,9 @@ impl AsRef<str> for StatsPrecision {
     }
 }
 
-impl ToString for StatsPrecision {
-    fn to_string(&self) -> String {
-        self.as_ref().to_string()
+impl std::fmt::Display for StatsPrecision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_ref())
     }
 }
 
@@ -258,6 +350,14 @@ pub struct StatByTag {
     pub hours: f64,
 }
 
+/// Restricts stats queries to a subset of the user's tags.
+/// An empty `include` means every tag, `exclude` is applied after it.
+#[derive(Debug, Default)]
+pub struct TagFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
 pub async fn get_hours_by_stats(
     db: &Db,
     user_id: &str,
@@ -265,75 +365,146 @@ pub async fn get_hours_by_stats(
     start: &NaiveDateTime,
     end: &NaiveDateTime,
     tz: &chrono_tz::Tz,
+    tag_filter: &TagFilter,
 ) -> Result<Vec<StatByDate>, anyhow::Error> {
-    let data = sqlx::query!(
+    let buckets = rollups::buckets_for_range(precision, start, end, tz)
+        .context("error splitting range into buckets")?;
+
+    let data = rollups::get_seconds_by_bucket(db, user_id, &buckets, tag_filter)
+        .await
+        .context("error fetching seconds by bucket")?;
+
+    let mut stats: Vec<StatByDate> = vec![];
+
+    for stat in data {
+        let stat_by_tag = StatByTag {
+            tag_id: stat.tag_id,
+            tag_label: stat.tag_label,
+            tag_color: stat.tag_color,
+            hours: stat.seconds as f64 / 3600.0,
+        };
+
+        // rows are ordered by date
+        match stats.last_mut() {
+            Some(last) if last.date == stat.date => last.stats.push(stat_by_tag),
+            _ => stats.push(StatByDate {
+                date: stat.date,
+                stats: vec![stat_by_tag],
+            }),
+        }
+    }
+
+    return Ok(stats);
+}
+
+/// Tracked seconds indexed by `[weekday][hour]`,
+/// weekday 0 is monday and hours are in the requested timezone.
+pub type WeekdayHourMatrix = [[i64; 24]; 7];
+
+/// Splits the span between `start` and `end` into the local hours it covers
+/// and adds the seconds spent in each one to `matrix`.
+pub fn add_to_weekday_hour_matrix(
+    matrix: &mut WeekdayHourMatrix,
+    start: &DateTime<Utc>,
+    end: &DateTime<Utc>,
+    tz: &chrono_tz::Tz,
+) {
+    let mut current = *start;
+
+    while current < *end {
+        let local = current.with_timezone(tz);
+        let seconds_into_hour = i64::from(local.minute() * 60 + local.second());
+        let next_hour = current - Duration::nanoseconds(i64::from(local.nanosecond()))
+            + Duration::seconds(3600 - seconds_into_hour);
+        let segment_end = next_hour.min(*end);
+
+        let weekday = local.weekday().num_days_from_monday() as usize;
+        let hour = local.hour() as usize;
+        matrix[weekday][hour] += (segment_end - current).num_seconds();
+
+        current = segment_end;
+    }
+}
+
+pub async fn get_weekday_hour_stats(
+    db: &Db,
+    user_id: &str,
+    start: &DateTime<Utc>,
+    end: &DateTime<Utc>,
+    tz: &chrono_tz::Tz,
+    tag_filter: &TagFilter,
+) -> Result<WeekdayHourMatrix, anyhow::Error> {
+    let spans = sqlx::query!(
         r#"
             SELECT
-                tasks.tag_id,
-                tags.label as tag_label,
-                tags.color as tag_color,
-                date_trunc($1, start_at AT TIME ZONE $5) AS date,
-                CAST(SUM(tasks.seconds / 3600.0) as float) AS hours
+                start_at,
+                end_at
             FROM
                 tasks
-            JOIN
-                tags ON tasks.tag_id = tags.id
             WHERE
-                tasks.user_id = $2
-                AND start_at AT TIME ZONE $5 >= $3
-                AND start_at AT TIME ZONE $5 <= $4
-            GROUP BY
-                date,
-                tasks.tag_id,
-                tag_label,
-                tag_color
-            ORDER BY
-                date ASC;
+                user_id = $1
+                AND end_at > $2
+                AND start_at < $3
+                AND (cardinality($4::text[]) = 0 OR tag_id = ANY($4))
+                AND NOT (tag_id = ANY($5))
         "#,
-        precision.as_ref(),
         user_id,
         start,
         end,
-        tz.name(),
+        &tag_filter.include,
+        &tag_filter.exclude,
     )
     .fetch_all(db)
     .await
     .context("error fetching tasks")?;
 
-    let mut stats: Vec<StatByDate> = vec![];
+    // ongoing tasks only count up to now
+    let until = (*end).min(Utc::now());
 
-    // TODO: horrible looking
-    for stat in data {
-        match stat.date {
-            Some(stat_date) => match stat.hours {
-                Some(stat_hours) => {
-                    let existing = stats.iter().position(|s| s.date == stat_date);
-
-                    match existing {
-                        Some(pos) => stats[pos].stats.push(StatByTag {
-                            tag_id: stat.tag_id,
-                            tag_label: stat.tag_label,
-                            tag_color: stat.tag_color,
-                            hours: stat_hours,
-                        }),
-                        None => stats.push(StatByDate {
-                            date: stat_date,
-                            stats: vec![StatByTag {
-                                tag_id: stat.tag_id,
-                                tag_label: stat.tag_label,
-                                tag_color: stat.tag_color,
-                                hours: stat_hours,
-                            }],
-                        }),
-                    }
-                }
-                None => continue,
-            },
-            None => continue,
-        }
+    let mut matrix: WeekdayHourMatrix = [[0; 24]; 7];
+
+    for span in spans {
+        let span_start = span.start_at.max(*start);
+        let span_end = span.end_at.min(until);
+
+        add_to_weekday_hour_matrix(&mut matrix, &span_start, &span_end, tz);
     }
 
-    return Ok(stats);
+    return Ok(matrix);
+}
+
+/// Returns the current and longest streak of days with at least `min_seconds`.
+/// The current streak ends at `today` and is still alive if only
+/// `today` is missing time, like on GitHub. It's 0 when `today` isn't one of the days.
+pub fn calendar_streaks(day_seconds: &[i64], min_seconds: i64, today: Option<usize>) -> (i64, i64) {
+    let counts = |seconds: &i64| *seconds >= min_seconds;
+
+    let mut longest = 0;
+    let mut running = 0;
+
+    for seconds in day_seconds {
+        running = if counts(seconds) { running + 1 } else { 0 };
+        longest = longest.max(running);
+    }
+
+    let current = match today {
+        Some(today) if today < day_seconds.len() => {
+            let mut end = today;
+
+            if !counts(&day_seconds[end]) && end > 0 {
+                end -= 1;
+            }
+
+            day_seconds[..=end]
+                .iter()
+                .rev()
+                .take_while(|seconds| counts(seconds))
+                .count() as i64
+        }
+        _ => 0,
+    };
+
+    return (current, longest);
 }
 
 #[derive(serde::Serialize, Debug)]
@@ -349,38 +520,282 @@ pub async fn get_tag_distribution_stats(
     start: &NaiveDateTime,
     end: &NaiveDateTime,
     tz: &chrono_tz::Tz,
+    tag_filter: &TagFilter,
 ) -> Result<Vec<TagDistributionStat>, anyhow::Error> {
-    let tag_distribution = sqlx::query_as!(
-        TagDistributionStat,
+    let bucket = rollups::StatsBucket {
+        date: *start,
+        start: rollups::local_to_utc(start, tz),
+        end: rollups::local_to_utc(end, tz) + Duration::microseconds(1),
+    };
+
+    let data = rollups::get_seconds_by_bucket(db, user_id, &[bucket], tag_filter)
+        .await
+        .context("error fetching seconds by bucket")?;
+
+    let mut tag_distribution = data
+        .into_iter()
+        .map(|stat| TagDistributionStat {
+            tag_label: stat.tag_label,
+            tag_color: stat.tag_color,
+            seconds: Some(stat.seconds),
+        })
+        .collect::<Vec<TagDistributionStat>>();
+
+    tag_distribution.sort_by_key(|stat| std::cmp::Reverse(stat.seconds));
+
+    return Ok(tag_distribution);
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct TagSummaryStat {
+    pub sessions: i64,
+    pub seconds: Option<i64>,
+    pub avg_session_seconds: Option<f64>,
+    pub first_used_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// Session count and length for one tag within the range,
+/// first and last use are over the whole lifetime of the tag.
+pub async fn get_tag_summary_stats(
+    db: &Db,
+    user_id: &str,
+    tag_id: &str,
+    start: &NaiveDateTime,
+    end: &NaiveDateTime,
+    tz: &chrono_tz::Tz,
+) -> Result<TagSummaryStat, anyhow::Error> {
+    let summary = sqlx::query_as!(
+        TagSummaryStat,
+        r#"
+            SELECT
+                COUNT(*) FILTER (
+                    WHERE start_at AT TIME ZONE $5 >= $3
+                    AND start_at AT TIME ZONE $5 <= $4
+                ) AS "sessions!",
+                SUM(seconds) FILTER (
+                    WHERE start_at AT TIME ZONE $5 >= $3
+                    AND start_at AT TIME ZONE $5 <= $4
+                ) AS seconds,
+                CAST(AVG(seconds) FILTER (
+                    WHERE start_at AT TIME ZONE $5 >= $3
+                    AND start_at AT TIME ZONE $5 <= $4
+                ) AS float) AS avg_session_seconds,
+                MIN(start_at) AS first_used_at,
+                MAX(end_at) AS last_used_at
+            FROM
+                tasks
+            WHERE
+                user_id = $1
+                AND tag_id = $2;
+        "#,
+        user_id,
+        tag_id,
+        start,
+        end,
+        tz.name(),
+    )
+    .fetch_one(db)
+    .await
+    .context("error fetching tag summary")?;
+
+    return Ok(summary);
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct SessionStat {
+    pub sessions: i64,
+    pub manual_sessions: i64,
+    pub timer_sessions: i64,
+    pub active_days: i64,
+    pub median_seconds: Option<f64>,
+    pub p90_seconds: Option<f64>,
+    pub longest_seconds: Option<i32>,
+}
+
+pub async fn get_session_stats(
+    db: &Db,
+    user_id: &str,
+    start: &NaiveDateTime,
+    end: &NaiveDateTime,
+    tz: &chrono_tz::Tz,
+    tag_filter: &TagFilter,
+) -> Result<SessionStat, anyhow::Error> {
+    let session_stats = sqlx::query_as!(
+        SessionStat,
         r#"
             SELECT
-                tags.label AS tag_label,
-                tags.color AS tag_color,
-                SUM(seconds) AS seconds
+                COUNT(*) AS "sessions!",
+                COUNT(*) FILTER (WHERE is_manual) AS "manual_sessions!",
+                COUNT(*) FILTER (WHERE NOT is_manual) AS "timer_sessions!",
+                COUNT(DISTINCT date_trunc('day', start_at AT TIME ZONE $4)) AS "active_days!",
+                CAST(percentile_cont(0.5) WITHIN GROUP (ORDER BY seconds) AS float) AS median_seconds,
+                CAST(percentile_cont(0.9) WITHIN GROUP (ORDER BY seconds) AS float) AS p90_seconds,
+                MAX(seconds) AS longest_seconds
             FROM
                 tasks
-            INNER JOIN
-                tags
-            ON
-                tasks.tag_id = tags.id
             WHERE
-                tasks.user_id = $1
-                AND tasks.start_at AT TIME ZONE $4 >= $2
-                AND tasks.start_at AT TIME ZONE $4 <= $3
+                user_id = $1
+                AND start_at AT TIME ZONE $4 >= $2
+                AND start_at AT TIME ZONE $4 <= $3
+                AND (cardinality($5::text[]) = 0 OR tag_id = ANY($5))
+                AND NOT (tag_id = ANY($6));
+        "#,
+        user_id,
+        start,
+        end,
+        tz.name(),
+        &tag_filter.include,
+        &tag_filter.exclude,
+    )
+    .fetch_one(db)
+    .await
+    .context("error fetching session stats")?;
+
+    return Ok(session_stats);
+}
+
+/// Lower bounds of the session length histogram buckets, in seconds.
+/// The last bucket has no upper bound.
+pub static SESSION_LENGTH_BUCKETS: [i32; 7] =
+    [0, 5 * 60, 15 * 60, 30 * 60, 60 * 60, 90 * 60, 120 * 60];
+
+#[derive(serde::Serialize, Debug)]
+pub struct SessionLengthBucket {
+    pub min_seconds: i32,
+    pub max_seconds: Option<i32>,
+    pub sessions: i64,
+}
+
+pub async fn get_session_length_histogram(
+    db: &Db,
+    user_id: &str,
+    start: &NaiveDateTime,
+    end: &NaiveDateTime,
+    tz: &chrono_tz::Tz,
+    tag_filter: &TagFilter,
+) -> Result<Vec<SessionLengthBucket>, anyhow::Error> {
+    let counts = sqlx::query!(
+        r#"
+            SELECT
+                width_bucket(seconds, $7::int[]) AS "bucket!",
+                COUNT(*) AS "sessions!"
+            FROM
+                tasks
+            WHERE
+                user_id = $1
+                AND start_at AT TIME ZONE $4 >= $2
+                AND start_at AT TIME ZONE $4 <= $3
+                AND (cardinality($5::text[]) = 0 OR tag_id = ANY($5))
+                AND NOT (tag_id = ANY($6))
             GROUP BY
-                tag_label,
-                tag_color
-            ORDER BY
-                seconds DESC;
+                1;
         "#,
         user_id,
         start,
         end,
-        tz.name()
+        tz.name(),
+        &tag_filter.include,
+        &tag_filter.exclude,
+        &SESSION_LENGTH_BUCKETS,
     )
     .fetch_all(db)
     .await
-    .context("error fetching tasks")?;
+    .context("error fetching session length histogram")?;
+
+    let histogram = SESSION_LENGTH_BUCKETS
+        .iter()
+        .enumerate()
+        .map(|(i, min_seconds)| SessionLengthBucket {
+            min_seconds: *min_seconds,
+            max_seconds: SESSION_LENGTH_BUCKETS.get(i + 1).copied(),
+            // width_bucket numbers the buckets from 1
+            sessions: counts
+                .iter()
+                .find(|count| count.bucket == i as i32 + 1)
+                .map_or(0, |count| count.sessions),
+        })
+        .collect::<Vec<SessionLengthBucket>>();
+
+    return Ok(histogram);
+}
 
-    return Ok(tag_distribution);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        s.parse::<DateTime<Utc>>().unwrap()
+    }
+
+    #[test]
+    fn test_weekday_hour_matrix_splits_across_hours() {
+        let mut matrix: WeekdayHourMatrix = [[0; 24]; 7];
+
+        // monday 2024-01-01, 09:30 - 11:15 UTC
+        add_to_weekday_hour_matrix(
+            &mut matrix,
+            &utc("2024-01-01T09:30:00Z"),
+            &utc("2024-01-01T11:15:00Z"),
+            &chrono_tz::Tz::UTC,
+        );
+
+        assert_eq!(matrix[0][9], 30 * 60);
+        assert_eq!(matrix[0][10], 60 * 60);
+        assert_eq!(matrix[0][11], 15 * 60);
+        assert_eq!(matrix.iter().flatten().sum::<i64>(), 105 * 60);
+    }
+
+    #[test]
+    fn test_weekday_hour_matrix_uses_local_time() {
+        let mut matrix: WeekdayHourMatrix = [[0; 24]; 7];
+
+        // sunday 23:30 UTC is monday 01:30 in Helsinki
+        add_to_weekday_hour_matrix(
+            &mut matrix,
+            &utc("2024-01-07T23:30:00Z"),
+            &utc("2024-01-08T00:10:00Z"),
+            &chrono_tz::Europe::Helsinki,
+        );
+
+        assert_eq!(matrix[0][1], 30 * 60);
+        assert_eq!(matrix[0][2], 10 * 60);
+        assert_eq!(matrix[6].iter().sum::<i64>(), 0);
+    }
+
+    #[test]
+    fn test_weekday_hour_matrix_half_hour_offset() {
+        let mut matrix: WeekdayHourMatrix = [[0; 24]; 7];
+
+        // Asia/Kolkata is UTC+05:30, 04:00 UTC is 09:30 local
+        add_to_weekday_hour_matrix(
+            &mut matrix,
+            &utc("2024-01-01T04:00:00Z"),
+            &utc("2024-01-01T05:00:00Z"),
+            &chrono_tz::Asia::Kolkata,
+        );
+
+        assert_eq!(matrix[0][9], 30 * 60);
+        assert_eq!(matrix[0][10], 30 * 60);
+    }
+
+    #[test]
+    fn test_calendar_streaks() {
+        let day_seconds = [60, 60, 60, 0, 60, 30, 60, 60, 0];
+
+        // today still untracked, streak from yesterday carries on
+        assert_eq!(calendar_streaks(&day_seconds, 60, Some(8)), (2, 3));
+
+        // a gap before today breaks the current streak
+        assert_eq!(calendar_streaks(&day_seconds, 60, Some(6)), (1, 3));
+        assert_eq!(calendar_streaks(&day_seconds, 60, Some(5)), (1, 3));
+
+        assert_eq!(calendar_streaks(&day_seconds, 30, Some(7)), (4, 4));
+        assert_eq!(calendar_streaks(&day_seconds, 60, None), (0, 3));
+
+        // a year in the past has no current streak
+        assert_eq!(calendar_streaks(&[0, 60, 60], 60, Some(400)), (0, 2));
+        assert_eq!(calendar_streaks(&[0, 60, 60], 60, Some(3)), (0, 2));
+        assert_eq!(calendar_streaks(&[], 60, Some(0)), (0, 0));
+    }
 }
@@ -0,0 +1,69 @@
+//! Fixtures shared by the tests of this crate and the crates built on it.
+
+use crate::{
+    create_id,
+    notifications::{Notification, NotificationKind, NotificationStatus},
+    tasks::Task,
+    Db,
+};
+use chrono::{DateTime, Utc};
+
+/// Creates a user with a one minute task that just ended, returns their ids.
+pub async fn insert_task(db: &Db, email: &str) -> (String, String) {
+    let user = crate::users::create(db, email).await.unwrap();
+    let tag = crate::tags::insert(db, &user.id, "work", "#d13c4b")
+        .await
+        .unwrap();
+    let now = Utc::now();
+    let task = Task {
+        id: create_id(),
+        user_id: user.id.to_owned(),
+        tag_id: tag.id,
+        is_manual: false,
+        seconds: 60,
+        start_at: now,
+        end_at: now,
+    };
+    crate::tasks::insert(db, &task).await.unwrap();
+
+    return (user.id, task.id);
+}
+
+/// Creates a user with a task and a finished notification for it that is due now.
+pub async fn insert_notification(db: &Db, email: &str) -> Notification {
+    let (user_id, task_id) = insert_task(db, email).await;
+
+    return crate::notifications::insert(
+        db,
+        &user_id,
+        &task_id,
+        &NotificationKind::Finished,
+        "title",
+        "message",
+        &Utc::now(),
+    )
+    .await
+    .unwrap();
+}
+
+/// A pending notification about a task, without storing it.
+pub fn notification(kind: NotificationKind, now: &DateTime<Utc>) -> Notification {
+    return Notification {
+        id: "notification".to_owned(),
+        user_id: "user".to_owned(),
+        task_id: Some("task".to_owned()),
+        kind,
+        title: None,
+        message: None,
+        send_at: *now,
+        status: NotificationStatus::Pending,
+        attempts: 0,
+        next_attempt_at: *now,
+        last_error: None,
+        claimed_by: None,
+        claimed_until: None,
+        link: None,
+        reminder_kind: None,
+        reminder_value: None,
+    };
+}
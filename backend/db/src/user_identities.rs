@@ -0,0 +1,97 @@
+use crate::{users::User, Db};
+use anyhow::Context;
+use chrono::Utc;
+
+/// The user that logged in with `subject` at the OpenID Connect `issuer`.
+pub async fn get_user(db: &Db, issuer: &str, subject: &str) -> Result<Option<User>, anyhow::Error> {
+    let user = sqlx::query_as!(
+        User,
+        r#"
+            SELECT users.id, users.email, users.joined_at
+            FROM user_identities
+            JOIN users ON users.id = user_identities.user_id
+            WHERE user_identities.issuer = $1
+            AND user_identities.subject = $2
+        "#,
+        issuer,
+        subject
+    )
+    .fetch_optional(db)
+    .await
+    .context("error getting user by identity")?;
+
+    return Ok(user);
+}
+
+/// Links the login to the user, unless they already have another login at the
+/// issuer. Returns whether the login belongs to the user afterwards.
+pub async fn link(
+    db: &Db,
+    issuer: &str,
+    subject: &str,
+    user_id: &str,
+) -> Result<bool, anyhow::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO user_identities (issuer, subject, user_id, created_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT DO NOTHING
+        "#,
+        issuer,
+        subject,
+        user_id,
+        Utc::now()
+    )
+    .execute(db)
+    .await
+    .context("error inserting user identity")?;
+
+    // another login at the same time may have linked it first
+    let linked_user_id = sqlx::query_scalar!(
+        r#"
+            SELECT user_id
+            FROM user_identities
+            WHERE issuer = $1
+            AND subject = $2
+        "#,
+        issuer,
+        subject
+    )
+    .fetch_optional(db)
+    .await
+    .context("error getting user identity")?;
+
+    return Ok(linked_user_id.as_deref() == Some(user_id));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_link(db: Db) {
+        let user = crate::users::create(&db, "sso@test.local").await.unwrap();
+        let other = crate::users::create(&db, "other@test.local").await.unwrap();
+        let issuer = "https://sso.test.local";
+
+        assert!(get_user(&db, issuer, "user-1").await.unwrap().is_none());
+        assert!(link(&db, issuer, "user-1", &user.id).await.unwrap());
+        assert!(link(&db, issuer, "user-1", &user.id).await.unwrap());
+        assert_eq!(
+            get_user(&db, issuer, "user-1").await.unwrap().unwrap().id,
+            user.id
+        );
+
+        // the login belongs to someone else
+        assert!(!link(&db, issuer, "user-1", &other.id).await.unwrap());
+        // the user already has a login there
+        assert!(!link(&db, issuer, "user-2", &user.id).await.unwrap());
+        assert!(get_user(&db, issuer, "user-2").await.unwrap().is_none());
+
+        // another provider may use the same subject
+        assert!(link(&db, "https://other.test.local", "user-1", &other.id)
+            .await
+            .unwrap());
+    }
+}
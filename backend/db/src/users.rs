@@ -6,7 +6,6 @@ use chrono::{DateTime, Utc};
 pub struct User {
     pub id: String,
     pub email: String,
-    pub preferences: i64,
     pub joined_at: DateTime<Utc>,
 }
 
@@ -45,19 +44,17 @@ pub async fn get_by_id(db: &Db, user_id: &str) -> Result<Option<User>, anyhow::E
 pub async fn create(db: &Db, email: &str) -> Result<User, anyhow::Error> {
     let user = User {
         id: create_id(),
-        preferences: 0,
         email: email.to_string(),
         joined_at: Utc::now(),
     };
 
     sqlx::query!(
         r#"
-            INSERT INTO users (id, email, preferences, joined_at)
-            VALUES ($1, $2, $3, $4)
+            INSERT INTO users (id, email, joined_at)
+            VALUES ($1, $2, $3)
         "#,
         user.id,
         user.email,
-        user.preferences,
         user.joined_at
     )
     .execute(db)
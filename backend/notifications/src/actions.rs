@@ -0,0 +1,108 @@
+use crate::{
+    send::PushAction,
+    templates::{action_title, Locale},
+};
+use auth::action_token::{create_action_token, TaskAction};
+use chrono::{DateTime, Duration, Utc};
+use db::notifications::{Notification, NotificationKind};
+
+/// How long the buttons on a notification keep working after it's sent.
+pub static ACTION_TOKEN_TTL: once_cell::sync::Lazy<Duration> =
+    once_cell::sync::Lazy::new(|| Duration::minutes(30));
+
+/// The buttons for the task of `notification`, each with its own signed token.
+/// Reminders go off while the task is running, so they can stop it.
+/// Notifications that aren't about a task have none.
+pub fn task_actions(
+    secret: &str,
+    locale: &Locale,
+    notification: &Notification,
+    now: &DateTime<Utc>,
+) -> Vec<PushAction> {
+    let Some(task_id) = notification.task_id.as_deref() else {
+        return vec![];
+    };
+
+    let expires_at = *now + *ACTION_TOKEN_TTL;
+
+    let actions = match notification.kind {
+        NotificationKind::Reminder => vec![TaskAction::Stop, TaskAction::Extend],
+        NotificationKind::Finished => vec![TaskAction::Extend, TaskAction::StartBreak],
+        NotificationKind::Nudge | NotificationKind::Summary => vec![],
+    };
+
+    return actions
+        .into_iter()
+        .map(|action| PushAction {
+            action: action.as_ref().to_owned(),
+            title: action_title(locale, &action),
+            token: create_action_token(
+                secret,
+                &notification.user_id,
+                task_id,
+                &action,
+                &expires_at,
+            ),
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use auth::action_token::verify_action_token;
+    use db::test_utils::notification;
+
+    fn titles(actions: &[PushAction]) -> Vec<&str> {
+        return actions.iter().map(|a| a.title.as_str()).collect();
+    }
+
+    #[test]
+    fn test_task_actions_depend_on_the_kind() {
+        let now = Utc::now();
+
+        let reminder = notification(NotificationKind::Reminder, &now);
+        assert_eq!(
+            titles(&task_actions("secret", &Locale::En, &reminder, &now)),
+            vec!["Stop", "+5 min"]
+        );
+
+        let finished = notification(NotificationKind::Finished, &now);
+        assert_eq!(
+            titles(&task_actions("secret", &Locale::En, &finished, &now)),
+            vec!["+5 min", "Start break"]
+        );
+        assert_eq!(
+            titles(&task_actions("secret", &Locale::De, &finished, &now)),
+            vec!["+5 Min.", "Pause starten"]
+        );
+
+        let summary = Notification {
+            task_id: None,
+            ..notification(NotificationKind::Summary, &now)
+        };
+        assert!(task_actions("secret", &Locale::En, &summary, &now).is_empty());
+    }
+
+    #[test]
+    fn test_task_actions_are_scoped_to_the_task() {
+        let now = Utc::now();
+        let actions = task_actions(
+            "secret",
+            &Locale::En,
+            &notification(NotificationKind::Reminder, &now),
+            &now,
+        );
+
+        for action in actions {
+            let token = verify_action_token("secret", &action.token, &now).unwrap();
+
+            assert_eq!(token.user_id, "user");
+            assert_eq!(token.task_id, "task");
+            assert_eq!(token.action.as_ref(), action.action);
+            assert!(
+                verify_action_token("secret", &action.token, &(now + Duration::hours(1))).is_err()
+            );
+        }
+    }
+}
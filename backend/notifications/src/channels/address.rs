@@ -0,0 +1,112 @@
+//! Keeps webhook and ntfy requests away from the server's own network,
+//! the targets are URLs of the user's choosing.
+
+use anyhow::Context;
+use std::net::{IpAddr, SocketAddr};
+
+/// Whether `ip` is reachable on the internet, as opposed to loopback,
+/// private, link-local and other special purpose addresses.
+pub fn is_public(ip: &IpAddr) -> bool {
+    return match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // "this network", shared address space, IETF protocol
+                // assignments, benchmarking and reserved
+                || a == 0
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 192 && b == 0 && c == 0)
+                || (a == 198 && (18..20).contains(&b))
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(&IpAddr::V4(ip)),
+            None => {
+                !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local()
+                    // documentation
+                    || ip.segments()[..2] == [0x2001, 0xdb8])
+            }
+        },
+    };
+}
+
+/// Looks up `host`, failing if it resolves to an address that isn't public.
+pub async fn resolve_public(host: &str, port: u16) -> Result<Vec<SocketAddr>, anyhow::Error> {
+    let addrs = tokio::net::lookup_host((host, port))
+        .await
+        .context("host could not be resolved")?
+        .collect::<Vec<SocketAddr>>();
+
+    if addrs.is_empty() {
+        anyhow::bail!("host could not be resolved");
+    }
+
+    if addrs.iter().any(|addr| !is_public(&addr.ip())) {
+        anyhow::bail!("host must have a public address");
+    }
+
+    return Ok(addrs);
+}
+
+/// Resolves hosts for the channels' http client with [`resolve_public`], so the
+/// address is checked again when connecting and can't change after validation.
+pub struct PublicResolver;
+
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        return Box::pin(async move {
+            // reqwest sets the port of the url
+            let addrs = resolve_public(name.as_str(), 0).await?;
+
+            return Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_public() {
+        for ip in ["93.184.215.14", "1.1.1.1", "2606:4700:4700::1111"] {
+            assert!(is_public(&ip.parse().unwrap()), "{ip}");
+        }
+
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+        ] {
+            assert!(!is_public(&ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_public_rejects_loopback() {
+        assert!(resolve_public("localhost", 80).await.is_err());
+        assert!(resolve_public("127.0.0.1", 80).await.is_err());
+    }
+}
@@ -0,0 +1,328 @@
+//! Notifications delivered outside of Web Push, to channels the user
+//! configured and verified.
+
+use anyhow::Context;
+use config::{CONFIG, IS_PROD};
+use db::{
+    notification_channels::{ChannelKind, NotificationChannel},
+    notification_deliveries::DeliveryStatus,
+    notifications::Notification,
+};
+
+pub use email::EmailChannel;
+pub use ntfy::NtfyChannel;
+pub use webhook::WebhookChannel;
+mod address;
+mod email;
+mod ntfy;
+mod webhook;
+
+/// How long a webhook or ntfy server gets to answer.
+const HTTP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// What a channel sends, `link` is an absolute URL to the app.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ChannelMessage {
+    pub title: String,
+    pub message: String,
+    pub link: Option<String>,
+}
+
+#[async_trait::async_trait]
+pub trait Channel: Send + Sync {
+    /// Checks that `target` is something this channel can send to.
+    async fn validate_target(&self, target: &str) -> Result<(), anyhow::Error>;
+
+    async fn send(&self, target: &str, message: &ChannelMessage) -> Result<(), anyhow::Error>;
+}
+
+/// One of every kind of channel, email is missing when SMTP isn't configured.
+pub struct Channels {
+    pub email: Option<EmailChannel>,
+    pub webhook: WebhookChannel,
+    pub ntfy: NtfyChannel,
+}
+
+impl Channels {
+    pub fn get(&self, kind: &ChannelKind) -> Option<&dyn Channel> {
+        return match kind {
+            ChannelKind::Email => self.email.as_ref().map(|email| email as &dyn Channel),
+            ChannelKind::Webhook => Some(&self.webhook),
+            ChannelKind::Ntfy => Some(&self.ntfy),
+        };
+    }
+}
+
+/// A client that doesn't follow redirects, and unless `allow_local` only
+/// connects to public addresses.
+fn http_client(allow_local: bool) -> Result<reqwest::Client, anyhow::Error> {
+    let mut builder = reqwest::Client::builder()
+        .timeout(HTTP_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none());
+
+    if !allow_local {
+        builder = builder.dns_resolver(std::sync::Arc::new(address::PublicResolver));
+    }
+
+    return builder.build().context("error creating http client");
+}
+
+/// Parses an http(s) URL. Plain http and IP addresses that aren't public
+/// are only allowed with `allow_local`, outside of production.
+fn parse_url(target: &str, allow_local: bool) -> Result<reqwest::Url, anyhow::Error> {
+    let url = reqwest::Url::parse(target).context("invalid url")?;
+
+    match url.scheme() {
+        "https" => {}
+        "http" if allow_local => {}
+        _ => return Err(anyhow::anyhow!("url must use https")),
+    }
+
+    let Some(host) = url.host_str() else {
+        return Err(anyhow::anyhow!("url must have a host"));
+    };
+
+    // hostnames are checked when they are resolved, IPv6 hosts are in brackets
+    let ip = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<std::net::IpAddr>();
+
+    if !allow_local && ip.is_ok_and(|ip| !address::is_public(&ip)) {
+        return Err(anyhow::anyhow!("url must point to a public address"));
+    }
+
+    return Ok(url);
+}
+
+/// Like [`parse_url`], also checking that the host resolves to public addresses.
+async fn parse_public_url(target: &str, allow_local: bool) -> Result<reqwest::Url, anyhow::Error> {
+    let url = parse_url(target, allow_local)?;
+
+    if !allow_local {
+        let host = url.host_str().context("url must have a host")?;
+        let port = url
+            .port_or_known_default()
+            .context("url must have a port")?;
+
+        address::resolve_public(host, port).await?;
+    }
+
+    return Ok(url);
+}
+
+/// Fails on error statuses, and on redirects since they aren't followed.
+fn check_response(response: reqwest::Response) -> Result<(), anyhow::Error> {
+    if response.status().is_redirection() {
+        anyhow::bail!(
+            "answered with a redirect to {:?}",
+            response.headers().get("location")
+        );
+    }
+
+    response.error_for_status()?;
+
+    return Ok(());
+}
+
+pub static CHANNELS: once_cell::sync::Lazy<Channels> = once_cell::sync::Lazy::new(|| {
+    let email = match (&CONFIG.smtp_url, &CONFIG.smtp_from) {
+        (Some(smtp_url), Some(smtp_from)) => {
+            Some(EmailChannel::new(smtp_url, smtp_from).expect("error creating email channel"))
+        }
+        _ => None,
+    };
+
+    return Channels {
+        email,
+        webhook: WebhookChannel::new(!*IS_PROD).expect("error creating webhook channel"),
+        ntfy: NtfyChannel::new(!*IS_PROD).expect("error creating ntfy channel"),
+    };
+});
+
+/// What happened to a notification on the user's channels, its status is
+/// decided together with the push subscriptions in [`crate::deliver`].
+#[derive(Debug, Default, PartialEq)]
+pub struct ChannelsOutcome {
+    /// A channel received the notification, in this or an earlier attempt.
+    pub sent_any: bool,
+    /// Why a channel failed, failed channels are tried again.
+    pub error: Option<String>,
+}
+
+/// Sends `message` for `notification` to every channel that hasn't received it yet
+/// and records the outcomes. Failed channels are tried again along with the
+/// notification's push retries, they don't schedule retries of their own.
+pub async fn deliver_to_channels(
+    db: &db::Db,
+    channels: &Channels,
+    notification: &Notification,
+    message: &ChannelMessage,
+    targets: &[NotificationChannel],
+) -> Result<ChannelsOutcome, anyhow::Error> {
+    let delivered_channel_ids =
+        db::notification_channels::get_delivered_channel_ids(db, &notification.id)
+            .await
+            .context("error getting channel deliveries")?;
+
+    let futures = targets
+        .iter()
+        .filter(|target| !delivered_channel_ids.contains(&target.id))
+        .map(|target| async move {
+            let result = match channels.get(&target.kind) {
+                Some(channel) => channel.send(&target.target, message).await,
+                None => Err(anyhow::anyhow!(
+                    "{} notifications aren't available",
+                    target.kind.as_ref()
+                )),
+            };
+
+            let (status, error) = match &result {
+                Ok(()) => {
+                    tracing::info!(
+                        "notification {} sent to channel {}",
+                        notification.id,
+                        target.id
+                    );
+                    (DeliveryStatus::Sent, None)
+                }
+                Err(e) => {
+                    tracing::error!(
+                        "failed to send notification {} to channel {}: {}",
+                        notification.id,
+                        target.id,
+                        e
+                    );
+                    (DeliveryStatus::Failed, Some(format!("{:#}", e)))
+                }
+            };
+
+            db::notification_channels::record_delivery_attempt(
+                db,
+                &notification.id,
+                &target.id,
+                &status,
+                error.as_deref(),
+            )
+            .await
+            .context("error recording channel delivery")?;
+
+            return Ok::<Option<String>, anyhow::Error>(error);
+        });
+
+    let errors = futures::future::join_all(futures)
+        .await
+        .into_iter()
+        .collect::<Result<Vec<Option<String>>, anyhow::Error>>()?;
+
+    let sent_now = errors.iter().any(|error| error.is_none());
+
+    return Ok(ChannelsOutcome {
+        sent_any: !delivered_channel_ids.is_empty() || sent_now,
+        error: errors.into_iter().flatten().next(),
+    });
+}
+
+/// The message that proves the user owns a channel.
+pub fn verification_message(code: &str) -> ChannelMessage {
+    return ChannelMessage {
+        title: "Verify your notification channel".to_owned(),
+        message: format!("Your verification code is {code}"),
+        link: Some(format!("{}/app/settings", CONFIG.front_url)),
+    };
+}
+
+/// Turns a notification's app path into a link that works outside the app.
+pub fn absolute_link(link: Option<&str>) -> Option<String> {
+    return link.map(|link| format!("{}{}", CONFIG.front_url, link));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock_push::MockPushEndpoint, mock_smtp::MockSmtpServer};
+    use chrono::Utc;
+
+    #[test]
+    fn test_parse_url() {
+        assert!(parse_url("https://hooks.test.local/a", false).is_ok());
+        assert!(parse_url("http://hooks.test.local/a", false).is_err());
+        assert!(parse_url("http://hooks.test.local/a", true).is_ok());
+        assert!(parse_url("ftp://hooks.test.local/a", true).is_err());
+        assert!(parse_url("not a url", true).is_err());
+
+        assert!(parse_url("https://127.0.0.1/a", false).is_err());
+        assert!(parse_url("https://[::1]/a", false).is_err());
+        assert!(parse_url("https://169.254.169.254/latest", false).is_err());
+        assert!(parse_url("http://127.0.0.1/a", true).is_ok());
+        assert!(parse_url("https://1.1.1.1/a", false).is_ok());
+    }
+
+    #[sqlx::test(migrations = "../db/migrations")]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_deliver_to_channels_retries_failed_channels_only(db: db::Db) {
+        let notification = db::test_utils::insert_notification(&db, "channels@test.local").await;
+
+        let smtp = MockSmtpServer::start().await;
+        let flaky_hook = MockPushEndpoint::start(vec![500, 200]).await;
+        let channels = Channels {
+            email: Some(EmailChannel::new(&smtp.url(), "tasks@test.local").unwrap()),
+            webhook: WebhookChannel::new(true).unwrap(),
+            ntfy: NtfyChannel::new(true).unwrap(),
+        };
+
+        let mut targets = vec![];
+        for (kind, target) in [
+            (ChannelKind::Email, "user@test.local".to_owned()),
+            (ChannelKind::Webhook, flaky_hook.url.to_owned()),
+        ] {
+            let channel = db::notification_channels::insert(
+                &db,
+                &notification.user_id,
+                &kind,
+                &target,
+                "hash",
+                &Utc::now(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+            db::notification_channels::mark_verified(&db, &channel.id, &Utc::now())
+                .await
+                .unwrap();
+            targets.push(channel);
+        }
+
+        let message = ChannelMessage {
+            title: "title".to_owned(),
+            message: "message".to_owned(),
+            link: None,
+        };
+
+        let outcome = deliver_to_channels(&db, &channels, &notification, &message, &targets)
+            .await
+            .unwrap();
+        assert!(outcome.sent_any);
+        assert!(outcome.error.is_some());
+
+        let outcome = deliver_to_channels(&db, &channels, &notification, &message, &targets)
+            .await
+            .unwrap();
+        assert_eq!(
+            outcome,
+            ChannelsOutcome {
+                sent_any: true,
+                error: None
+            }
+        );
+
+        // the email went out once, the webhook failed once and was tried again
+        assert_eq!(smtp.emails().await.len(), 1);
+        assert_eq!(flaky_hook.requests().await.len(), 2);
+
+        let delivered = db::notification_channels::get_delivered_channel_ids(&db, &notification.id)
+            .await
+            .unwrap();
+        assert_eq!(delivered.len(), 2);
+    }
+}
@@ -0,0 +1,129 @@
+use super::{check_response, http_client, parse_public_url, parse_url, Channel, ChannelMessage};
+use anyhow::Context;
+
+/// Publishes to an ntfy topic, the target is the topic URL like `https://ntfy.sh/my-topic`.
+pub struct NtfyChannel {
+    client: reqwest::Client,
+    /// Allows plain http and addresses on the server's own network, outside of production.
+    allow_local: bool,
+}
+
+#[derive(serde::Serialize)]
+struct NtfyPublish<'a> {
+    topic: &'a str,
+    title: &'a str,
+    message: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    click: Option<&'a str>,
+}
+
+impl NtfyChannel {
+    pub fn new(allow_local: bool) -> Result<Self, anyhow::Error> {
+        return Ok(Self {
+            client: http_client(allow_local)?,
+            allow_local,
+        });
+    }
+
+    /// Splits a topic URL into the server and the topic, ntfy takes JSON
+    /// messages at the root of the server.
+    fn split_target(&self, target: &str) -> Result<(reqwest::Url, String), anyhow::Error> {
+        let mut url = parse_url(target, self.allow_local)?;
+
+        let topic = url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .filter(|topic| !topic.is_empty())
+            .ok_or(anyhow::anyhow!("url must end with a topic"))?
+            .to_owned();
+
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("invalid url"))?
+            .pop();
+
+        return Ok((url, topic));
+    }
+}
+
+#[async_trait::async_trait]
+impl Channel for NtfyChannel {
+    async fn validate_target(&self, target: &str) -> Result<(), anyhow::Error> {
+        self.split_target(target)?;
+        parse_public_url(target, self.allow_local).await?;
+
+        return Ok(());
+    }
+
+    async fn send(&self, target: &str, message: &ChannelMessage) -> Result<(), anyhow::Error> {
+        let (server, topic) = self.split_target(target)?;
+
+        let response = self
+            .client
+            .post(server)
+            .json(&NtfyPublish {
+                topic: &topic,
+                title: &message.title,
+                message: &message.message,
+                click: message.link.as_deref(),
+            })
+            .send()
+            .await
+            .context("error publishing to ntfy")?;
+
+        check_response(response).context("ntfy answered with an error")?;
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_push::MockPushEndpoint;
+
+    #[test]
+    fn test_split_target() {
+        let channel = NtfyChannel::new(false).unwrap();
+
+        let (server, topic) = channel.split_target("https://ntfy.sh/my-topic").unwrap();
+        assert_eq!(server.as_str(), "https://ntfy.sh/");
+        assert_eq!(topic, "my-topic");
+
+        let (server, topic) = channel
+            .split_target("https://push.test.local/ntfy/my-topic")
+            .unwrap();
+        assert_eq!(server.as_str(), "https://push.test.local/ntfy");
+        assert_eq!(topic, "my-topic");
+
+        assert!(channel.split_target("https://ntfy.sh/").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_publish_to_local_server() {
+        let channel = NtfyChannel::new(true).unwrap();
+        let server = MockPushEndpoint::start(vec![200]).await;
+
+        let message = ChannelMessage {
+            title: "Task finished".to_owned(),
+            message: "Your task 'work' has finished".to_owned(),
+            link: None,
+        };
+
+        channel
+            .send(&format!("{}/my-topic", server.url), &message)
+            .await
+            .unwrap();
+
+        let requests = server.requests().await;
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, "/");
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&requests[0].body).unwrap(),
+            serde_json::json!({
+                "topic": "my-topic",
+                "title": "Task finished",
+                "message": "Your task 'work' has finished"
+            })
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use super::{check_response, http_client, parse_public_url, parse_url, Channel, ChannelMessage};
+use anyhow::Context;
+
+/// Posts the notification as JSON to a URL of the user's choosing.
+pub struct WebhookChannel {
+    client: reqwest::Client,
+    /// Allows plain http and addresses on the server's own network, outside of production.
+    allow_local: bool,
+}
+
+impl WebhookChannel {
+    pub fn new(allow_local: bool) -> Result<Self, anyhow::Error> {
+        return Ok(Self {
+            client: http_client(allow_local)?,
+            allow_local,
+        });
+    }
+}
+
+#[async_trait::async_trait]
+impl Channel for WebhookChannel {
+    async fn validate_target(&self, target: &str) -> Result<(), anyhow::Error> {
+        parse_public_url(target, self.allow_local).await?;
+
+        return Ok(());
+    }
+
+    async fn send(&self, target: &str, message: &ChannelMessage) -> Result<(), anyhow::Error> {
+        let url = parse_url(target, self.allow_local)?;
+
+        let response = self
+            .client
+            .post(url)
+            .json(message)
+            .send()
+            .await
+            .context("error calling webhook")?;
+
+        check_response(response).context("webhook answered with an error")?;
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_push::MockPushEndpoint;
+
+    #[tokio::test]
+    async fn test_send_to_local_receiver() {
+        let channel = WebhookChannel::new(true).unwrap();
+        let receiver = MockPushEndpoint::start(vec![200]).await;
+
+        let message = ChannelMessage {
+            title: "Task finished".to_owned(),
+            message: "Your task 'work' has finished".to_owned(),
+            link: Some("https://tasks.test.local/app/tasks".to_owned()),
+        };
+
+        channel
+            .send(&format!("{}/hook", receiver.url), &message)
+            .await
+            .unwrap();
+
+        let requests = receiver.requests().await;
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, "/hook");
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&requests[0].body).unwrap(),
+            serde_json::json!({
+                "title": "Task finished",
+                "message": "Your task 'work' has finished",
+                "link": "https://tasks.test.local/app/tasks"
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_to_failing_receiver() {
+        let channel = WebhookChannel::new(true).unwrap();
+        let receiver = MockPushEndpoint::start(vec![500]).await;
+
+        let message = ChannelMessage {
+            title: "title".to_owned(),
+            message: "message".to_owned(),
+            link: None,
+        };
+
+        assert!(channel.send(&receiver.url, &message).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_redirects_and_local_addresses_are_refused() {
+        let message = ChannelMessage {
+            title: "title".to_owned(),
+            message: "message".to_owned(),
+            link: None,
+        };
+
+        let channel = WebhookChannel::new(true).unwrap();
+        let redirecting = MockPushEndpoint::start(vec![302]).await;
+        assert!(channel.send(&redirecting.url, &message).await.is_err());
+
+        let channel = WebhookChannel::new(false).unwrap();
+        assert!(channel
+            .validate_target("https://localhost/hook")
+            .await
+            .is_err());
+        let error = channel
+            .send("https://localhost:1/hook", &message)
+            .await
+            .unwrap_err();
+        assert!(format!("{:?}", error).contains("public address"));
+    }
+}
@@ -0,0 +1,269 @@
+//! The nudge sent when nothing was tracked yet in a day and the summary of
+//! the day, both at the local times the user picked.
+
+use crate::templates::{self, Locale};
+use anyhow::Context;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use chrono_tz::Tz;
+use db::{
+    notification_settings::NotificationSettings,
+    notifications::{DeepLink, NotificationKind},
+    tasks::TagFilter,
+};
+
+/// How late a nudge or summary still goes out, like when the service was
+/// down at the time. Past that it's skipped for the day.
+pub static DAILY_GRACE: once_cell::sync::Lazy<Duration> =
+    once_cell::sync::Lazy::new(|| Duration::hours(1));
+
+/// The nudge and summary of `settings` that are due at `now`,
+/// along with the local day they're for.
+pub fn due(
+    settings: &NotificationSettings,
+    now: &DateTime<Utc>,
+) -> Vec<(NotificationKind, NaiveDate)> {
+    let tz = settings.timezone.parse::<Tz>().unwrap_or(Tz::UTC);
+    let local = now.with_timezone(&tz).naive_local();
+    let today = local.date();
+
+    return [
+        (
+            NotificationKind::Nudge,
+            settings.nudge_at,
+            settings.nudged_on,
+        ),
+        (
+            NotificationKind::Summary,
+            settings.summary_at,
+            settings.summarized_on,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(kind, at, sent_on)| {
+        let since = local.time() - at?;
+        let is_due = since >= Duration::zero() && since < *DAILY_GRACE && sent_on != Some(today);
+
+        return is_due.then_some((kind, today));
+    })
+    .collect();
+}
+
+/// Queues the nudges and summaries due at `now`, each once per local day.
+pub async fn queue_due(db: &db::Db, now: &DateTime<Utc>) {
+    let settings = match db::notification_settings::get_with_daily_times(db).await {
+        Ok(settings) => settings,
+        Err(e) => {
+            tracing::error!("error getting daily notification settings: {}", e);
+            return;
+        }
+    };
+
+    for settings in settings {
+        for (kind, day) in due(&settings, now) {
+            if let Err(e) = queue(db, &settings, &kind, &day, now).await {
+                tracing::error!(
+                    "error queuing {} of user {}: {:#}",
+                    kind.as_ref(),
+                    settings.user_id,
+                    e
+                );
+            }
+        }
+    }
+}
+
+async fn queue(
+    db: &db::Db,
+    settings: &NotificationSettings,
+    kind: &NotificationKind,
+    day: &NaiveDate,
+    now: &DateTime<Utc>,
+) -> Result<(), anyhow::Error> {
+    let user_id = settings.user_id.as_str();
+
+    // another worker may have got there first
+    let claimed = match kind {
+        NotificationKind::Summary => db::notification_settings::claim_summary(db, user_id, day)
+            .await
+            .context("error claiming summary")?,
+        _ => db::notification_settings::claim_nudge(db, user_id, day)
+            .await
+            .context("error claiming nudge")?,
+    };
+
+    if !claimed {
+        return Ok(());
+    }
+
+    let tz = settings.timezone.parse::<Tz>().unwrap_or(Tz::UTC);
+    let start = day.and_hms_opt(0, 0, 0).context("invalid day")?;
+    let end = now.with_timezone(&tz).naive_local();
+
+    let stats = db::tasks::get_tag_distribution_stats(
+        db,
+        user_id,
+        &start,
+        &end,
+        &tz,
+        &TagFilter::default(),
+    )
+    .await
+    .context("error getting today's stats")?;
+
+    let seconds = stats.iter().filter_map(|stat| stat.seconds).sum::<i64>();
+    let locale = Locale::from_settings(Some(settings));
+
+    let (text, link) = match kind {
+        NotificationKind::Summary => {
+            // nothing to sum up, that's what the nudge is for
+            let Some(top) = stats.first().filter(|_| seconds > 0) else {
+                return Ok(());
+            };
+
+            (
+                templates::render_summary(&locale, seconds, &top.tag_label),
+                DeepLink::Stats {
+                    from: *day,
+                    to: *day,
+                },
+            )
+        }
+        _ => {
+            let ongoing = db::tasks::get_ongoing(db, user_id)
+                .await
+                .context("error getting ongoing task")?;
+
+            if seconds > 0 || ongoing.is_some() {
+                return Ok(());
+            }
+
+            (templates::render_nudge(&locale), DeepLink::Tasks)
+        }
+    };
+
+    db::notifications::insert_for_user(db, user_id, kind, &text.title, &text.message, &link, now)
+        .await
+        .context("error queuing notification")?;
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveTime, TimeZone};
+
+    fn helsinki(hour: u32, minute: u32) -> DateTime<Utc> {
+        return chrono_tz::Europe::Helsinki
+            .with_ymd_and_hms(2026, 10, 19, hour, minute, 0)
+            .unwrap()
+            .with_timezone(&Utc);
+    }
+
+    fn settings() -> NotificationSettings {
+        return NotificationSettings {
+            timezone: "Europe/Helsinki".to_owned(),
+            nudge_at: NaiveTime::from_hms_opt(10, 0, 0),
+            summary_at: NaiveTime::from_hms_opt(21, 0, 0),
+            ..NotificationSettings::new("user")
+        };
+    }
+
+    #[test]
+    fn test_due_in_the_users_timezone() {
+        let today = NaiveDate::from_ymd_opt(2026, 10, 19).unwrap();
+
+        assert!(due(&settings(), &helsinki(9, 59)).is_empty());
+        assert_eq!(
+            due(&settings(), &helsinki(10, 0)),
+            vec![(NotificationKind::Nudge, today)]
+        );
+        assert_eq!(
+            due(&settings(), &helsinki(21, 30)),
+            vec![(NotificationKind::Summary, today)]
+        );
+        // too late, the service was down
+        assert!(due(&settings(), &helsinki(11, 0)).is_empty());
+    }
+
+    #[test]
+    fn test_due_once_a_day() {
+        let settings = NotificationSettings {
+            nudged_on: NaiveDate::from_ymd_opt(2026, 10, 19),
+            ..settings()
+        };
+
+        assert!(due(&settings, &helsinki(10, 5)).is_empty());
+        assert!(due(&NotificationSettings::new("user"), &helsinki(10, 5)).is_empty());
+    }
+
+    #[sqlx::test(migrations = "../db/migrations")]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_queue_due(db: db::Db) {
+        let user = db::users::create(&db, "daily@test.local").await.unwrap();
+        let tag = db::tags::insert(&db, &user.id, "work", "#d13c4b")
+            .await
+            .unwrap();
+
+        db::notification_settings::set_quiet_hours(&db, &user.id, "Europe/Helsinki", None)
+            .await
+            .unwrap();
+        db::notification_settings::set_daily_times(
+            &db,
+            &user.id,
+            NaiveTime::from_hms_opt(10, 0, 0).as_ref(),
+            NaiveTime::from_hms_opt(21, 0, 0).as_ref(),
+        )
+        .await
+        .unwrap();
+
+        let claim = |at: DateTime<Utc>| {
+            let db = db.clone();
+            async move {
+                db::notifications::claim_due(&db, "worker", &at, &Duration::minutes(5), 10)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        // nothing tracked yet
+        queue_due(&db, &helsinki(10, 0)).await;
+        queue_due(&db, &helsinki(10, 1)).await;
+        let nudges = claim(helsinki(10, 1)).await;
+        assert_eq!(nudges.len(), 1);
+        assert_eq!(nudges[0].kind, NotificationKind::Nudge);
+        assert!(nudges[0].task_id.is_none());
+
+        db::tasks::insert(
+            &db,
+            &db::tasks::Task {
+                id: db::create_id(),
+                user_id: user.id.to_owned(),
+                tag_id: tag.id,
+                is_manual: true,
+                seconds: 90 * 60,
+                start_at: helsinki(12, 0),
+                end_at: helsinki(13, 30),
+            },
+        )
+        .await
+        .unwrap();
+
+        queue_due(&db, &helsinki(21, 0)).await;
+        // the nudge's claim ran out by then, it's still pending
+        let summaries = claim(helsinki(21, 0))
+            .await
+            .into_iter()
+            .filter(|notification| notification.kind == NotificationKind::Summary)
+            .collect::<Vec<_>>();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(
+            summaries[0].message.as_deref(),
+            Some("You tracked 1 h 30 min today, mostly 'work'")
+        );
+        assert_eq!(
+            summaries[0].link.as_deref(),
+            Some("/app/stats?from=2026-10-19&to=2026-10-19")
+        );
+    }
+}
@@ -0,0 +1,549 @@
+use crate::{
+    channels::ChannelsOutcome,
+    send::{FailureKind, PushClient, PushMessage},
+};
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use db::{
+    notification_deliveries::DeliveryStatus,
+    notification_subs::NotificationSub,
+    notifications::{Notification, NotificationStatus},
+};
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Sent,
+    Failed(FailureKind, String),
+}
+
+/// How often a notification is retried before it's marked as failed.
+pub struct RetryPolicy {
+    pub max_attempts: i32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+pub static RETRY_POLICY: once_cell::sync::Lazy<RetryPolicy> =
+    once_cell::sync::Lazy::new(|| RetryPolicy {
+        max_attempts: 6,
+        base_delay: Duration::seconds(15),
+        max_delay: Duration::minutes(15),
+    });
+
+impl RetryPolicy {
+    /// Delay after the given failed attempt, doubling each time.
+    pub fn delay_after(&self, attempts: i32) -> Duration {
+        let exponent = (attempts - 1).clamp(0, 20) as u32;
+        let delay = self.base_delay * 2_i32.pow(exponent);
+
+        return delay.min(self.max_delay);
+    }
+
+    /// Status and next attempt time of a notification after `attempts` failed attempts.
+    pub fn next_state(
+        &self,
+        attempts: i32,
+        now: &DateTime<Utc>,
+    ) -> (NotificationStatus, DateTime<Utc>) {
+        if attempts >= self.max_attempts {
+            return (NotificationStatus::Failed, *now);
+        }
+
+        return (
+            NotificationStatus::Pending,
+            *now + self.delay_after(attempts),
+        );
+    }
+}
+
+/// Sends `message` for `notification`, claimed by `worker_id`, to every subscription that hasn't
+/// received it yet and records the outcome per subscription. Together with what happened on
+/// the user's `channels` this decides the notification's status: a retry is scheduled if a
+/// subscription or channel failed, otherwise it's sent if any of them received it.
+#[allow(clippy::too_many_arguments)]
+pub async fn deliver(
+    db: &db::Db,
+    worker_id: &str,
+    client: &PushClient,
+    policy: &RetryPolicy,
+    notification: &Notification,
+    message: &PushMessage,
+    subs: &[NotificationSub],
+    channels: &ChannelsOutcome,
+) -> Result<NotificationStatus, anyhow::Error> {
+    let delivered_sub_ids =
+        db::notification_deliveries::get_by_notification_id(db, &notification.id)
+            .await
+            .context("error getting deliveries")?
+            .into_iter()
+            .filter(|delivery| delivery.status == DeliveryStatus::Sent)
+            .map(|delivery| delivery.notification_sub_id)
+            .collect::<Vec<String>>();
+
+    let pending_subs = subs
+        .iter()
+        .filter(|sub| !delivered_sub_ids.contains(&sub.id))
+        .collect::<Vec<&NotificationSub>>();
+
+    let futures = pending_subs.iter().map(|sub| async move {
+        let result = client.send(sub, message).await;
+
+        let outcome = match &result {
+            Ok(_) => {
+                tracing::info!("notification {} sent to sub {}", notification.id, sub.id);
+                db::notification_subs::mark_success(db, &sub.id, &Utc::now())
+                    .await
+                    .context("error marking sub success")?;
+                Outcome::Sent
+            }
+            Err(e) => {
+                let kind = FailureKind::classify(e);
+                tracing::error!(
+                    "failed to send notification {} to sub {} ({:?}): {}",
+                    notification.id,
+                    sub.id,
+                    kind,
+                    e
+                );
+                if kind == FailureKind::Permanent {
+                    tracing::info!("expiring notification sub {}", sub.id);
+                    db::notification_subs::expire(db, &sub.id, &Utc::now())
+                        .await
+                        .context("error expiring sub")?;
+                }
+                Outcome::Failed(kind, e.to_string())
+            }
+        };
+
+        let (status, error) = match &outcome {
+            Outcome::Sent => (DeliveryStatus::Sent, None),
+            Outcome::Failed(_, e) => (DeliveryStatus::Failed, Some(e.as_str())),
+        };
+
+        db::notification_deliveries::record_attempt(db, &notification.id, &sub.id, &status, error)
+            .await
+            .context("error recording delivery")?;
+
+        return Ok::<Outcome, anyhow::Error>(outcome);
+    });
+
+    let outcomes = futures::future::join_all(futures)
+        .await
+        .into_iter()
+        .collect::<Result<Vec<Outcome>, anyhow::Error>>()?;
+
+    let sent_any =
+        channels.sent_any || !delivered_sub_ids.is_empty() || outcomes.contains(&Outcome::Sent);
+    // channels don't tell permanent failures apart, they are always retried
+    let transient_error = outcomes
+        .iter()
+        .find_map(|outcome| match outcome {
+            Outcome::Failed(FailureKind::Transient, e) => Some(e.to_owned()),
+            _ => None,
+        })
+        .or_else(|| channels.error.to_owned());
+    let permanent_error = outcomes.iter().find_map(|outcome| match outcome {
+        Outcome::Failed(FailureKind::Permanent, e) => Some(e.to_owned()),
+        _ => None,
+    });
+
+    let attempts = notification.attempts + 1;
+    let now = Utc::now();
+
+    // only transient failures are retried, expired subs won't come back
+    let (status, next_attempt_at, last_error) = if let Some(error) = transient_error {
+        let (status, next_attempt_at) = policy.next_state(attempts, &now);
+        (status, next_attempt_at, Some(error))
+    } else if sent_any {
+        (NotificationStatus::Sent, now, None)
+    } else {
+        let error =
+            permanent_error.unwrap_or("no notification subscriptions or channels".to_owned());
+        (NotificationStatus::Failed, now, Some(error))
+    };
+
+    let updated = db::notifications::update_status(
+        db,
+        &notification.id,
+        worker_id,
+        &status,
+        attempts,
+        &next_attempt_at,
+        last_error.as_deref(),
+    )
+    .await
+    .context("error updating notification status")?;
+
+    if !updated {
+        tracing::warn!(
+            "lost the claim on notification {} before it was delivered",
+            notification.id
+        );
+    }
+
+    return Ok(status);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mock_push::{MockPushEndpoint, TEST_VAPID_PRIVATE_KEY},
+        send::PushPayload,
+    };
+    use db::test_utils::insert_task;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::seconds(10),
+            max_delay: Duration::seconds(25),
+        }
+    }
+
+    #[test]
+    fn test_retry_policy_backoff() {
+        let policy = policy();
+
+        assert_eq!(policy.delay_after(1), Duration::seconds(10));
+        assert_eq!(policy.delay_after(2), Duration::seconds(20));
+        assert_eq!(policy.delay_after(3), Duration::seconds(25));
+        assert_eq!(policy.delay_after(100), Duration::seconds(25));
+
+        let now = Utc::now();
+        assert_eq!(
+            policy.next_state(1, &now),
+            (NotificationStatus::Pending, now + Duration::seconds(10))
+        );
+        assert_eq!(
+            policy.next_state(3, &now),
+            (NotificationStatus::Failed, now)
+        );
+    }
+
+    async fn insert_sub(db: &db::Db, sub: &NotificationSub) -> NotificationSub {
+        return db::notification_subs::upsert(
+            db,
+            &sub.user_id,
+            &sub.endpoint,
+            &sub.p256dh,
+            &sub.auth,
+            None,
+            &crate::vapid::public_key_of(TEST_VAPID_PRIVATE_KEY).unwrap(),
+        )
+        .await
+        .unwrap();
+    }
+
+    const WORKER_ID: &str = "worker";
+
+    fn message(notification: &Notification) -> PushMessage {
+        return PushMessage::new(PushPayload::new(
+            notification.title.as_deref().unwrap_or_default(),
+            notification.message.as_deref().unwrap_or_default(),
+        ));
+    }
+
+    /// Claims the one notification that is due at `at`.
+    async fn claim(db: &db::Db, at: DateTime<Utc>) -> Notification {
+        let mut notifications =
+            db::notifications::claim_due(db, WORKER_ID, &at, &Duration::minutes(5), 10)
+                .await
+                .unwrap();
+        assert_eq!(notifications.len(), 1);
+
+        return notifications.remove(0);
+    }
+
+    #[sqlx::test(migrations = "../db/migrations")]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_deliver_retries_failed_subs_only(db: db::Db) {
+        let client = PushClient::new(TEST_VAPID_PRIVATE_KEY).unwrap();
+        let policy = policy();
+
+        let (user_id, task_id) = insert_task(&db, "deliver@test.local").await;
+
+        let working = MockPushEndpoint::start(vec![201]).await;
+        let flaky = MockPushEndpoint::start(vec![503, 201]).await;
+        let subs = vec![
+            insert_sub(&db, &working.sub(&user_id)).await,
+            insert_sub(&db, &flaky.sub(&user_id)).await,
+        ];
+
+        db::notifications::insert(
+            &db,
+            &user_id,
+            &task_id,
+            &db::notifications::NotificationKind::Finished,
+            "title",
+            "message",
+            &Utc::now(),
+        )
+        .await
+        .unwrap();
+        let notification = claim(&db, Utc::now()).await;
+
+        let status = deliver(
+            &db,
+            WORKER_ID,
+            &client,
+            &policy,
+            &notification,
+            &message(&notification),
+            &subs,
+            &ChannelsOutcome::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, NotificationStatus::Pending);
+
+        // the retry is due after the backoff
+        let notification = claim(&db, Utc::now() + Duration::hours(1)).await;
+        assert_eq!(notification.attempts, 1);
+        let status = deliver(
+            &db,
+            WORKER_ID,
+            &client,
+            &policy,
+            &notification,
+            &message(&notification),
+            &subs,
+            &ChannelsOutcome::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, NotificationStatus::Sent);
+
+        // the working sub got the notification once, the flaky one on the retry
+        assert_eq!(working.requests().await.len(), 1);
+        assert_eq!(flaky.requests().await.len(), 2);
+
+        let deliveries = db::notification_deliveries::get_by_notification_id(&db, &notification.id)
+            .await
+            .unwrap();
+        assert_eq!(deliveries.len(), 2);
+        assert!(deliveries.iter().all(|d| d.status == DeliveryStatus::Sent));
+        assert!(deliveries.iter().any(|d| d.attempts == 2));
+    }
+
+    #[sqlx::test(migrations = "../db/migrations")]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_deliver_gives_up_after_max_attempts(db: db::Db) {
+        let client = PushClient::new(TEST_VAPID_PRIVATE_KEY).unwrap();
+        let policy = policy();
+
+        let (user_id, task_id) = insert_task(&db, "failing@test.local").await;
+
+        let down = MockPushEndpoint::start(vec![500]).await;
+        let subs = vec![insert_sub(&db, &down.sub(&user_id)).await];
+
+        db::notifications::insert(
+            &db,
+            &user_id,
+            &task_id,
+            &db::notifications::NotificationKind::Finished,
+            "title",
+            "message",
+            &Utc::now(),
+        )
+        .await
+        .unwrap();
+
+        for attempt in 1..=policy.max_attempts {
+            let notification = claim(&db, Utc::now() + Duration::days(attempt as i64)).await;
+            let status = deliver(
+                &db,
+                WORKER_ID,
+                &client,
+                &policy,
+                &notification,
+                &message(&notification),
+                &subs,
+                &ChannelsOutcome::default(),
+            )
+            .await
+            .unwrap();
+
+            if attempt < policy.max_attempts {
+                assert_eq!(status, NotificationStatus::Pending);
+            } else {
+                assert_eq!(status, NotificationStatus::Failed);
+            }
+        }
+
+        assert_eq!(down.requests().await.len(), policy.max_attempts as usize);
+    }
+
+    #[sqlx::test(migrations = "../db/migrations")]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_deliver_expires_gone_subs(db: db::Db) {
+        let client = PushClient::new(TEST_VAPID_PRIVATE_KEY).unwrap();
+        let policy = policy();
+
+        let (user_id, task_id) = insert_task(&db, "gone@test.local").await;
+
+        let working = MockPushEndpoint::start(vec![201]).await;
+        let gone = MockPushEndpoint::start(vec![410]).await;
+        let working_sub = insert_sub(&db, &working.sub(&user_id)).await;
+        let gone_sub = insert_sub(&db, &gone.sub(&user_id)).await;
+
+        db::notifications::insert(
+            &db,
+            &user_id,
+            &task_id,
+            &db::notifications::NotificationKind::Finished,
+            "title",
+            "message",
+            &Utc::now(),
+        )
+        .await
+        .unwrap();
+        let notification = claim(&db, Utc::now()).await;
+
+        let status = deliver(
+            &db,
+            WORKER_ID,
+            &client,
+            &policy,
+            &notification,
+            &message(&notification),
+            &[working_sub, gone_sub],
+            &ChannelsOutcome::default(),
+        )
+        .await
+        .unwrap();
+
+        // the gone sub isn't worth a retry
+        assert_eq!(status, NotificationStatus::Sent);
+        assert_eq!(gone.requests().await.len(), 1);
+
+        let subs = db::notification_subs::get_by_user_ids(&db, &vec![user_id.to_owned()])
+            .await
+            .unwrap();
+        assert_eq!(subs.len(), 1);
+        assert!(subs[0].endpoint.starts_with(&working.url));
+        assert!(subs[0].last_success_at.is_some());
+
+        // only gone subs left, the next notification fails right away
+        let gone_sub = insert_sub(&db, &gone.sub(&user_id)).await;
+        db::notifications::insert(
+            &db,
+            &user_id,
+            &task_id,
+            &db::notifications::NotificationKind::Finished,
+            "title",
+            "message",
+            &Utc::now(),
+        )
+        .await
+        .unwrap();
+        let notification = claim(&db, Utc::now()).await;
+
+        let status = deliver(
+            &db,
+            WORKER_ID,
+            &client,
+            &policy,
+            &notification,
+            &message(&notification),
+            &[gone_sub],
+            &ChannelsOutcome::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, NotificationStatus::Failed);
+
+        let stale = db::notification_subs::delete_stale(&db, &(Utc::now() - Duration::days(30)))
+            .await
+            .unwrap();
+        assert_eq!(stale.len(), 2);
+        assert!(stale.iter().all(|sub| sub.expired_at.is_some()));
+    }
+
+    #[sqlx::test(migrations = "../db/migrations")]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_deliver_counts_channel_outcomes(db: db::Db) {
+        let client = PushClient::new(TEST_VAPID_PRIVATE_KEY).unwrap();
+        let policy = policy();
+
+        let (user_id, task_id) = insert_task(&db, "channels@test.local").await;
+        let insert_notification = || async {
+            db::notifications::insert(
+                &db,
+                &user_id,
+                &task_id,
+                &db::notifications::NotificationKind::Finished,
+                "title",
+                "message",
+                &Utc::now(),
+            )
+            .await
+            .unwrap();
+        };
+
+        // only a channel, no push subscriptions
+        insert_notification().await;
+        let notification = claim(&db, Utc::now()).await;
+        let status = deliver(
+            &db,
+            WORKER_ID,
+            &client,
+            &policy,
+            &notification,
+            &message(&notification),
+            &[],
+            &ChannelsOutcome {
+                sent_any: true,
+                error: None,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, NotificationStatus::Sent);
+
+        // push worked but a channel failed, it's tried again
+        let working = MockPushEndpoint::start(vec![201]).await;
+        let subs = vec![insert_sub(&db, &working.sub(&user_id)).await];
+        insert_notification().await;
+        let notification = claim(&db, Utc::now()).await;
+        let status = deliver(
+            &db,
+            WORKER_ID,
+            &client,
+            &policy,
+            &notification,
+            &message(&notification),
+            &subs,
+            &ChannelsOutcome {
+                sent_any: false,
+                error: Some("webhook answered 500".to_owned()),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, NotificationStatus::Pending);
+
+        let notification = claim(&db, Utc::now() + Duration::hours(1)).await;
+        assert_eq!(
+            notification.last_error.as_deref(),
+            Some("webhook answered 500")
+        );
+        let status = deliver(
+            &db,
+            WORKER_ID,
+            &client,
+            &policy,
+            &notification,
+            &message(&notification),
+            &subs,
+            &ChannelsOutcome {
+                sent_any: true,
+                error: None,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, NotificationStatus::Sent);
+        assert_eq!(working.requests().await.len(), 1);
+    }
+}
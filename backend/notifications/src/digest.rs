@@ -0,0 +1,718 @@
+//! The weekly digest email, a look back at the previous week sent on the
+//! weekday and at the local time the user picked.
+
+use crate::{
+    channels::EmailChannel,
+    daily::DAILY_GRACE,
+    templates::{fill, format_duration, templates, Locale},
+};
+use anyhow::Context;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use db::{
+    notification_channels::{ChannelKind, NotificationChannel},
+    notification_settings::{DigestRecipient, NotificationSettings},
+    notifications::DeepLink,
+    preferences::{EventPreferences, NotificationEvent, Preferences},
+    tasks::{
+        calendar_streaks, get_hours_by_stats, get_session_stats, get_tag_distribution_stats,
+        StatsPrecision, TagFilter,
+    },
+};
+use std::collections::HashMap;
+
+/// How far back the streak is counted.
+const STREAK_DAYS: i64 = 365;
+
+/// Characters in the longest bar of the text charts.
+const BAR_WIDTH: usize = 20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DigestTag {
+    pub label: String,
+    pub color: String,
+    pub seconds: i64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeeklyDigest {
+    /// The monday of the week.
+    pub from: NaiveDate,
+    /// The sunday of the week.
+    pub to: NaiveDate,
+    pub total_seconds: i64,
+    pub previous_total_seconds: i64,
+    /// Most tracked first.
+    pub tags: Vec<DigestTag>,
+    /// Tracked seconds of each day, monday first.
+    pub days: [i64; 7],
+    pub longest_session_seconds: i64,
+    /// Days in a row with something tracked, up to the end of the week.
+    pub streak: i64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DigestEmail {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+/// The monday of the last full week before `today`.
+pub fn previous_week(today: &NaiveDate) -> NaiveDate {
+    let monday = *today - Duration::days(today.weekday().num_days_from_monday().into());
+
+    return monday - Duration::days(7);
+}
+
+/// The local day the digest of `settings` is due on at `now`, if it is.
+pub fn due(settings: &NotificationSettings, now: &DateTime<Utc>) -> Option<NaiveDate> {
+    let weekday = settings.digest_weekday?;
+    let at = settings.digest_at?;
+
+    let tz = settings.timezone.parse::<Tz>().unwrap_or(Tz::UTC);
+    let local = now.with_timezone(&tz).naive_local();
+    let today = local.date();
+    let since = local.time() - at;
+
+    let is_due = i64::from(today.weekday().num_days_from_monday()) == i64::from(weekday)
+        && since >= Duration::zero()
+        && since < *DAILY_GRACE
+        && settings.digested_on != Some(today);
+
+    return is_due.then_some(today);
+}
+
+fn start_of(day: &NaiveDate) -> Result<NaiveDateTime, anyhow::Error> {
+    return day.and_hms_opt(0, 0, 0).context("error and_hms_opt");
+}
+
+fn end_of(day: &NaiveDate) -> Result<NaiveDateTime, anyhow::Error> {
+    return day.and_hms_opt(23, 59, 59).context("error and_hms_opt");
+}
+
+/// Gathers the stats of the week starting on `from`, in `tz`.
+pub async fn build(
+    db: &db::Db,
+    user_id: &str,
+    tz: &Tz,
+    from: &NaiveDate,
+) -> Result<WeeklyDigest, anyhow::Error> {
+    let to = *from + Duration::days(6);
+    let history_from = to - Duration::days(STREAK_DAYS - 1);
+    let tag_filter = TagFilter::default();
+
+    let stats_by_day = get_hours_by_stats(
+        db,
+        user_id,
+        &StatsPrecision::Day,
+        &start_of(&history_from)?,
+        &end_of(&to)?,
+        tz,
+        &tag_filter,
+    )
+    .await
+    .context("error getting stats by day")?;
+
+    let seconds_by_day = stats_by_day
+        .iter()
+        .map(|day| {
+            let hours = day.stats.iter().fold(0.0, |acc, stat| acc + stat.hours);
+            return (day.date.date(), (hours * 3600.0).round() as i64);
+        })
+        .collect::<HashMap<NaiveDate, i64>>();
+
+    // oldest day first, the week is at the end
+    let history = (0..STREAK_DAYS)
+        .map(|i| {
+            let day = history_from + Duration::days(i);
+            return seconds_by_day.get(&day).copied().unwrap_or(0);
+        })
+        .collect::<Vec<i64>>();
+
+    let week_start = history.len() - 7;
+    let mut days = [0; 7];
+    days.copy_from_slice(&history[week_start..]);
+
+    let (streak, _) = calendar_streaks(&history, 1, Some(history.len() - 1));
+
+    let tags = get_tag_distribution_stats(
+        db,
+        user_id,
+        &start_of(from)?,
+        &end_of(&to)?,
+        tz,
+        &tag_filter,
+    )
+    .await
+    .context("error getting tag distribution")?
+    .into_iter()
+    .map(|stat| DigestTag {
+        label: stat.tag_label,
+        color: stat.tag_color,
+        seconds: stat.seconds.unwrap_or(0),
+    })
+    .collect::<Vec<DigestTag>>();
+
+    let sessions = get_session_stats(
+        db,
+        user_id,
+        &start_of(from)?,
+        &end_of(&to)?,
+        tz,
+        &tag_filter,
+    )
+    .await
+    .context("error getting session stats")?;
+
+    return Ok(WeeklyDigest {
+        from: *from,
+        to,
+        total_seconds: tags.iter().map(|tag| tag.seconds).sum(),
+        previous_total_seconds: history[week_start - 7..week_start].iter().sum(),
+        tags,
+        days,
+        longest_session_seconds: sessions.longest_seconds.unwrap_or(0).into(),
+        streak,
+    });
+}
+
+fn bar(seconds: i64, max_seconds: i64) -> String {
+    if seconds <= 0 || max_seconds <= 0 {
+        return String::new();
+    }
+
+    let width = (seconds as f64 / max_seconds as f64 * BAR_WIDTH as f64).round() as usize;
+
+    // something tracked always shows
+    return "█".repeat(width.max(1));
+}
+
+/// The week-over-week change, like `+12%`, unless nothing was tracked the week before.
+fn change(digest: &WeeklyDigest) -> Option<String> {
+    if digest.previous_total_seconds <= 0 {
+        return None;
+    }
+
+    let percent = ((digest.total_seconds - digest.previous_total_seconds) as f64
+        / digest.previous_total_seconds as f64
+        * 100.0)
+        .round() as i64;
+
+    return Some(format!("{percent:+}%"));
+}
+
+fn escape_html(text: &str) -> String {
+    return text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;");
+}
+
+fn pad(text: &str, width: usize) -> String {
+    let padding = width.saturating_sub(text.chars().count());
+
+    return format!("{text}{}", " ".repeat(padding));
+}
+
+/// The digest as an email, `link` is the absolute URL of the week's stats.
+pub fn render(locale: &Locale, digest: &WeeklyDigest, link: &str) -> DigestEmail {
+    let templates = templates(locale);
+    let total = format_duration(digest.total_seconds);
+
+    let heading = fill(
+        templates.digest_heading,
+        &[
+            ("from", &digest.from.to_string()),
+            ("to", &digest.to.to_string()),
+        ],
+    );
+
+    let mut summary = vec![fill(templates.digest_total, &[("duration", &total)])];
+    if let Some(change) = change(digest) {
+        summary.push(fill(templates.digest_change, &[("change", &change)]));
+    }
+    summary.push(fill(
+        templates.digest_longest_session,
+        &[("duration", &format_duration(digest.longest_session_seconds))],
+    ));
+    summary.push(fill(
+        templates.digest_streak,
+        &[("days", &digest.streak.to_string())],
+    ));
+
+    let max_tag_seconds = digest.tags.iter().map(|tag| tag.seconds).max().unwrap_or(0);
+    let max_day_seconds = digest.days.iter().copied().max().unwrap_or(0);
+    let label_width = digest
+        .tags
+        .iter()
+        .map(|tag| tag.label.chars().count())
+        .max()
+        .unwrap_or(0);
+    let weekday_width = templates
+        .weekdays
+        .iter()
+        .map(|weekday| weekday.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    let mut text = format!("{heading}\n\n{}\n", summary.join("\n"));
+
+    text.push_str(&format!("\n{}\n", templates.digest_by_tag));
+    for tag in &digest.tags {
+        text.push_str(&format!(
+            "{}  {}  {}\n",
+            pad(&tag.label, label_width),
+            pad(&bar(tag.seconds, max_tag_seconds), BAR_WIDTH),
+            format_duration(tag.seconds)
+        ));
+    }
+
+    text.push_str(&format!("\n{}\n", templates.digest_by_day));
+    for (weekday, seconds) in templates.weekdays.iter().zip(digest.days) {
+        text.push_str(&format!(
+            "{}  {}  {}\n",
+            pad(weekday, weekday_width),
+            pad(&bar(seconds, max_day_seconds), BAR_WIDTH),
+            format_duration(seconds)
+        ));
+    }
+
+    text.push_str(&format!("\n{}: {link}\n", templates.digest_link));
+
+    let row = |label: &str, color: &str, seconds: i64, max_seconds: i64| {
+        return format!(
+            r#"<tr><td>{}</td><td style="color: {}; font-family: monospace;">{}</td><td>{}</td></tr>"#,
+            escape_html(label),
+            escape_html(color),
+            bar(seconds, max_seconds),
+            format_duration(seconds)
+        );
+    };
+
+    let tag_rows = digest
+        .tags
+        .iter()
+        .map(|tag| row(&tag.label, &tag.color, tag.seconds, max_tag_seconds))
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let day_rows = templates
+        .weekdays
+        .iter()
+        .zip(digest.days)
+        .map(|(weekday, seconds)| row(weekday, "#888888", seconds, max_day_seconds))
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<body style="font-family: sans-serif; color: #222222;">
+<h1 style="font-size: 20px;">{heading}</h1>
+<p>{summary}</p>
+<h2 style="font-size: 16px;">{by_tag}</h2>
+<table cellpadding="4">
+{tag_rows}
+</table>
+<h2 style="font-size: 16px;">{by_day}</h2>
+<table cellpadding="4">
+{day_rows}
+</table>
+<p><a href="{link}">{link_text}</a></p>
+</body>
+</html>
+"#,
+        heading = escape_html(&heading),
+        summary = summary
+            .iter()
+            .map(|line| escape_html(line))
+            .collect::<Vec<String>>()
+            .join("<br>\n"),
+        by_tag = escape_html(templates.digest_by_tag),
+        by_day = escape_html(templates.digest_by_day),
+        link = escape_html(link),
+        link_text = escape_html(templates.digest_link),
+    );
+
+    return DigestEmail {
+        subject: fill(templates.digest_subject, &[("duration", &total)]),
+        text,
+        html,
+    };
+}
+
+/// Where a digest goes: the account's email, or the verified email channels
+/// among the devices the user picked for digests.
+pub fn addresses(
+    account_email: &str,
+    preferences: &EventPreferences,
+    channels: &[NotificationChannel],
+) -> Vec<String> {
+    if !preferences.enabled {
+        return vec![];
+    }
+
+    if preferences.devices.is_none() {
+        return vec![account_email.to_owned()];
+    }
+
+    return channels
+        .iter()
+        .filter(|channel| channel.kind == ChannelKind::Email)
+        .filter(|channel| preferences.includes_device(&channel.id))
+        .map(|channel| channel.target.to_owned())
+        .collect();
+}
+
+/// Emails the digests due at `now`, each once a week. A digest that fails
+/// to send isn't tried again until the next week.
+pub async fn send_due(db: &db::Db, email: &EmailChannel, front_url: &str, now: &DateTime<Utc>) {
+    let recipients = match db::notification_settings::get_digest_recipients(db).await {
+        Ok(recipients) => recipients
+            .into_iter()
+            .filter_map(|recipient| {
+                let day = due(&recipient.settings, now)?;
+                return Some((recipient, day));
+            })
+            .collect::<Vec<(DigestRecipient, NaiveDate)>>(),
+        Err(e) => {
+            tracing::error!("error getting digest recipients: {}", e);
+            return;
+        }
+    };
+
+    if recipients.is_empty() {
+        return;
+    }
+
+    let user_ids = recipients
+        .iter()
+        .map(|(recipient, _)| recipient.settings.user_id.to_owned())
+        .collect::<Vec<String>>();
+
+    let preferences_by_user_id = match db::preferences::get_by_user_ids(db, &user_ids).await {
+        Ok(preferences) => preferences,
+        Err(e) => {
+            tracing::error!("error getting digest preferences: {}", e);
+            return;
+        }
+    };
+
+    let channels = match db::notification_channels::get_verified_by_user_ids(db, &user_ids).await {
+        Ok(channels) => channels,
+        Err(e) => {
+            tracing::error!("error getting digest channels: {}", e);
+            return;
+        }
+    };
+
+    let default_preferences = Preferences::default();
+
+    for (recipient, day) in recipients {
+        let user_id = &recipient.settings.user_id;
+        let preferences = preferences_by_user_id
+            .get(user_id)
+            .unwrap_or(&default_preferences)
+            .event(&NotificationEvent::Digests);
+        let user_channels = channels
+            .iter()
+            .filter(|channel| &channel.user_id == user_id)
+            .cloned()
+            .collect::<Vec<NotificationChannel>>();
+
+        let to = addresses(&recipient.email, preferences, &user_channels);
+
+        if to.is_empty() {
+            tracing::debug!("no digest for user {}, turned off", user_id);
+            continue;
+        }
+
+        if let Err(e) = send(db, email, front_url, &recipient, &to, &day).await {
+            tracing::error!(
+                "error sending digest of user {}: {:#}",
+                recipient.settings.user_id,
+                e
+            );
+        }
+    }
+}
+
+async fn send(
+    db: &db::Db,
+    email: &EmailChannel,
+    front_url: &str,
+    recipient: &DigestRecipient,
+    to: &[String],
+    day: &NaiveDate,
+) -> Result<(), anyhow::Error> {
+    let settings = &recipient.settings;
+
+    // another worker may have got there first
+    let claimed = db::notification_settings::claim_digest(db, &settings.user_id, day)
+        .await
+        .context("error claiming digest")?;
+
+    if !claimed {
+        return Ok(());
+    }
+
+    let tz = settings.timezone.parse::<Tz>().unwrap_or(Tz::UTC);
+    let from = previous_week(day);
+
+    let digest = build(db, &settings.user_id, &tz, &from)
+        .await
+        .context("error building digest")?;
+
+    if digest.total_seconds == 0 && digest.previous_total_seconds == 0 {
+        tracing::debug!("no digest for user {}, nothing tracked", settings.user_id);
+        return Ok(());
+    }
+
+    let link = format!(
+        "{front_url}{}",
+        DeepLink::Stats {
+            from: digest.from,
+            to: digest.to,
+        }
+        .to_path()
+    );
+
+    let rendered = render(&Locale::from_settings(Some(settings)), &digest, &link);
+
+    for address in to {
+        email
+            .send_alternative(
+                address,
+                &rendered.subject,
+                rendered.text.to_owned(),
+                rendered.html.to_owned(),
+            )
+            .await
+            .context("error sending digest email")?;
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_smtp::MockSmtpServer;
+    use chrono::{NaiveTime, TimeZone};
+
+    fn day(day: u32) -> NaiveDate {
+        return NaiveDate::from_ymd_opt(2026, 10, day).unwrap();
+    }
+
+    fn helsinki(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        return chrono_tz::Europe::Helsinki
+            .with_ymd_and_hms(2026, 10, day, hour, minute, 0)
+            .unwrap()
+            .with_timezone(&Utc);
+    }
+
+    fn digest() -> WeeklyDigest {
+        return WeeklyDigest {
+            from: day(12),
+            to: day(18),
+            total_seconds: 3 * 60 * 60,
+            previous_total_seconds: 2 * 60 * 60,
+            tags: vec![
+                DigestTag {
+                    label: "work".to_owned(),
+                    color: "#d13c4b".to_owned(),
+                    seconds: 2 * 60 * 60,
+                },
+                DigestTag {
+                    label: "<b>reading</b>".to_owned(),
+                    color: "#4b3cd1".to_owned(),
+                    seconds: 60 * 60,
+                },
+            ],
+            days: [60 * 60, 0, 0, 2 * 60 * 60, 0, 0, 0],
+            longest_session_seconds: 90 * 60,
+            streak: 2,
+        };
+    }
+
+    #[test]
+    fn test_addresses() {
+        let channel = |id: &str, kind, target: &str| NotificationChannel {
+            id: id.to_owned(),
+            user_id: "user".to_owned(),
+            kind,
+            target: target.to_owned(),
+            created_at: Utc::now(),
+            verified_at: Some(Utc::now()),
+            verification_code_hash: None,
+            verification_expires_at: None,
+            verification_attempts: 0,
+            verification_sends: 1,
+            verification_sent_at: None,
+        };
+        let channels = vec![
+            channel("email", ChannelKind::Email, "work@test.local"),
+            channel("hook", ChannelKind::Webhook, "https://hooks.test.local"),
+        ];
+        let preferences = Preferences::default().digests;
+
+        assert_eq!(
+            addresses("user@test.local", &preferences, &channels),
+            vec!["user@test.local".to_owned()]
+        );
+
+        let off = EventPreferences {
+            enabled: false,
+            ..preferences.to_owned()
+        };
+        assert!(addresses("user@test.local", &off, &channels).is_empty());
+
+        let picked = EventPreferences {
+            devices: Some(vec!["email".to_owned(), "hook".to_owned()]),
+            ..preferences.to_owned()
+        };
+        assert_eq!(
+            addresses("user@test.local", &picked, &channels),
+            vec!["work@test.local".to_owned()]
+        );
+    }
+
+    #[test]
+    fn test_previous_week() {
+        // 2026-10-19 is a monday
+        assert_eq!(previous_week(&day(19)), day(12));
+        assert_eq!(previous_week(&day(25)), day(12));
+        assert_eq!(previous_week(&day(26)), day(19));
+    }
+
+    #[test]
+    fn test_due_on_the_picked_weekday() {
+        let settings = NotificationSettings {
+            timezone: "Europe/Helsinki".to_owned(),
+            digest_weekday: Some(0),
+            digest_at: NaiveTime::from_hms_opt(8, 0, 0),
+            ..NotificationSettings::new("user")
+        };
+
+        assert_eq!(due(&settings, &helsinki(19, 8, 0)), Some(day(19)));
+        assert_eq!(due(&settings, &helsinki(19, 7, 59)), None);
+        assert_eq!(due(&settings, &helsinki(20, 8, 0)), None);
+
+        let sent = NotificationSettings {
+            digested_on: Some(day(19)),
+            ..settings
+        };
+        assert_eq!(due(&sent, &helsinki(19, 8, 30)), None);
+    }
+
+    #[test]
+    fn test_render() {
+        let email = render(&Locale::En, &digest(), "https://tasks.test.local/app/stats");
+
+        assert_eq!(email.subject, "Your week: 3 h tracked");
+        assert!(email
+            .text
+            .contains("Your week from 2026-10-12 to 2026-10-18"));
+        assert!(email.text.contains("+50% compared to the week before"));
+        assert!(email.text.contains("Longest session: 1 h 30 min"));
+        assert!(email.text.contains("Streak: 2 days"));
+        assert!(email.text.contains(&format!("{}  2 h\n", "█".repeat(20))));
+        assert!(email
+            .text
+            .contains(&format!("Tue  {}  0 min", " ".repeat(20))));
+
+        assert!(email.html.contains("&lt;b&gt;reading&lt;/b&gt;"));
+        assert!(!email.html.contains("<b>reading"));
+        assert!(email
+            .html
+            .contains(r#"<a href="https://tasks.test.local/app/stats">"#));
+    }
+
+    #[test]
+    fn test_render_without_a_week_before() {
+        let digest = WeeklyDigest {
+            previous_total_seconds: 0,
+            ..digest()
+        };
+
+        let email = render(&Locale::Fr, &digest, "https://tasks.test.local");
+
+        assert_eq!(email.subject, "Votre semaine : 3 h suivies");
+        assert!(!email.text.contains("semaine précédente"));
+    }
+
+    #[sqlx::test(migrations = "../db/migrations")]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_send_due(db: db::Db) {
+        let server = MockSmtpServer::start().await;
+        let email = EmailChannel::new(&server.url(), "tasks@test.local").unwrap();
+
+        let user = db::users::create(&db, "digest@test.local").await.unwrap();
+        let tag = db::tags::insert(&db, &user.id, "work", "#d13c4b")
+            .await
+            .unwrap();
+
+        for (start_day, minutes) in [(6, 60), (13, 90), (14, 30)] {
+            let start_at = helsinki(start_day, 9, 0);
+
+            db::tasks::insert(
+                &db,
+                &db::tasks::Task {
+                    id: db::create_id(),
+                    user_id: user.id.to_owned(),
+                    tag_id: tag.id.to_owned(),
+                    is_manual: true,
+                    seconds: minutes * 60,
+                    start_at,
+                    end_at: start_at + Duration::minutes(minutes.into()),
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        db::notification_settings::set_quiet_hours(&db, &user.id, "Europe/Helsinki", None)
+            .await
+            .unwrap();
+        db::notification_settings::set_digest_schedule(
+            &db,
+            &user.id,
+            Some((0, NaiveTime::from_hms_opt(8, 0, 0).unwrap())),
+        )
+        .await
+        .unwrap();
+
+        let digest = build(&db, &user.id, &chrono_tz::Europe::Helsinki, &day(12))
+            .await
+            .unwrap();
+        assert_eq!(digest.total_seconds, 2 * 60 * 60);
+        assert_eq!(digest.previous_total_seconds, 60 * 60);
+        assert_eq!(digest.days[1], 90 * 60);
+        assert_eq!(digest.longest_session_seconds, 90 * 60);
+        // nothing after wednesday
+        assert_eq!(digest.streak, 0);
+
+        send_due(&db, &email, "https://tasks.test.local", &helsinki(19, 8, 0)).await;
+        send_due(&db, &email, "https://tasks.test.local", &helsinki(19, 8, 1)).await;
+
+        let emails = server.emails().await;
+        assert_eq!(emails.len(), 1);
+        assert_eq!(emails[0].to, vec!["digest@test.local".to_owned()]);
+        assert!(emails[0].data.contains("Subject: Your week: 2 h tracked"));
+        assert!(emails[0].data.contains("multipart/alternative"));
+        assert!(emails[0].data.contains("text/html"));
+
+        // turned off, the next week's digest isn't sent
+        let mut preferences = db::preferences::get(&db, &user.id).await.unwrap();
+        preferences.digests.enabled = false;
+        db::preferences::update(&db, &user.id, &preferences)
+            .await
+            .unwrap();
+
+        send_due(&db, &email, "https://tasks.test.local", &helsinki(26, 8, 0)).await;
+        assert_eq!(server.emails().await.len(), 1);
+    }
+}
@@ -1,85 +1,440 @@
-use std::{collections::HashMap, time::Duration};
+use crate::quiet::QuietDecision;
+use config::CONFIG;
+use db::{
+    notification_channels::NotificationChannel,
+    notification_settings::NotificationSettings,
+    notification_subs::NotificationSub,
+    notifications::{Notification, NotificationStatus},
+    preferences::{NotificationEvent, Preferences},
+    tasks::TaskWithTag,
+};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
 
-pub use crate::send::send_notification;
+pub use crate::channels::{
+    deliver_to_channels, verification_message, Channel, ChannelMessage, Channels, ChannelsOutcome,
+    CHANNELS,
+};
+pub use crate::deliver::{deliver, RetryPolicy, RETRY_POLICY};
+pub use crate::push_policy::{PushOptions, PushPolicy, PUSH_POLICY};
+pub use crate::quiet::{QuietAction, QuietHoursPolicy, QUIET_HOURS_POLICY};
+pub use crate::send::{
+    send_test_notification, FailureKind, PushAction, PushClient, PushMessage, PushPayload, CLIENT,
+};
+pub use crate::templates::{render, Locale, RenderedNotification};
+pub use crate::vapid::VapidKeys;
+mod actions;
+mod channels;
+mod daily;
+mod deliver;
+mod digest;
+#[cfg(test)]
+mod mock_push;
+#[cfg(test)]
+mod mock_smtp;
+mod push_policy;
+mod quiet;
+mod scheduler;
 mod send;
+mod templates;
+pub mod vapid;
 
-pub async fn start_notification_service() {
-    tracing::info!("starting notification service");
+/// Subscriptions that failed and haven't received anything for this long are deleted.
+pub static STALE_SUB_AGE: once_cell::sync::Lazy<chrono::Duration> =
+    once_cell::sync::Lazy::new(|| chrono::Duration::days(60));
 
-    let db = db::get_db().await;
+/// Notifications the service is done with are kept this long, for their delivery records.
+pub static NOTIFICATION_RETENTION: once_cell::sync::Lazy<chrono::Duration> =
+    once_cell::sync::Lazy::new(|| chrono::Duration::days(30));
 
-    tracing::info!("notification service started");
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
-    loop {
-        let notifs = db::notifications::get_to_send(&db).await;
+/// Nudges, summaries and digests are picked to the minute.
+const DAILY_INTERVAL: Duration = Duration::from_secs(60);
 
-        if let Err(e) = notifs {
-            tracing::error!("failed to get notifications: {}", e);
-        } else if let Ok(notifs) = notifs {
-            if notifs.len() > 0 {
-                let user_ids = notifs
-                    .iter()
-                    .map(|n| n.user_id.to_owned())
-                    .collect::<Vec<String>>();
+/// How long a claimed notification is reserved for this worker,
+/// after that another worker may pick it up.
+static CLAIM_LEASE: once_cell::sync::Lazy<chrono::Duration> =
+    once_cell::sync::Lazy::new(|| chrono::Duration::minutes(5));
+
+const CLAIM_BATCH_SIZE: i64 = 100;
+
+async fn prune_stale_subs(db: &db::Db) {
+    let cutoff = chrono::Utc::now() - *STALE_SUB_AGE;
+
+    match db::notification_subs::delete_stale(db, &cutoff).await {
+        Err(e) => tracing::error!("error pruning notification subs: {}", e),
+        Ok(subs) => {
+            for sub in subs {
+                tracing::info!(
+                    "pruned notification sub {} of user {}, expired at {:?}, last success at {:?}",
+                    sub.id,
+                    sub.user_id,
+                    sub.expired_at,
+                    sub.last_success_at
+                );
+            }
+        }
+    }
+}
+
+async fn prune_finished_notifications(db: &db::Db) {
+    let cutoff = chrono::Utc::now() - *NOTIFICATION_RETENTION;
+
+    match db::notifications::delete_finished(db, &cutoff).await {
+        Err(e) => tracing::error!("error pruning notifications: {}", e),
+        Ok(deleted) => tracing::info!("pruned {} finished notifications", deleted),
+    }
+}
+
+async fn prune_used_action_tokens(db: &db::Db) {
+    match db::action_tokens::delete_expired(db, &chrono::Utc::now()).await {
+        Err(e) => tracing::error!("error pruning used action tokens: {}", e),
+        Ok(deleted) => tracing::info!("pruned {} used action tokens", deleted),
+    }
+}
+
+/// Claims a batch of due notifications and delivers them.
+async fn send_due(db: &db::Db, worker_id: &str) {
+    let notifs = db::notifications::claim_due(
+        db,
+        worker_id,
+        &chrono::Utc::now(),
+        &CLAIM_LEASE,
+        CLAIM_BATCH_SIZE,
+    )
+    .await;
+
+    if let Err(e) = notifs {
+        tracing::error!("failed to get notifications: {}", e);
+    } else if let Ok(notifs) = notifs {
+        if !notifs.is_empty() {
+            let user_ids = notifs
+                .iter()
+                .map(|n| n.user_id.to_owned())
+                .collect::<Vec<String>>();
 
-                let subs = db::notification_subs::get_by_user_ids(&db, &user_ids).await;
+            let subs = db::notification_subs::get_by_user_ids(db, &user_ids).await;
 
-                let notif_ids = notifs
+            if subs.is_err() {
+                tracing::error!("error getting notification subs");
+            } else if let Ok(subs) = subs {
+                tracing::debug!(
+                    "found {} notifications for {} subs",
+                    notifs.len(),
+                    subs.len()
+                );
+
+                let mut subs_by_user_id = HashMap::new();
+
+                for sub in subs {
+                    let user_id = sub.user_id.to_owned();
+                    let subs = subs_by_user_id.entry(user_id).or_insert(vec![]);
+                    subs.push(sub);
+                }
+
+                let settings_by_user_id =
+                    match db::notification_settings::get_by_user_ids(db, &user_ids).await {
+                        Ok(settings) => settings
+                            .into_iter()
+                            .map(|settings| (settings.user_id.to_owned(), settings))
+                            .collect::<HashMap<String, NotificationSettings>>(),
+                        Err(e) => {
+                            tracing::error!("error getting notification settings: {}", e);
+                            HashMap::new()
+                        }
+                    };
+
+                let preferences_by_user_id =
+                    match db::preferences::get_by_user_ids(db, &user_ids).await {
+                        Ok(preferences) => preferences,
+                        Err(e) => {
+                            tracing::error!("error getting notification preferences: {}", e);
+                            HashMap::new()
+                        }
+                    };
+
+                let mut channels_by_user_id = HashMap::new();
+
+                match db::notification_channels::get_verified_by_user_ids(db, &user_ids).await {
+                    Ok(channels) => {
+                        for channel in channels {
+                            let user_id = channel.user_id.to_owned();
+                            let channels = channels_by_user_id.entry(user_id).or_insert(vec![]);
+                            channels.push(channel);
+                        }
+                    }
+                    Err(e) => tracing::error!("error getting notification channels: {}", e),
+                }
+
+                let task_ids = notifs
                     .iter()
-                    .map(|n| n.id.to_owned())
+                    .filter_map(|n| n.task_id.to_owned())
                     .collect::<Vec<String>>();
 
-                if let Err(_) = subs {
-                    tracing::error!("error getting notification subs");
-                } else if let Ok(subs) = subs {
-                    tracing::debug!(
-                        "found {} notifications for {} subs",
-                        notifs.len(),
-                        subs.len()
-                    );
-
-                    let mut subs_by_user_id = HashMap::new();
-
-                    for sub in subs {
-                        let user_id = sub.user_id.to_owned();
-                        let subs = subs_by_user_id.entry(user_id).or_insert(vec![]);
-                        subs.push(sub);
+                // tasks that were deleted since are rendered from the stored text
+                let tasks_by_id = match db::tasks::get_by_ids(db, &task_ids).await {
+                    Ok(tasks) => tasks
+                        .into_iter()
+                        .map(|task| (task.id.to_owned(), task))
+                        .collect::<HashMap<String, TaskWithTag>>(),
+                    Err(e) => {
+                        tracing::error!("error getting notification tasks: {}", e);
+                        HashMap::new()
                     }
+                };
+
+                let default_preferences = Preferences::default();
 
-                    for notif in notifs {
-                        let user_id = notif.user_id.to_owned();
-                        let subs = subs_by_user_id.get_mut(&user_id);
+                for notif in notifs {
+                    let now = chrono::Utc::now();
+
+                    let preferences = preferences_by_user_id
+                        .get(&notif.user_id)
+                        .unwrap_or(&default_preferences)
+                        .event(&NotificationEvent::from(&notif.kind));
+
+                    if !preferences.enabled {
+                        tracing::debug!("dropping notification {}, turned off", notif.id);
+                        set_aside(db, worker_id, &notif, &NotificationStatus::Dropped, &now).await;
+                        continue;
+                    }
 
-                        if let None = subs {
+                    let settings = settings_by_user_id.get(&notif.user_id);
+                    let locale = Locale::from_settings(settings);
+                    let task = notif.task_id.as_ref().and_then(|id| tasks_by_id.get(id));
+                    let text = render(&locale, &notif, task);
+
+                    let decision = quiet::decide(&QUIET_HOURS_POLICY, settings, &notif.kind, &now);
+
+                    let silent = match decision {
+                        QuietDecision::Send { silent } => silent,
+                        QuietDecision::Hold { until } => {
+                            tracing::debug!("holding notification {} until {}", notif.id, until);
+                            set_aside(db, worker_id, &notif, &NotificationStatus::Pending, &until)
+                                .await;
                             continue;
-                        } else if let Some(subs) = subs {
-                            if subs.len() <= 0 {
-                                continue;
-                            }
+                        }
+                        QuietDecision::Drop => {
+                            tracing::debug!("dropping notification {} in quiet hours", notif.id);
+                            set_aside(db, worker_id, &notif, &NotificationStatus::Dropped, &now)
+                                .await;
+                            // still shows up in the inbox as something they missed
+                            add_to_inbox(db, &notif, &text, &now).await;
+                            continue;
+                        }
+                    };
+
+                    let subs = subs_by_user_id.get(&notif.user_id).map_or(vec![], |subs| {
+                        subs.iter()
+                            .filter(|sub| preferences.includes_device(&sub.id))
+                            .cloned()
+                            .collect::<Vec<NotificationSub>>()
+                    });
 
-                            let futures = subs.iter().map(|sub| {
-                                let future = send_notification(&sub, &notif.title, &notif.message);
+                    let options = PUSH_POLICY.options(&notif.kind);
+                    let topic = options
+                        .collapse_by_task
+                        .then(|| notif.task_id.as_deref().and_then(push_policy::task_topic))
+                        .flatten();
 
-                                async move {
-                                    let response = future.await;
+                    let message = PushMessage {
+                        payload: PushPayload {
+                            title: text.title.to_owned(),
+                            message: text.message.to_owned(),
+                            actions: actions::task_actions(&CONFIG.secret, &locale, &notif, &now),
+                            silent: silent || !preferences.sound,
+                            tag: topic.to_owned(),
+                            link: notif.link.to_owned(),
+                        },
+                        urgency: preferences.urgency.unwrap_or(options.urgency),
+                        ttl_seconds: options.ttl_seconds,
+                        topic,
+                    };
 
-                                    if let Err(e) = response {
-                                        tracing::error!("Failed to send notification: {}", e);
-                                    } else if let Ok(_response) = response {
-                                        tracing::info!("Notification sent");
-                                    }
-                                }
+                    let channels =
+                        channels_by_user_id
+                            .get(&notif.user_id)
+                            .map_or(vec![], |channels| {
+                                channels
+                                    .iter()
+                                    .filter(|channel| preferences.includes_device(&channel.id))
+                                    .cloned()
+                                    .collect::<Vec<NotificationChannel>>()
                             });
 
-                            futures::future::join_all(futures).await;
+                    let channel_message = ChannelMessage {
+                        title: text.title.to_owned(),
+                        message: text.message.to_owned(),
+                        link: channels::absolute_link(notif.link.as_deref()),
+                    };
+
+                    let channels_outcome = match deliver_to_channels(
+                        db,
+                        &CHANNELS,
+                        &notif,
+                        &channel_message,
+                        &channels,
+                    )
+                    .await
+                    {
+                        Ok(outcome) => outcome,
+                        Err(e) => {
+                            tracing::error!(
+                                "error delivering notification {} to channels: {}",
+                                notif.id,
+                                e
+                            );
+                            // tried again with the push retries
+                            ChannelsOutcome {
+                                sent_any: false,
+                                error: Some(format!("{:#}", e)),
+                            }
+                        }
+                    };
+
+                    let status = deliver(
+                        db,
+                        worker_id,
+                        &CLIENT,
+                        &RETRY_POLICY,
+                        &notif,
+                        &message,
+                        &subs,
+                        &channels_outcome,
+                    )
+                    .await;
+
+                    if let Err(e) = status {
+                        tracing::error!("error delivering notification {}: {}", notif.id, e);
+                    } else if let Ok(status) = status {
+                        tracing::debug!("notification {} is {}", notif.id, status.as_ref());
+
+                        if status != NotificationStatus::Pending {
+                            add_to_inbox(db, &notif, &text, &now).await;
                         }
                     }
                 }
+            }
+        }
+    }
+}
+
+/// Releases a notification that isn't sent now because of quiet hours,
+/// a held one stays pending until `next_attempt_at`.
+async fn set_aside(
+    db: &db::Db,
+    worker_id: &str,
+    notif: &Notification,
+    status: &NotificationStatus,
+    next_attempt_at: &chrono::DateTime<chrono::Utc>,
+) {
+    let result = db::notifications::update_status(
+        db,
+        &notif.id,
+        worker_id,
+        status,
+        notif.attempts,
+        next_attempt_at,
+        notif.last_error.as_deref(),
+    )
+    .await;
+
+    if let Err(e) = result {
+        tracing::error!("error setting notification {} aside: {}", notif.id, e);
+    }
+}
+
+/// Keeps a notification the service is done with in the user's history.
+async fn add_to_inbox(
+    db: &db::Db,
+    notif: &Notification,
+    text: &RenderedNotification,
+    now: &chrono::DateTime<chrono::Utc>,
+) {
+    if let Err(e) =
+        db::inbox::insert_for_notification(db, notif, &text.title, &text.message, now).await
+    {
+        tracing::error!("error adding notification {} to inbox: {}", notif.id, e);
+    }
+}
+
+pub async fn start_notification_service() {
+    tracing::info!("starting notification service");
 
-                let _ = db::notifications::delete_by_ids(&db, &notif_ids).await;
+    let db = db::get_db().await;
+    // identifies this instance's claims when several instances run
+    let worker_id = db::create_id();
+
+    // without a listener changes are only picked up by the reconciliation sweep
+    let mut listener = match db::notifications::listen_for_changes(&db).await {
+        Ok(listener) => Some(listener),
+        Err(e) => {
+            tracing::error!("error listening for notification changes: {}", e);
+            None
+        }
+    };
+
+    tracing::info!("notification service {} started", worker_id);
+
+    let mut last_prune_at: Option<Instant> = None;
+    let mut last_daily_at: Option<Instant> = None;
+
+    loop {
+        if last_prune_at.is_none_or(|at| at.elapsed() >= PRUNE_INTERVAL) {
+            prune_stale_subs(&db).await;
+            prune_finished_notifications(&db).await;
+            prune_used_action_tokens(&db).await;
+            last_prune_at = Some(Instant::now());
+        }
+
+        if last_daily_at.is_none_or(|at| at.elapsed() >= DAILY_INTERVAL) {
+            let now = chrono::Utc::now();
+
+            daily::queue_due(&db, &now).await;
+
+            // digests go out by email only
+            if let Some(email) = CHANNELS.email.as_ref() {
+                digest::send_due(&db, email, &CONFIG.front_url, &now).await;
             }
+
+            last_daily_at = Some(Instant::now());
         }
 
-        tokio::time::sleep(Duration::from_secs(10)).await;
+        send_due(&db, &worker_id).await;
+
+        let next_attempt_at = match db::notifications::get_next_attempt_at(&db).await {
+            Ok(next_attempt_at) => next_attempt_at,
+            Err(e) => {
+                tracing::error!("error getting next notification: {}", e);
+                None
+            }
+        };
+
+        let sleep = scheduler::sleep_duration(
+            next_attempt_at,
+            &chrono::Utc::now(),
+            scheduler::RECONCILE_INTERVAL,
+        );
+
+        match listener.as_mut() {
+            None => tokio::time::sleep(sleep).await,
+            Some(listener) => {
+                tokio::select! {
+                    _ = tokio::time::sleep(sleep) => {}
+                    notification = listener.recv() => {
+                        if let Err(e) = notification {
+                            tracing::error!("error receiving notification change: {}", e);
+                            // don't spin while the connection is down
+                            tokio::time::sleep(Duration::from_secs(1)).await;
+                        } else {
+                            tracing::debug!("notifications changed, rescheduling");
+                        }
+                    }
+                }
+            }
+        }
     }
 }
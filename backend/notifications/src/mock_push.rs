@@ -0,0 +1,115 @@
+//! A local stand-in for a push service, records the requests it receives
+//! and answers them with the given status codes. It's also the receiver
+//! webhook and ntfy channels are tested against.
+
+use db::notification_subs::NotificationSub;
+use std::{collections::HashMap, sync::Arc};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    sync::Mutex,
+};
+
+pub static TEST_VAPID_PRIVATE_KEY: &str = "kyzA6_mnukcJljo2wFPdrZ9YBhhMgv8IABGJQgtve_o";
+
+static TEST_SUB_P256DH: &str =
+    "BI0wI14UUYNxm9KVQ9A4ucG_87zPeZUPpPLHnJ4zLdZz9883cRKLI5LuGIzCrXDZnInejJR7W9cCeiRNU8jAuCw";
+static TEST_SUB_AUTH: &str = "vpDeDNQ7ew1ty9kXxwidWg";
+
+#[derive(Debug, Clone)]
+pub struct MockPushRequest {
+    pub path: String,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+pub struct MockPushEndpoint {
+    pub url: String,
+    requests: Arc<Mutex<Vec<MockPushRequest>>>,
+}
+
+impl MockPushEndpoint {
+    /// Answers the requests with `statuses` in order, repeating the last one.
+    pub async fn start(statuses: Vec<u16>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            let mut statuses = statuses.into_iter().peekable();
+
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = BufReader::new(stream);
+
+                let mut request_line = String::new();
+                stream.read_line(&mut request_line).await.unwrap();
+                let path = request_line
+                    .split_whitespace()
+                    .nth(1)
+                    .unwrap_or("")
+                    .to_owned();
+
+                let mut headers = HashMap::new();
+                loop {
+                    let mut line = String::new();
+                    stream.read_line(&mut line).await.unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        headers.insert(name.trim().to_lowercase(), value.trim().to_owned());
+                    }
+                }
+
+                let content_length = headers
+                    .get("content-length")
+                    .and_then(|length| length.parse::<usize>().ok())
+                    .unwrap_or(0);
+                let mut body = vec![0; content_length];
+                stream.read_exact(&mut body).await.unwrap();
+
+                recorded.lock().await.push(MockPushRequest {
+                    path,
+                    headers,
+                    body,
+                });
+
+                let status = match statuses.len() {
+                    0 | 1 => *statuses.peek().unwrap_or(&201),
+                    _ => statuses.next().unwrap_or(201),
+                };
+
+                let response = format!(
+                    "HTTP/1.1 {status} Mock\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                );
+                let _ = stream.get_mut().write_all(response.as_bytes()).await;
+            }
+        });
+
+        return Self { url, requests };
+    }
+
+    pub fn sub(&self, user_id: &str) -> NotificationSub {
+        return NotificationSub {
+            id: db::create_id(),
+            user_id: user_id.to_owned(),
+            endpoint: format!("{}/push/{}", self.url, db::create_id()),
+            p256dh: TEST_SUB_P256DH.to_owned(),
+            auth: TEST_SUB_AUTH.to_owned(),
+            created_at: chrono::Utc::now(),
+            last_success_at: None,
+            expired_at: None,
+            name: None,
+            user_agent: None,
+            vapid_public_key: None,
+            resubscribe_requested_at: None,
+        };
+    }
+
+    pub async fn requests(&self) -> Vec<MockPushRequest> {
+        return self.requests.lock().await.clone();
+    }
+}
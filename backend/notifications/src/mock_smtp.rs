@@ -0,0 +1,108 @@
+//! A local stand-in for an SMTP server, accepts every email and records it.
+
+use std::sync::Arc;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    sync::Mutex,
+};
+
+#[derive(Debug, Clone)]
+pub struct MockEmail {
+    pub from: String,
+    pub to: Vec<String>,
+    /// Headers and body as sent after `DATA`.
+    pub data: String,
+}
+
+pub struct MockSmtpServer {
+    port: u16,
+    emails: Arc<Mutex<Vec<MockEmail>>>,
+}
+
+fn address(line: &str) -> String {
+    return line
+        .split_once(':')
+        .map(|(_, address)| address.trim().trim_start_matches('<'))
+        .and_then(|address| address.split('>').next())
+        .unwrap_or("")
+        .to_owned();
+}
+
+impl MockSmtpServer {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let emails = Arc::new(Mutex::new(vec![]));
+
+        let recorded = emails.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let recorded = recorded.clone();
+
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    let mut email = MockEmail {
+                        from: String::new(),
+                        to: vec![],
+                        data: String::new(),
+                    };
+
+                    let _ = stream.get_mut().write_all(b"220 mock ESMTP\r\n").await;
+
+                    loop {
+                        let mut line = String::new();
+                        if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+                            break;
+                        }
+                        let command = line.trim_end().to_uppercase();
+
+                        let reply = if command.starts_with("EHLO") {
+                            "250-mock\r\n250 8BITMIME\r\n"
+                        } else if command.starts_with("MAIL FROM") {
+                            email.from = address(line.trim_end());
+                            "250 OK\r\n"
+                        } else if command.starts_with("RCPT TO") {
+                            email.to.push(address(line.trim_end()));
+                            "250 OK\r\n"
+                        } else if command == "DATA" {
+                            let _ = stream.get_mut().write_all(b"354 go ahead\r\n").await;
+
+                            loop {
+                                let mut data_line = String::new();
+                                if stream.read_line(&mut data_line).await.unwrap_or(0) == 0
+                                    || data_line == ".\r\n"
+                                {
+                                    break;
+                                }
+                                email.data.push_str(&data_line);
+                            }
+
+                            recorded.lock().await.push(email.clone());
+                            "250 OK\r\n"
+                        } else if command == "QUIT" {
+                            let _ = stream.get_mut().write_all(b"221 bye\r\n").await;
+                            break;
+                        } else {
+                            "250 OK\r\n"
+                        };
+
+                        let _ = stream.get_mut().write_all(reply.as_bytes()).await;
+                    }
+                });
+            }
+        });
+
+        return Self { port, emails };
+    }
+
+    /// A connection URL for lettre, without TLS.
+    pub fn url(&self) -> String {
+        return format!("smtp://127.0.0.1:{}", self.port);
+    }
+
+    pub async fn emails(&self) -> Vec<MockEmail> {
+        return self.emails.lock().await.clone();
+    }
+}
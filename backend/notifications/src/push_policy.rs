@@ -0,0 +1,80 @@
+use db::{notifications::NotificationKind, preferences::Urgency};
+
+/// How the push service treats one kind of notification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PushOptions {
+    /// How long the push service keeps trying to reach an offline device,
+    /// after that the notification is thrown away.
+    pub ttl_seconds: u32,
+    /// Used unless the user picked an urgency in their preferences.
+    pub urgency: Urgency,
+    /// Whether a newer notification for the same task replaces this one
+    /// while it's still waiting to be delivered.
+    pub collapse_by_task: bool,
+}
+
+pub struct PushPolicy {
+    pub finished: PushOptions,
+    pub reminder: PushOptions,
+    pub daily: PushOptions,
+}
+
+impl PushPolicy {
+    pub fn options(&self, kind: &NotificationKind) -> &PushOptions {
+        return match kind {
+            NotificationKind::Finished => &self.finished,
+            NotificationKind::Reminder => &self.reminder,
+            NotificationKind::Nudge | NotificationKind::Summary => &self.daily,
+        };
+    }
+}
+
+/// A finished task is old news after half an hour, a reminder after a few minutes.
+/// Nudges and summaries can wait an hour for a device to come back.
+pub static PUSH_POLICY: PushPolicy = PushPolicy {
+    finished: PushOptions {
+        ttl_seconds: 30 * 60,
+        urgency: Urgency::High,
+        collapse_by_task: true,
+    },
+    reminder: PushOptions {
+        ttl_seconds: 5 * 60,
+        urgency: Urgency::Normal,
+        collapse_by_task: true,
+    },
+    daily: PushOptions {
+        ttl_seconds: 60 * 60,
+        urgency: Urgency::Low,
+        collapse_by_task: false,
+    },
+};
+
+/// Used for messages that aren't about a task, like test notifications.
+pub const DEFAULT_TTL_SECONDS: u32 = 24 * 60 * 60;
+
+/// The Topic header for the notifications of a task. Topics are at most
+/// 32 characters of the URL-safe base64 alphabet, which ULIDs are.
+pub fn task_topic(task_id: &str) -> Option<String> {
+    let is_valid = !task_id.is_empty()
+        && task_id.len() <= 32
+        && task_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    return is_valid.then(|| task_id.to_owned());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_topic() {
+        let task_id = db::create_id();
+
+        assert_eq!(task_topic(&task_id), Some(task_id));
+        assert_eq!(task_topic(""), None);
+        assert_eq!(task_topic("not/a/topic"), None);
+        assert_eq!(task_topic(&"a".repeat(33)), None);
+    }
+}
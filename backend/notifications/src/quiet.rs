@@ -0,0 +1,213 @@
+use chrono::{DateTime, Duration, NaiveTime, Utc};
+use db::{notification_settings::NotificationSettings, notifications::NotificationKind};
+
+/// What happens to a notification that is due while the user doesn't want to be disturbed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuietAction {
+    /// Send it once the quiet time is over.
+    Hold,
+    /// Don't send it at all.
+    Drop,
+    /// Send it without sound or vibration.
+    Silent,
+}
+
+pub struct QuietHoursPolicy {
+    pub finished: QuietAction,
+    pub reminder: QuietAction,
+    /// Nudges and summaries.
+    pub daily: QuietAction,
+}
+
+impl QuietHoursPolicy {
+    pub fn action(&self, kind: &NotificationKind) -> QuietAction {
+        return match kind {
+            NotificationKind::Finished => self.finished,
+            NotificationKind::Reminder => self.reminder,
+            NotificationKind::Nudge | NotificationKind::Summary => self.daily,
+        };
+    }
+}
+
+/// Reminders are only useful during the task, a finished task is still worth knowing about.
+/// Nudges and summaries wait, the user picked a time for them that may overlap their quiet hours.
+pub static QUIET_HOURS_POLICY: QuietHoursPolicy = QuietHoursPolicy {
+    finished: QuietAction::Silent,
+    reminder: QuietAction::Drop,
+    daily: QuietAction::Hold,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuietDecision {
+    Send { silent: bool },
+    Hold { until: DateTime<Utc> },
+    Drop,
+}
+
+pub fn decide(
+    policy: &QuietHoursPolicy,
+    settings: Option<&NotificationSettings>,
+    kind: &NotificationKind,
+    now: &DateTime<Utc>,
+) -> QuietDecision {
+    let Some(until) = settings.and_then(|settings| quiet_until(settings, now)) else {
+        return QuietDecision::Send { silent: false };
+    };
+
+    return match policy.action(kind) {
+        QuietAction::Hold => QuietDecision::Hold { until },
+        QuietAction::Drop => QuietDecision::Drop,
+        QuietAction::Silent => QuietDecision::Send { silent: true },
+    };
+}
+
+/// End of the quiet hours window `now` is in, if any. Windows where the start
+/// is after the end wrap past midnight, like 22:00 to 07:00.
+fn quiet_hours_until(
+    start: &NaiveTime,
+    end: &NaiveTime,
+    tz: &chrono_tz::Tz,
+    now: &DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    if start == end {
+        return None;
+    }
+
+    let local = now.with_timezone(tz).naive_local();
+    let time = local.time();
+    let today = local.date();
+
+    let end_date = if start < end {
+        if time < *start || time >= *end {
+            return None;
+        }
+        today
+    } else if time >= *start {
+        today + Duration::days(1)
+    } else if time < *end {
+        today
+    } else {
+        return None;
+    };
+
+    return Some(db::rollups::local_to_utc(&end_date.and_time(*end), tz));
+}
+
+/// When the user can be disturbed again, `None` if they can be right now.
+pub fn quiet_until(settings: &NotificationSettings, now: &DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let dnd_until = settings.dnd_until.filter(|dnd_until| dnd_until > now);
+
+    let tz = settings
+        .timezone
+        .parse::<chrono_tz::Tz>()
+        .unwrap_or(chrono_tz::UTC);
+
+    let quiet_hours_until = match (&settings.quiet_hours_start, &settings.quiet_hours_end) {
+        (Some(start), Some(end)) => quiet_hours_until(start, end, &tz, now),
+        _ => None,
+    };
+
+    return dnd_until.max(quiet_hours_until);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn settings(start: (u32, u32), end: (u32, u32)) -> NotificationSettings {
+        return NotificationSettings {
+            timezone: "Europe/Helsinki".to_owned(),
+            quiet_hours_start: NaiveTime::from_hms_opt(start.0, start.1, 0),
+            quiet_hours_end: NaiveTime::from_hms_opt(end.0, end.1, 0),
+            ..NotificationSettings::new("user")
+        };
+    }
+
+    fn helsinki(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        return chrono_tz::Europe::Helsinki
+            .with_ymd_and_hms(2024, 6, day, hour, minute, 0)
+            .unwrap()
+            .with_timezone(&Utc);
+    }
+
+    #[test]
+    fn test_quiet_hours_within_a_day() {
+        let settings = settings((12, 0), (13, 30));
+
+        assert_eq!(quiet_until(&settings, &helsinki(3, 11, 59)), None);
+        assert_eq!(
+            quiet_until(&settings, &helsinki(3, 12, 0)),
+            Some(helsinki(3, 13, 30))
+        );
+        assert_eq!(quiet_until(&settings, &helsinki(3, 13, 30)), None);
+    }
+
+    #[test]
+    fn test_quiet_hours_past_midnight() {
+        let settings = settings((22, 0), (7, 0));
+
+        assert_eq!(quiet_until(&settings, &helsinki(3, 21, 0)), None);
+        assert_eq!(
+            quiet_until(&settings, &helsinki(3, 23, 0)),
+            Some(helsinki(4, 7, 0))
+        );
+        assert_eq!(
+            quiet_until(&settings, &helsinki(4, 6, 59)),
+            Some(helsinki(4, 7, 0))
+        );
+        assert_eq!(quiet_until(&settings, &helsinki(4, 7, 0)), None);
+    }
+
+    #[test]
+    fn test_do_not_disturb() {
+        let now = helsinki(3, 9, 0);
+        let mut settings = settings((22, 0), (7, 0));
+
+        settings.dnd_until = Some(now - Duration::minutes(1));
+        assert_eq!(quiet_until(&settings, &now), None);
+
+        settings.dnd_until = Some(now + Duration::hours(1));
+        assert_eq!(quiet_until(&settings, &now), Some(now + Duration::hours(1)));
+
+        // the later of the two wins when both apply
+        let night = helsinki(3, 23, 0);
+        settings.dnd_until = Some(night + Duration::minutes(30));
+        assert_eq!(quiet_until(&settings, &night), Some(helsinki(4, 7, 0)));
+    }
+
+    #[test]
+    fn test_decide_by_kind() {
+        let now = helsinki(3, 23, 0);
+        let settings = settings((22, 0), (7, 0));
+        let policy = QuietHoursPolicy {
+            finished: QuietAction::Hold,
+            reminder: QuietAction::Drop,
+            daily: QuietAction::Silent,
+        };
+
+        assert_eq!(
+            decide(&policy, None, &NotificationKind::Finished, &now),
+            QuietDecision::Send { silent: false }
+        );
+        assert_eq!(
+            decide(&policy, Some(&settings), &NotificationKind::Finished, &now),
+            QuietDecision::Hold {
+                until: helsinki(4, 7, 0)
+            }
+        );
+        assert_eq!(
+            decide(&policy, Some(&settings), &NotificationKind::Reminder, &now),
+            QuietDecision::Drop
+        );
+        assert_eq!(
+            decide(
+                &QUIET_HOURS_POLICY,
+                Some(&settings),
+                &NotificationKind::Finished,
+                &now
+            ),
+            QuietDecision::Send { silent: true }
+        );
+    }
+}
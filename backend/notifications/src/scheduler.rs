@@ -0,0 +1,47 @@
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+/// Longest the scheduler sleeps without checking the database, in case it
+/// missed a change while the listener was disconnected.
+pub const RECONCILE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long to sleep until the next notification is due, capped to the
+/// reconciliation interval. Overdue notifications wake it right away.
+pub fn sleep_duration(
+    next_attempt_at: Option<DateTime<Utc>>,
+    now: &DateTime<Utc>,
+    reconcile_interval: Duration,
+) -> Duration {
+    let Some(next_attempt_at) = next_attempt_at else {
+        return reconcile_interval;
+    };
+
+    let until_due = (next_attempt_at - *now).to_std().unwrap_or(Duration::ZERO);
+
+    return until_due.min(reconcile_interval);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sleep_duration() {
+        let now = Utc::now();
+        let interval = Duration::from_secs(60);
+
+        assert_eq!(sleep_duration(None, &now, interval), interval);
+        assert_eq!(
+            sleep_duration(Some(now + chrono::Duration::seconds(5)), &now, interval),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            sleep_duration(Some(now + chrono::Duration::hours(1)), &now, interval),
+            interval
+        );
+        assert_eq!(
+            sleep_duration(Some(now - chrono::Duration::seconds(5)), &now, interval),
+            Duration::ZERO
+        );
+    }
+}
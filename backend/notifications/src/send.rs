@@ -1,55 +1,325 @@
+use crate::push_policy::DEFAULT_TTL_SECONDS;
 use anyhow::Context;
 use config::CONFIG;
-use db::notification_subs::NotificationSub;
-use serde_json::json;
+use db::{notification_subs::NotificationSub, preferences::Urgency};
 use web_push::{
     ContentEncoding, IsahcWebPushClient, PartialVapidSignatureBuilder, SubscriptionInfo,
-    VapidSignatureBuilder, WebPushClient, WebPushMessageBuilder, URL_SAFE_NO_PAD,
+    WebPushClient, WebPushError, WebPushMessageBuilder,
 };
 
-static CLIENT: once_cell::sync::Lazy<IsahcWebPushClient> = once_cell::sync::Lazy::new(|| {
-    IsahcWebPushClient::new().expect("error creating static web push client")
+/// A button on the notification, the service worker posts `token`
+/// to the notification actions endpoint when it's clicked.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PushAction {
+    pub action: String,
+    pub title: String,
+    pub token: String,
+}
+
+/// What the service worker receives, as JSON.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PushPayload {
+    pub title: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub actions: Vec<PushAction>,
+    /// Shown without sound or vibration.
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub silent: bool,
+    /// A shown notification with the same tag is replaced instead of stacked.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub tag: Option<String>,
+    /// The app path to open when the notification is clicked.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub link: Option<String>,
+}
+
+impl PushPayload {
+    pub fn new(title: &str, message: &str) -> Self {
+        return Self {
+            title: title.to_owned(),
+            message: message.to_owned(),
+            actions: vec![],
+            silent: false,
+            tag: None,
+            link: None,
+        };
+    }
+}
+
+/// A payload along with how the push service should handle it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PushMessage {
+    pub payload: PushPayload,
+    pub urgency: Urgency,
+    pub ttl_seconds: u32,
+    /// A message waiting at the push service is replaced by a newer one with the same topic.
+    pub topic: Option<String>,
+}
+
+impl PushMessage {
+    pub fn new(payload: PushPayload) -> Self {
+        return Self {
+            payload,
+            urgency: Urgency::Normal,
+            ttl_seconds: DEFAULT_TTL_SECONDS,
+            topic: None,
+        };
+    }
+}
+
+fn web_push_urgency(urgency: &Urgency) -> web_push::Urgency {
+    return match urgency {
+        Urgency::VeryLow => web_push::Urgency::VeryLow,
+        Urgency::Low => web_push::Urgency::Low,
+        Urgency::Normal => web_push::Urgency::Normal,
+        Urgency::High => web_push::Urgency::High,
+    };
+}
+
+/// Sends Web Push messages signed with the current VAPID key, or with the
+/// previous one for subscriptions made before the keys were rotated.
+pub struct PushClient {
+    client: IsahcWebPushClient,
+    signature_builder: PartialVapidSignatureBuilder,
+    /// The previous public key along with its signature builder.
+    previous: Option<(String, PartialVapidSignatureBuilder)>,
+}
+
+impl PushClient {
+    pub fn new(vapid_private_key: &str) -> Result<Self, anyhow::Error> {
+        let client = IsahcWebPushClient::new().context("error creating web push client")?;
+
+        let signature_builder = crate::vapid::signature_builder(vapid_private_key)
+            .context("error creating vapid signature builder")?;
+
+        return Ok(Self {
+            client,
+            signature_builder,
+            previous: None,
+        });
+    }
+
+    /// Keeps signing with `previous_private_key` for the subscriptions made
+    /// with it, and for the ones from before the key was recorded.
+    pub fn with_previous_key(self, previous_private_key: &str) -> Result<Self, anyhow::Error> {
+        let public_key = crate::vapid::public_key_of(previous_private_key)
+            .context("invalid previous vapid key")?;
+        let signature_builder = crate::vapid::signature_builder(previous_private_key)?;
+
+        return Ok(Self {
+            previous: Some((public_key, signature_builder)),
+            ..self
+        });
+    }
+
+    fn signature_builder_for(&self, sub: &NotificationSub) -> &PartialVapidSignatureBuilder {
+        return match (&self.previous, &sub.vapid_public_key) {
+            (Some((previous_key, builder)), Some(key)) if key == previous_key => builder,
+            (Some((_, builder)), None) => builder,
+            _ => &self.signature_builder,
+        };
+    }
+
+    pub async fn send(
+        &self,
+        sub: &NotificationSub,
+        message: &PushMessage,
+    ) -> Result<(), WebPushError> {
+        let subscription_info = SubscriptionInfo::new(
+            sub.endpoint.to_owned(),
+            sub.p256dh.to_owned(),
+            sub.auth.to_owned(),
+        );
+
+        let signature = self
+            .signature_builder_for(sub)
+            .to_owned()
+            .add_sub_info(&subscription_info)
+            .build()?;
+
+        let payload = serde_json::to_string(&message.payload)?;
+
+        let mut message_builder = WebPushMessageBuilder::new(&subscription_info);
+        message_builder.set_payload(ContentEncoding::Aes128Gcm, payload.as_bytes());
+        message_builder.set_urgency(web_push_urgency(&message.urgency));
+        message_builder.set_ttl(message.ttl_seconds);
+        if let Some(topic) = &message.topic {
+            message_builder.set_topic(topic.to_owned());
+        }
+        message_builder.set_vapid_signature(signature);
+
+        let web_push_message = message_builder.build()?;
+
+        return self.client.send(web_push_message).await;
+    }
+}
+
+/// Whether a failed send is worth retrying.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// The subscription is gone or unusable, it will never succeed again.
+    Permanent,
+    /// The push service or the network had a problem, a retry may succeed.
+    Transient,
+}
+
+impl FailureKind {
+    pub fn classify(error: &WebPushError) -> Self {
+        match error {
+            // 410 Gone and 404 Not Found, the browser unsubscribed
+            WebPushError::EndpointNotValid
+            | WebPushError::EndpointNotFound
+            | WebPushError::InvalidUri
+            | WebPushError::MissingCryptoKeys
+            | WebPushError::InvalidCryptoKeys => FailureKind::Permanent,
+            _ => FailureKind::Transient,
+        }
+    }
+}
+
+pub static CLIENT: once_cell::sync::Lazy<PushClient> = once_cell::sync::Lazy::new(|| {
+    let client =
+        PushClient::new(&CONFIG.vapid_private_key).expect("error creating static push client");
+
+    return match &CONFIG.vapid_previous_private_key {
+        Some(previous_private_key) => client
+            .with_previous_key(previous_private_key)
+            .expect("error creating static push client"),
+        None => client,
+    };
 });
 
-static SIGNATURE_BUILDER: once_cell::sync::Lazy<PartialVapidSignatureBuilder> =
-    once_cell::sync::Lazy::new(|| {
-        VapidSignatureBuilder::from_base64_no_sub(&CONFIG.vapid_private_key, URL_SAFE_NO_PAD)
-            .expect("error creating static vapid signature builder")
-    });
-
-pub async fn send_notification(
-    sub: &NotificationSub,
-    title: &str,
-    message: &str,
-) -> Result<(), anyhow::Error> {
-    let subscription_info = SubscriptionInfo::new(
-        sub.endpoint.to_owned(),
-        sub.p256dh.to_owned(),
-        sub.auth.to_owned(),
-    );
-
-    let signature = SIGNATURE_BUILDER
-        .to_owned()
-        .add_sub_info(&subscription_info)
-        .build()
-        .context("error building signature")?;
-
-    let payload = json!({
-        "title": title,
-        "message": message,
-    })
-    .to_string();
-
-    let mut message_builder = WebPushMessageBuilder::new(&subscription_info);
-    message_builder.set_payload(ContentEncoding::Aes128Gcm, payload.as_bytes());
-    message_builder.set_vapid_signature(signature);
-
-    let message = message_builder.build().context("error building message")?;
-
-    CLIENT
-        .send(message)
-        .await
-        .context("error sending notification")?;
-
-    return Ok(());
+/// Lets the user check that notifications reach one of their devices.
+pub async fn send_test_notification(sub: &NotificationSub) -> Result<(), WebPushError> {
+    return CLIENT
+        .send(
+            sub,
+            &PushMessage::new(PushPayload::new(
+                "Test notification",
+                "If you see this, notifications are working! 🥳",
+            )),
+        )
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_push::{MockPushEndpoint, TEST_VAPID_PRIVATE_KEY};
+
+    #[tokio::test]
+    async fn test_send_to_mock_endpoint() {
+        let client = PushClient::new(TEST_VAPID_PRIVATE_KEY).unwrap();
+
+        let endpoint = MockPushEndpoint::start(vec![201]).await;
+        let sub = endpoint.sub("user");
+
+        let mut message = PushMessage::new(PushPayload::new("title", "message"));
+        message.urgency = Urgency::High;
+        message.ttl_seconds = 300;
+        message.topic = Some("task".to_owned());
+
+        client.send(&sub, &message).await.unwrap();
+
+        let requests = endpoint.requests().await;
+        assert_eq!(requests.len(), 1);
+        assert!(sub.endpoint.ends_with(&requests[0].path));
+        assert!(!requests[0].body.is_empty());
+        assert!(requests[0].headers.contains_key("authorization"));
+        assert_eq!(
+            requests[0]
+                .headers
+                .get("content-encoding")
+                .map(|h| h.as_str()),
+            Some("aes128gcm")
+        );
+        assert_eq!(
+            requests[0].headers.get("urgency").map(|h| h.as_str()),
+            Some("high")
+        );
+        assert_eq!(
+            requests[0].headers.get("ttl").map(|h| h.as_str()),
+            Some("300")
+        );
+        assert_eq!(
+            requests[0].headers.get("topic").map(|h| h.as_str()),
+            Some("task")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_with_previous_vapid_key() {
+        let current = crate::vapid::generate();
+        let previous_public_key = crate::vapid::public_key_of(TEST_VAPID_PRIVATE_KEY).unwrap();
+        let client = PushClient::new(&current.private_key)
+            .unwrap()
+            .with_previous_key(TEST_VAPID_PRIVATE_KEY)
+            .unwrap();
+
+        for (vapid_public_key, expected) in [
+            (None, &previous_public_key),
+            (Some(previous_public_key.to_owned()), &previous_public_key),
+            (Some(current.public_key.to_owned()), &current.public_key),
+        ] {
+            let endpoint = MockPushEndpoint::start(vec![201]).await;
+            let sub = NotificationSub {
+                vapid_public_key,
+                ..endpoint.sub("user")
+            };
+
+            client
+                .send(
+                    &sub,
+                    &PushMessage::new(PushPayload::new("title", "message")),
+                )
+                .await
+                .unwrap();
+
+            let requests = endpoint.requests().await;
+            let authorization = requests[0].headers.get("authorization").unwrap();
+            assert!(authorization.ends_with(&format!("k={expected}")));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_to_mock_endpoint_error() {
+        let client = PushClient::new(TEST_VAPID_PRIVATE_KEY).unwrap();
+
+        let endpoint = MockPushEndpoint::start(vec![503]).await;
+
+        let result = client
+            .send(
+                &endpoint.sub("user"),
+                &PushMessage::new(PushPayload::new("title", "message")),
+            )
+            .await;
+
+        assert_eq!(result, Err(WebPushError::ServerError(None)));
+    }
+
+    #[tokio::test]
+    async fn test_classify_mock_endpoint_errors() {
+        let client = PushClient::new(TEST_VAPID_PRIVATE_KEY).unwrap();
+
+        for (status, kind) in [
+            (404, FailureKind::Permanent),
+            (410, FailureKind::Permanent),
+            (429, FailureKind::Transient),
+            (500, FailureKind::Transient),
+            (503, FailureKind::Transient),
+        ] {
+            let endpoint = MockPushEndpoint::start(vec![status]).await;
+
+            let error = client
+                .send(
+                    &endpoint.sub("user"),
+                    &PushMessage::new(PushPayload::new("title", "message")),
+                )
+                .await
+                .unwrap_err();
+
+            assert_eq!(FailureKind::classify(&error), kind, "status {status}");
+        }
+    }
 }
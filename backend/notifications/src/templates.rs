@@ -0,0 +1,536 @@
+//! Notification text, rendered in the user's language when the notification
+//! is sent so it reflects the task as it is then, like a renamed tag.
+
+use auth::action_token::{TaskAction, BREAK_MINUTES, EXTEND_BY_MINUTES};
+use db::{
+    notification_settings::NotificationSettings,
+    notifications::{Notification, NotificationKind},
+    reminders::ReminderOffset,
+    tasks::TaskWithTag,
+};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Locale {
+    #[default]
+    En,
+    Fr,
+    De,
+}
+
+impl FromStr for Locale {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "en" => Ok(Locale::En),
+            "fr" => Ok(Locale::Fr),
+            "de" => Ok(Locale::De),
+            _ => Err(anyhow::anyhow!("unsupported locale")),
+        }
+    }
+}
+
+impl AsRef<str> for Locale {
+    fn as_ref(&self) -> &str {
+        match self {
+            Locale::En => "en",
+            Locale::Fr => "fr",
+            Locale::De => "de",
+        }
+    }
+}
+
+impl Locale {
+    /// The user's locale, users without settings or with an unknown one get English.
+    pub fn from_settings(settings: Option<&NotificationSettings>) -> Self {
+        return settings
+            .and_then(|settings| settings.locale.parse::<Locale>().ok())
+            .unwrap_or_default();
+    }
+}
+
+/// The text of every notification in one language. Placeholders are
+/// `{tag}`, `{duration}`, `{minutes}`, `{percent}` and `{next_step}`.
+pub struct Templates {
+    pub finished_title: &'static str,
+    pub finished_message: &'static str,
+    /// What to do once a task is finished, `{minutes}` is the break length.
+    pub finished_next_step: &'static str,
+    pub ending_soon_title: &'static str,
+    pub ending_in_a_minute_message: &'static str,
+    pub ending_in_minutes_message: &'static str,
+    pub halfway_title: &'static str,
+    pub halfway_message: &'static str,
+    pub progress_title: &'static str,
+    pub progress_message: &'static str,
+    pub nudge_title: &'static str,
+    pub nudge_message: &'static str,
+    pub summary_title: &'static str,
+    /// `{duration}` is the total of the day and `{tag}` the tag tracked the most.
+    pub summary_message: &'static str,
+    /// The weekly digest email, see `digest`.
+    pub digest_subject: &'static str,
+    /// `{from}` and `{to}` are the first and last day of the week.
+    pub digest_heading: &'static str,
+    pub digest_total: &'static str,
+    /// `{change}` is a signed percentage, like `+12%`.
+    pub digest_change: &'static str,
+    pub digest_longest_session: &'static str,
+    /// `{days}` is the number of days in a row with something tracked.
+    pub digest_streak: &'static str,
+    pub digest_by_tag: &'static str,
+    pub digest_by_day: &'static str,
+    pub digest_link: &'static str,
+    /// Short weekday names, starting on monday.
+    pub weekdays: [&'static str; 7],
+    pub stop_action: &'static str,
+    /// `{minutes}` is how much the task is extended by.
+    pub extend_action: &'static str,
+    pub start_break_action: &'static str,
+}
+
+static EN: Templates = Templates {
+    finished_title: "Task finished",
+    finished_message: "Your task '{tag}' has finished after {duration}. {next_step}",
+    finished_next_step: "Time for a {minutes} minute break?",
+    ending_soon_title: "Task ending soon",
+    ending_in_a_minute_message: "Your task '{tag}' ends in a minute",
+    ending_in_minutes_message: "Your task '{tag}' ends in {minutes} minutes",
+    halfway_title: "Task halfway done",
+    halfway_message: "Your task '{tag}' is halfway done",
+    progress_title: "Task {percent}% done",
+    progress_message: "Your task '{tag}' is {percent}% done",
+    nudge_title: "Nothing tracked today",
+    nudge_message: "You haven't tracked anything yet today, time to start a task?",
+    summary_title: "Your day",
+    summary_message: "You tracked {duration} today, mostly '{tag}'",
+    digest_subject: "Your week: {duration} tracked",
+    digest_heading: "Your week from {from} to {to}",
+    digest_total: "Total: {duration}",
+    digest_change: "{change} compared to the week before",
+    digest_longest_session: "Longest session: {duration}",
+    digest_streak: "Streak: {days} days",
+    digest_by_tag: "By tag",
+    digest_by_day: "By day",
+    digest_link: "See your stats",
+    weekdays: ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"],
+    stop_action: "Stop",
+    extend_action: "+{minutes} min",
+    start_break_action: "Start break",
+};
+
+static FR: Templates = Templates {
+    finished_title: "Tâche terminée",
+    finished_message: "Votre tâche « {tag} » est terminée après {duration}. {next_step}",
+    finished_next_step: "Une pause de {minutes} minutes ?",
+    ending_soon_title: "Tâche bientôt terminée",
+    ending_in_a_minute_message: "Votre tâche « {tag} » se termine dans une minute",
+    ending_in_minutes_message: "Votre tâche « {tag} » se termine dans {minutes} minutes",
+    halfway_title: "Tâche à mi-parcours",
+    halfway_message: "Votre tâche « {tag} » est à moitié terminée",
+    progress_title: "Tâche terminée à {percent} %",
+    progress_message: "Votre tâche « {tag} » est terminée à {percent} %",
+    nudge_title: "Rien de suivi aujourd'hui",
+    nudge_message: "Vous n'avez encore rien suivi aujourd'hui, on commence une tâche ?",
+    summary_title: "Votre journée",
+    summary_message: "Vous avez suivi {duration} aujourd'hui, surtout « {tag} »",
+    digest_subject: "Votre semaine : {duration} suivies",
+    digest_heading: "Votre semaine du {from} au {to}",
+    digest_total: "Total : {duration}",
+    digest_change: "{change} par rapport à la semaine précédente",
+    digest_longest_session: "Session la plus longue : {duration}",
+    digest_streak: "Série : {days} jours",
+    digest_by_tag: "Par tag",
+    digest_by_day: "Par jour",
+    digest_link: "Voir vos statistiques",
+    weekdays: ["lun", "mar", "mer", "jeu", "ven", "sam", "dim"],
+    stop_action: "Arrêter",
+    extend_action: "+{minutes} min",
+    start_break_action: "Faire une pause",
+};
+
+static DE: Templates = Templates {
+    finished_title: "Aufgabe beendet",
+    finished_message: "Deine Aufgabe „{tag}“ ist nach {duration} beendet. {next_step}",
+    finished_next_step: "Zeit für {minutes} Minuten Pause?",
+    ending_soon_title: "Aufgabe endet bald",
+    ending_in_a_minute_message: "Deine Aufgabe „{tag}“ endet in einer Minute",
+    ending_in_minutes_message: "Deine Aufgabe „{tag}“ endet in {minutes} Minuten",
+    halfway_title: "Aufgabe zur Hälfte erledigt",
+    halfway_message: "Deine Aufgabe „{tag}“ ist zur Hälfte erledigt",
+    progress_title: "Aufgabe zu {percent} % erledigt",
+    progress_message: "Deine Aufgabe „{tag}“ ist zu {percent} % erledigt",
+    nudge_title: "Heute noch nichts erfasst",
+    nudge_message: "Du hast heute noch nichts erfasst, Zeit für eine Aufgabe?",
+    summary_title: "Dein Tag",
+    summary_message: "Du hast heute {duration} erfasst, vor allem „{tag}“",
+    digest_subject: "Deine Woche: {duration} erfasst",
+    digest_heading: "Deine Woche vom {from} bis {to}",
+    digest_total: "Gesamt: {duration}",
+    digest_change: "{change} im Vergleich zur Vorwoche",
+    digest_longest_session: "Längste Sitzung: {duration}",
+    digest_streak: "Serie: {days} Tage",
+    digest_by_tag: "Nach Tag",
+    digest_by_day: "Nach Wochentag",
+    digest_link: "Statistiken ansehen",
+    weekdays: ["Mo", "Di", "Mi", "Do", "Fr", "Sa", "So"],
+    stop_action: "Stopp",
+    extend_action: "+{minutes} Min.",
+    start_break_action: "Pause starten",
+};
+
+pub fn templates(locale: &Locale) -> &'static Templates {
+    return match locale {
+        Locale::En => &EN,
+        Locale::Fr => &FR,
+        Locale::De => &DE,
+    };
+}
+
+/// Replaces every `{name}` in `template` with its value, unknown placeholders are left as is.
+/// Done in one pass over the template, so placeholders inside the values stay as they are.
+pub fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let value = rest.find('}').and_then(|end| {
+            return values
+                .iter()
+                .find(|(name, _)| *name == &rest[1..end])
+                .map(|(_, value)| (end, *value));
+        });
+
+        match value {
+            Some((end, value)) => {
+                text.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                text.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    text.push_str(rest);
+
+    return text;
+}
+
+/// Like `25 min` or `1 h 30 min`.
+pub(crate) fn format_duration(seconds: i64) -> String {
+    let minutes = (seconds.max(0) + 30) / 60;
+
+    return match (minutes / 60, minutes % 60) {
+        (0, minutes) => format!("{minutes} min"),
+        (hours, 0) => format!("{hours} h"),
+        (hours, minutes) => format!("{hours} h {minutes} min"),
+    };
+}
+
+pub fn action_title(locale: &Locale, action: &TaskAction) -> String {
+    let templates = templates(locale);
+
+    return match action {
+        TaskAction::Stop => templates.stop_action.to_owned(),
+        TaskAction::Extend => fill(
+            templates.extend_action,
+            &[("minutes", &EXTEND_BY_MINUTES.to_string())],
+        ),
+        TaskAction::StartBreak => templates.start_break_action.to_owned(),
+    };
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderedNotification {
+    pub title: String,
+    pub message: String,
+}
+
+/// The text of `notification` for `task` as it is now. Notifications written
+/// before templates keep their text, so do ones whose task is gone.
+pub fn render(
+    locale: &Locale,
+    notification: &Notification,
+    task: Option<&TaskWithTag>,
+) -> RenderedNotification {
+    let templates = templates(locale);
+
+    let rendered = task.and_then(|task| {
+        let tag = task.tag_label.as_str();
+
+        let (title, message) = match (notification.kind, notification.reminder_offset()) {
+            (NotificationKind::Finished, _) => {
+                let next_step = fill(
+                    templates.finished_next_step,
+                    &[("minutes", &BREAK_MINUTES.to_string())],
+                );
+
+                (
+                    templates.finished_title.to_owned(),
+                    fill(
+                        templates.finished_message,
+                        &[
+                            ("tag", tag),
+                            ("duration", &format_duration(task.seconds.into())),
+                            ("next_step", &next_step),
+                        ],
+                    ),
+                )
+            }
+            (NotificationKind::Reminder, Some(ReminderOffset::BeforeEnd { minutes: 1 })) => (
+                templates.ending_soon_title.to_owned(),
+                fill(templates.ending_in_a_minute_message, &[("tag", tag)]),
+            ),
+            (NotificationKind::Reminder, Some(ReminderOffset::BeforeEnd { minutes })) => (
+                templates.ending_soon_title.to_owned(),
+                fill(
+                    templates.ending_in_minutes_message,
+                    &[("tag", tag), ("minutes", &minutes.to_string())],
+                ),
+            ),
+            (NotificationKind::Reminder, Some(ReminderOffset::Progress { percent: 50 })) => (
+                templates.halfway_title.to_owned(),
+                fill(templates.halfway_message, &[("tag", tag)]),
+            ),
+            (NotificationKind::Reminder, Some(ReminderOffset::Progress { percent })) => {
+                let percent = percent.to_string();
+
+                (
+                    fill(templates.progress_title, &[("percent", &percent)]),
+                    fill(
+                        templates.progress_message,
+                        &[("tag", tag), ("percent", &percent)],
+                    ),
+                )
+            }
+            (NotificationKind::Reminder, None) => return None,
+            // written when they're queued, see `daily`
+            (NotificationKind::Nudge | NotificationKind::Summary, _) => return None,
+        };
+
+        return Some(RenderedNotification { title, message });
+    });
+
+    let stored = notification
+        .title
+        .to_owned()
+        .zip(notification.message.to_owned())
+        .map(|(title, message)| RenderedNotification { title, message });
+
+    return match (stored, rendered) {
+        (Some(stored), _) => stored,
+        (None, Some(rendered)) => rendered,
+        (None, None) => RenderedNotification {
+            title: templates.finished_title.to_owned(),
+            message: String::new(),
+        },
+    };
+}
+
+pub fn render_nudge(locale: &Locale) -> RenderedNotification {
+    let templates = templates(locale);
+
+    return RenderedNotification {
+        title: templates.nudge_title.to_owned(),
+        message: templates.nudge_message.to_owned(),
+    };
+}
+
+/// The summary of a day where `seconds` were tracked, mostly with `top_tag`.
+pub fn render_summary(locale: &Locale, seconds: i64, top_tag: &str) -> RenderedNotification {
+    let templates = templates(locale);
+
+    return RenderedNotification {
+        title: templates.summary_title.to_owned(),
+        message: fill(
+            templates.summary_message,
+            &[("duration", &format_duration(seconds)), ("tag", top_tag)],
+        ),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn notification(kind: NotificationKind, reminder: Option<(&str, i32)>) -> Notification {
+        return Notification {
+            reminder_kind: reminder.map(|(kind, _)| kind.to_owned()),
+            reminder_value: reminder.map(|(_, value)| value),
+            ..db::test_utils::notification(kind, &Utc::now())
+        };
+    }
+
+    fn task(tag_label: &str, seconds: i32) -> TaskWithTag {
+        let now = Utc::now();
+
+        return TaskWithTag {
+            id: "task".to_owned(),
+            user_id: "user".to_owned(),
+            tag_id: "tag".to_owned(),
+            is_manual: false,
+            seconds,
+            start_at: now,
+            end_at: now,
+            tag_label: tag_label.to_owned(),
+            tag_color: "#d13c4b".to_owned(),
+        };
+    }
+
+    #[test]
+    fn test_fill() {
+        assert_eq!(
+            fill(
+                "{tag} for {duration} {unknown}",
+                &[("tag", "work"), ("duration", "5 min")]
+            ),
+            "work for 5 min {unknown}"
+        );
+
+        // a tag named like a placeholder isn't expanded
+        assert_eq!(
+            fill(
+                "{tag} for {duration}. {next_step}",
+                &[
+                    ("tag", "{next_step} {duration}"),
+                    ("duration", "5 min"),
+                    ("next_step", "Break?")
+                ]
+            ),
+            "{next_step} {duration} for 5 min. Break?"
+        );
+        assert_eq!(fill("{ {tag}} {", &[("tag", "work")]), "{ work} {");
+    }
+
+    #[test]
+    fn test_render_keeps_placeholders_in_the_tag() {
+        let finished = notification(NotificationKind::Finished, None);
+
+        assert_eq!(
+            render(&Locale::En, &finished, Some(&task("{next_step}", 25 * 60))).message,
+            "Your task '{next_step}' has finished after 25 min. Time for a 5 minute break?"
+        );
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(25 * 60), "25 min");
+        assert_eq!(format_duration(60 * 60), "1 h");
+        assert_eq!(format_duration(90 * 60 + 10), "1 h 30 min");
+    }
+
+    #[test]
+    fn test_render_uses_the_current_tag() {
+        let finished = notification(NotificationKind::Finished, None);
+
+        assert_eq!(
+            render(&Locale::En, &finished, Some(&task("renamed", 25 * 60))),
+            RenderedNotification {
+                title: "Task finished".to_owned(),
+                message:
+                    "Your task 'renamed' has finished after 25 min. Time for a 5 minute break?"
+                        .to_owned(),
+            }
+        );
+    }
+
+    #[sqlx::test(migrations = "../db/migrations")]
+    #[ignore = "needs a postgres database, run with DATABASE_URL set and --ignored"]
+    async fn test_longest_tag_fits_in_inbox(db: db::Db) {
+        let sent = db::test_utils::insert_notification(&db, "long@test.local").await;
+        let label = "a".repeat(255);
+
+        let rendered = render(
+            &Locale::Fr,
+            &notification(NotificationKind::Finished, None),
+            Some(&task(&label, 25 * 60)),
+        );
+        assert!(rendered.message.contains(&label));
+
+        db::inbox::insert_for_notification(
+            &db,
+            &sent,
+            &rendered.title,
+            &rendered.message,
+            &Utc::now(),
+        )
+        .await
+        .unwrap();
+
+        let items = db::inbox::get_many(&db, &sent.user_id, None, false)
+            .await
+            .unwrap();
+        assert_eq!(items[0].message, rendered.message);
+    }
+
+    #[test]
+    fn test_render_reminders_in_every_locale() {
+        let ending = notification(NotificationKind::Reminder, Some(("before_end", 5)));
+        let halfway = notification(NotificationKind::Reminder, Some(("progress", 50)));
+        let task = task("work", 60 * 60);
+
+        assert_eq!(
+            render(&Locale::En, &ending, Some(&task)).message,
+            "Your task 'work' ends in 5 minutes"
+        );
+        assert_eq!(
+            render(&Locale::Fr, &ending, Some(&task)).message,
+            "Votre tâche « work » se termine dans 5 minutes"
+        );
+        assert_eq!(
+            render(&Locale::De, &halfway, Some(&task)).title,
+            "Aufgabe zur Hälfte erledigt"
+        );
+
+        for locale in [Locale::En, Locale::Fr, Locale::De] {
+            for notification in [&ending, &halfway] {
+                let rendered = render(&locale, notification, Some(&task));
+                assert!(!rendered.message.contains('{'), "{:?}", rendered);
+            }
+        }
+    }
+
+    #[test]
+    fn test_render_summary() {
+        assert_eq!(
+            render_summary(&Locale::En, 2 * 60 * 60 + 15 * 60, "work").message,
+            "You tracked 2 h 15 min today, mostly 'work'"
+        );
+        assert_eq!(
+            render_summary(&Locale::De, 45 * 60, "work").message,
+            "Du hast heute 45 min erfasst, vor allem „work“"
+        );
+    }
+
+    #[test]
+    fn test_render_keeps_stored_text() {
+        let mut reminder = notification(NotificationKind::Reminder, None);
+        reminder.title = Some("Task ending soon".to_owned());
+        reminder.message = Some("Your task 'work' ends in 5 minutes".to_owned());
+
+        assert_eq!(
+            render(&Locale::Fr, &reminder, Some(&task("work", 60))).title,
+            "Task ending soon"
+        );
+    }
+
+    #[test]
+    fn test_locale_from_settings() {
+        let mut settings = NotificationSettings::new("user");
+        assert_eq!(Locale::from_settings(Some(&settings)), Locale::En);
+
+        settings.locale = "fr".to_owned();
+        assert_eq!(Locale::from_settings(Some(&settings)), Locale::Fr);
+
+        settings.locale = "xx".to_owned();
+        assert_eq!(Locale::from_settings(Some(&settings)), Locale::En);
+        assert_eq!(Locale::from_settings(None), Locale::En);
+    }
+}
@@ -0,0 +1,84 @@
+//! Generating and checking the VAPID keys that sign Web Push messages.
+
+use anyhow::Context;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
+use web_push::{PartialVapidSignatureBuilder, VapidSignatureBuilder};
+
+/// A key pair, both halves base64url encoded without padding like the
+/// browsers' `applicationServerKey` expects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VapidKeys {
+    pub public_key: String,
+    pub private_key: String,
+}
+
+pub(crate) fn signature_builder(
+    private_key: &str,
+) -> Result<PartialVapidSignatureBuilder, anyhow::Error> {
+    return VapidSignatureBuilder::from_base64_no_sub(private_key, web_push::URL_SAFE_NO_PAD)
+        .context("invalid vapid private key");
+}
+
+/// The public key that goes with `private_key`.
+pub fn public_key_of(private_key: &str) -> Result<String, anyhow::Error> {
+    let public_key = signature_builder(private_key)?.get_public_key();
+
+    return Ok(URL_SAFE_NO_PAD.encode(public_key));
+}
+
+/// Generates a new P-256 key pair.
+pub fn generate() -> VapidKeys {
+    loop {
+        let mut secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        let private_key = URL_SAFE_NO_PAD.encode(secret);
+
+        // the odd scalar that is zero or past the curve order isn't a valid key
+        if let Ok(public_key) = public_key_of(&private_key) {
+            return VapidKeys {
+                public_key,
+                private_key,
+            };
+        }
+    }
+}
+
+/// Checks that the configured keys are a pair, so a typo doesn't go
+/// unnoticed until every push fails.
+pub fn validate(public_key: &str, private_key: &str) -> Result<(), anyhow::Error> {
+    let expected = public_key_of(private_key)?;
+
+    if expected != public_key {
+        anyhow::bail!("vapid public key doesn't match the private key, expected {expected}");
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_push::TEST_VAPID_PRIVATE_KEY;
+
+    #[test]
+    fn test_generate() {
+        let keys = generate();
+
+        assert_eq!(keys.private_key.len(), 43);
+        // uncompressed point, 0x04 then x and y
+        assert_eq!(keys.public_key.len(), 87);
+        assert!(keys.public_key.starts_with('B'));
+        assert_ne!(keys, generate());
+        validate(&keys.public_key, &keys.private_key).unwrap();
+    }
+
+    #[test]
+    fn test_validate() {
+        let public_key = public_key_of(TEST_VAPID_PRIVATE_KEY).unwrap();
+
+        validate(&public_key, TEST_VAPID_PRIVATE_KEY).unwrap();
+        assert!(validate(&generate().public_key, TEST_VAPID_PRIVATE_KEY).is_err());
+        assert!(validate(&public_key, "not a key").is_err());
+    }
+}
@@ -1,3 +1,5 @@
+const API_URL = new URL(self.location.href).searchParams.get("api");
+
 self.addEventListener("install", function (event) {
 	console.debug("sw - install", { event });
 	self.skipWaiting();
@@ -34,10 +36,16 @@ self.addEventListener("push", (event) => {
 		return;
 	}
 
+	const actions = Array.isArray(json.actions) ? json.actions : [];
+
 	event.waitUntil(
 		self.registration.showNotification(title, {
 			body,
 			icon: "/icons/app-icon-192x192.png",
+			actions: actions.map(({ action, title }) => ({ action, title })),
+			silent: json.silent === true,
+			...(typeof json.tag === "string" ? { tag: json.tag, renotify: true } : {}),
+			data: { actions, link: typeof json.link === "string" ? json.link : null },
 		})
 	);
 });
@@ -47,7 +55,26 @@ self.addEventListener("notificationclick", (event) => {
 
 	event.notification.close();
 
+	const action = event.notification.data?.actions?.find(
+		(a) => a.action === event.action
+	);
+
+	if (action && API_URL) {
+		event.waitUntil(
+			fetch(`${API_URL}/notification-actions`, {
+				method: "POST",
+				headers: { "Content-Type": "application/json" },
+				body: JSON.stringify({ token: action.token }),
+			}).catch((error) => {
+				console.error("sw - notificationclick - action failed", { error });
+			})
+		);
+		return;
+	}
+
 	const url = new URL("/", self.location.origin).toString();
+	const link = event.notification.data?.link;
+	const linkUrl = link ? new URL(link, self.location.origin).toString() : url;
 
 	event.waitUntil(
 		self.clients
@@ -58,13 +85,16 @@ self.addEventListener("notificationclick", (event) => {
 			.then((clientList) => {
 				for (const client of clientList) {
 					if (client.url.startsWith(url) && "focus" in client) {
+						if (link && "navigate" in client) {
+							return client.navigate(linkUrl).then((c) => c?.focus());
+						}
 						client.focus();
 						return;
 					}
 				}
 
 				if (self.clients.openWindow) {
-					return self.clients.openWindow(url);
+					return self.clients.openWindow(linkUrl);
 				}
 			})
 	);
@@ -26,3 +26,40 @@ export function useGoogleVerifyMutation() {
 			}),
 	});
 }
+
+export type ApiOidcProvider = {
+	id: string;
+	name: string;
+};
+
+export function useOidcProviders() {
+	return useQuery<Array<ApiOidcProvider>>({
+		queryKey: ["auth", "oidc-providers"],
+		queryFn: ({ signal }) =>
+			apiRequest({
+				method: "GET",
+				path: "/auth/oidc",
+				signal,
+			}),
+	});
+}
+
+export function useOidcVerifyMutation() {
+	return useMutation({
+		mutationKey: ["auth", "oidc-verify"],
+		mutationFn: async ({
+			providerId,
+			code,
+			state,
+		}: {
+			providerId: string;
+			code: string;
+			state: string;
+		}) =>
+			apiRequest({
+				method: "POST",
+				path: `/auth/oidc/${providerId}/verify`,
+				query: { code, state },
+			}),
+	});
+}
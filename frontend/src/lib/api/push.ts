@@ -0,0 +1,21 @@
+import { apiRequest } from "./apiRequest";
+
+/** The key to subscribe with, fetched so a key rotation doesn't need a new build. */
+export async function getVapidPublicKey() {
+	const { public_key } = await apiRequest<{ public_key: string }>({
+		method: "GET",
+		path: "/notif-subs/vapid-public-key",
+	});
+
+	return public_key;
+}
+
+/** Whether `sub` was made with `publicKey`, it has to be renewed after a key rotation. */
+export function isSubscribedWithKey(sub: PushSubscription, publicKey: Uint8Array) {
+	const key = sub.options.applicationServerKey;
+	if (!key) return false;
+
+	const bytes = new Uint8Array(key);
+
+	return bytes.length === publicKey.length && bytes.every((byte, i) => byte === publicKey[i]);
+}
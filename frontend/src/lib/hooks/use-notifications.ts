@@ -1,8 +1,7 @@
 import { useEffect } from "react";
 
-import { conf } from "@/lib/conf";
-
 import { apiRequest } from "../api/apiRequest";
+import { getVapidPublicKey, isSubscribedWithKey } from "../api/push";
 import { base64ToUint8Array } from "../base64-to-uint8-array";
 import { useLocalStorage } from "./use-local-storage";
 
@@ -16,8 +15,6 @@ export function useNotifications() {
 	const isNotificationsEnabled = notificationEnabled === "1";
 
 	useEffect(() => {
-		if (isNotificationsEnabled) return;
-
 		(async () => {
 			if (Notification.permission !== "granted") {
 				console.debug("useNotifications no permission");
@@ -36,9 +33,18 @@ export function useNotifications() {
 				return;
 			}
 
+			const publicKey = base64ToUint8Array(await getVapidPublicKey());
+			const isCurrentKey = isSubscribedWithKey(sub, publicKey);
+			if (isNotificationsEnabled && isCurrentKey) return;
+
+			// the keys were rotated, a subscription can't change its key
+			if (!isCurrentKey) {
+				await sub.unsubscribe();
+			}
+
 			const subscription = await reg.pushManager.subscribe({
 				userVisibleOnly: true,
-				applicationServerKey: base64ToUint8Array(conf.VAPID_PUBLIC_KEY),
+				applicationServerKey: publicKey,
 			});
 
 			const subJson = subscription.toJSON();
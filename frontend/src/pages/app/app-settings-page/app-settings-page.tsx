@@ -4,8 +4,8 @@ import { PageLayout } from "@/components/page-layout";
 import { Button } from "@/components/ui/button";
 import { Switch } from "@/components/ui/switch";
 import { apiRequest } from "@/lib/api/apiRequest";
+import { getVapidPublicKey } from "@/lib/api/push";
 import { base64ToUint8Array } from "@/lib/base64-to-uint8-array";
-import { conf } from "@/lib/conf";
 import { errorToast } from "@/lib/error-toast";
 import { useLocalStorage } from "@/lib/hooks/use-local-storage";
 import { notificationsEnabledLocalStorageKey } from "@/lib/hooks/use-notifications";
@@ -96,16 +96,20 @@ async function enableNotifications() {
 		throw new Error("no data");
 	}
 
-	await apiRequest<void>({
+	const notifSub = await apiRequest<{ id: string }>({
 		method: "POST",
 		path: "/notif-subs",
-		query: { send_test_notification: "1" },
 		body: {
 			endpoint: result.data.endpoint,
 			auth: result.data.auth,
 			p256dh: result.data.p256dh,
 		},
 	});
+
+	await apiRequest<void>({
+		method: "POST",
+		path: `/notif-subs/${notifSub.id}/test`,
+	});
 }
 
 async function disableNotifications() {
@@ -137,7 +141,7 @@ async function createNotifSubscription(reg: ServiceWorkerRegistration) {
 
 	const subscription = await reg.pushManager.subscribe({
 		userVisibleOnly: true,
-		applicationServerKey: base64ToUint8Array(conf.VAPID_PUBLIC_KEY),
+		applicationServerKey: base64ToUint8Array(await getVapidPublicKey()),
 	});
 
 	const subJson = subscription?.toJSON();
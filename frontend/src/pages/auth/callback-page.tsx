@@ -1,21 +1,26 @@
 import { useQueryClient } from "@tanstack/react-query";
 import { AnimatePresence, motion } from "framer-motion";
 import { type ReactNode, useEffect, useState } from "react";
-import { useNavigate, useSearchParams } from "react-router-dom";
+import { useNavigate, useParams, useSearchParams } from "react-router-dom";
 import useMeasure from "react-use-measure";
 
 import { Spinner } from "@/components/spinner";
 import { CheckmarkGreen } from "@/components/status";
-import { useGoogleVerifyMutation } from "@/lib/api/auth";
+import { useGoogleVerifyMutation, useOidcVerifyMutation } from "@/lib/api/auth";
 import { cn } from "@/lib/classnames";
 import { useSetTimeout } from "@/lib/hooks/use-set-interval";
 
 export function CallbackPage() {
 	const [searchParams] = useSearchParams();
 	const code = searchParams.get("code");
+	const state = searchParams.get("state");
+	// google has its own callback, other providers have their id in the path
+	const { providerId } = useParams();
 	const navigate = useNavigate();
 
-	const mutation = useGoogleVerifyMutation();
+	const googleMutation = useGoogleVerifyMutation();
+	const oidcMutation = useOidcVerifyMutation();
+	const mutation = providerId ? oidcMutation : googleMutation;
 
 	const queryClient = useQueryClient();
 	const [page, setPage] = useState<"logged-in" | "welcome">("logged-in");
@@ -37,7 +42,9 @@ export function CallbackPage() {
 		(async () => {
 			if (code) {
 				await Promise.allSettled([
-					mutation.mutateAsync({ code }),
+					providerId
+						? oidcMutation.mutateAsync({ providerId, code, state: state ?? "" })
+						: googleMutation.mutateAsync({ code }),
 					new Promise((res) => setTimeout(res, 800)),
 				]);
 
@@ -1,11 +1,14 @@
 import { Link } from "react-router-dom";
 
 import { Button } from "@/components/ui/button";
+import { useOidcProviders } from "@/lib/api/auth";
 import { conf } from "@/lib/conf";
 
 const apiUrl = conf.API_URL;
 
 export function LoginPage() {
+	const providers = useOidcProviders();
+
 	return (
 		<div className="flex flex-col items-center gap-10">
 			<h1 className="text-5xl">login</h1>
@@ -14,6 +17,14 @@ export function LoginPage() {
 				<Link to={`${apiUrl}/auth/google-init`}>login with google</Link>
 			</Button>
 
+			{providers.data?.map((provider) => (
+				<Button asChild key={provider.id} className="-mt-6">
+					<Link to={`${apiUrl}/auth/oidc/${provider.id}/init`}>
+						login with {provider.name}
+					</Link>
+				</Button>
+			))}
+
 			{!conf.IS_PROD && (
 				<Button asChild className="-mt-6">
 					<Link to={`${apiUrl}/auth/dev-login`}>dev login</Link>
@@ -1,5 +1,6 @@
 import { Navigate, Route, Routes } from "react-router-dom";
 
+import { conf } from "@/lib/conf";
 import { useNotifications } from "@/lib/hooks/use-notifications";
 
 import { AppIndexPage } from "./app/app-index-page/app-index-page";
@@ -32,7 +33,10 @@ export function AuthenticatedApp() {
 if ("serviceWorker" in navigator) {
 	(async () => {
 		console.debug("registering service worker...");
-		await navigator.serviceWorker.register("/sw.js");
+		// the service worker calls the api for notification actions
+		await navigator.serviceWorker.register(
+			`/sw.js?api=${encodeURIComponent(conf.API_URL)}`
+		);
 		console.debug("service worker registered");
 
 		await navigator.serviceWorker.ready;
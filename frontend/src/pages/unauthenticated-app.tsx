@@ -9,6 +9,7 @@ export function UnauthenticatedApp() {
 			<Route path="/auth">
 				<Route path="login" element={<LoginPage />} />
 				<Route path="callback" element={<CallbackPage />} />
+				<Route path="callback/:providerId" element={<CallbackPage />} />
 			</Route>
 
 			<Route path="*" element={<Navigate to="/auth/login" />} />